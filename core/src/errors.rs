@@ -10,4 +10,15 @@ pub enum CoreError {
     FailedToGetAccountFromCluster,
     #[error("Failed to parse account data")]
     FailedToParseDelegationRecord(String),
+
+    #[error("StdIoError")]
+    StdIoError(#[from] std::io::Error),
+    #[error("SerdeJSONError")]
+    SerdeJSONError(#[from] serde_json::Error),
+    #[error("TomlError")]
+    TomlError(#[from] toml::de::Error),
+    #[error("Invalid routing rule {idx}: {reason}")]
+    InvalidRoutingRule { idx: usize, reason: String },
+    #[error("Routing rules need to be a .toml or .json file: {0}")]
+    UnsupportedRoutingRulesFile(String),
 }
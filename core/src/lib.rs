@@ -2,6 +2,7 @@ pub mod delegation_inconsistency;
 pub mod delegation_record;
 pub mod delegation_record_parser;
 pub mod errors;
+pub mod routing_rules;
 mod traits;
 mod types;
 
@@ -0,0 +1,188 @@
+use std::{fmt, fs, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    errors::{CoreError, CoreResult},
+    GuideStrategy, RequestEndpoint,
+};
+
+/// Where requests matching a rule are sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Route {
+    Chain,
+    Ephemeral,
+    Both,
+    /// Only sent to chain if the ephemeral validator fails or has no result,
+    /// subscriptions are confirmed even if there is nothing to notify about
+    EphemeralThenChain,
+    /// Only sent to the ephemeral validator if chain fails or has no result,
+    /// subscriptions are confirmed even if there is nothing to notify about
+    ChainThenEphemeral,
+    /// Use the built-in endpoint of the method, i.e. to exempt a specific
+    /// param from a rule that applies to the whole method
+    BuiltIn,
+}
+
+impl Route {
+    /// The strategy of the route, `None` for the built-in one
+    pub fn guide_strategy(&self) -> Option<GuideStrategy> {
+        match self {
+            Route::Chain => Some(GuideStrategy::Chain),
+            Route::Ephemeral => Some(GuideStrategy::Ephemeral),
+            Route::Both => Some(GuideStrategy::Both),
+            Route::EphemeralThenChain => {
+                Some(GuideStrategy::EphemeralThenChain)
+            }
+            Route::ChainThenEphemeral => {
+                Some(GuideStrategy::ChainThenEphemeral)
+            }
+            Route::BuiltIn => None,
+        }
+    }
+
+    /// The endpoint of the route, `None` for the built-in one
+    pub fn endpoint(&self) -> Option<RequestEndpoint> {
+        match self {
+            Route::Chain => Some(RequestEndpoint::Chain),
+            Route::Ephemeral => Some(RequestEndpoint::Ephemeral),
+            Route::Both => Some(RequestEndpoint::Both),
+            Route::EphemeralThenChain => {
+                Some(RequestEndpoint::EphemeralThenChain)
+            }
+            Route::ChainThenEphemeral => {
+                Some(RequestEndpoint::ChainThenEphemeral)
+            }
+            Route::BuiltIn => None,
+        }
+    }
+}
+
+/// Methods that routing rules can target, each director restricts the
+/// methods, params and routes it supports
+pub trait RoutableMethod: PartialEq + fmt::Debug {
+    /// Checks that a rule may route the method with the given param
+    fn validate_rule(
+        &self,
+        param: Option<&str>,
+        route: Route,
+    ) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RoutingRule<M> {
+    pub method: M,
+    /// Only matches requests with this param, i.e. an address
+    #[serde(default)]
+    pub param: Option<String>,
+    pub route: Route,
+}
+
+impl<M: PartialEq> RoutingRule<M> {
+    fn matches<Q: ?Sized>(&self, method: &Q, params: &[&str]) -> bool
+    where
+        M: PartialEq<Q>,
+    {
+        if self.method != *method {
+            return false;
+        }
+        match &self.param {
+            Some(param) => params.contains(&param.as_str()),
+            None => true,
+        }
+    }
+
+    /// True if every request matching the other rule also matches this one
+    fn shadows(&self, other: &RoutingRule<M>) -> bool {
+        self.method == other.method
+            && (self.param.is_none() || self.param == other.param)
+    }
+}
+
+/// Overrides the built-in endpoints of methods, the first rule matching a
+/// request decides where it goes.
+/// Loaded from JSON, i.e. `{ "rules": [{ "method": "<method>",
+/// "route": "ephemeral" }] }` or the equivalent TOML.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RoutingRules<M> {
+    #[serde(default)]
+    pub rules: Vec<RoutingRule<M>>,
+}
+
+impl<M> Default for RoutingRules<M> {
+    fn default() -> Self {
+        Self { rules: vec![] }
+    }
+}
+
+impl<M: RoutableMethod + DeserializeOwned> RoutingRules<M> {
+    pub fn from_json_str(json: &str) -> CoreResult<Self> {
+        let routing_rules: Self = serde_json::from_str(json)?;
+        routing_rules.validate()?;
+        Ok(routing_rules)
+    }
+
+    pub fn from_toml_str(toml: &str) -> CoreResult<Self> {
+        let routing_rules: Self = toml::from_str(toml)?;
+        routing_rules.validate()?;
+        Ok(routing_rules)
+    }
+
+    /// Loads the rules from a `.toml` or `.json` file
+    pub fn from_file(path: impl AsRef<Path>) -> CoreResult<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&content),
+            Some("json") => Self::from_json_str(&content),
+            _ => Err(CoreError::UnsupportedRoutingRulesFile(
+                path.display().to_string(),
+            )),
+        }
+    }
+}
+
+impl<M: RoutableMethod> RoutingRules<M> {
+    /// Ensures that every rule is supported by its method and that no rule
+    /// is unreachable due to a previous one
+    pub fn validate(&self) -> CoreResult<()> {
+        for (idx, rule) in self.rules.iter().enumerate() {
+            rule.method
+                .validate_rule(rule.param.as_deref(), rule.route)
+                .map_err(|reason| CoreError::InvalidRoutingRule {
+                    idx,
+                    reason,
+                })?;
+            if let Some(shadowing_idx) = self.rules[..idx]
+                .iter()
+                .position(|previous| previous.shadows(rule))
+            {
+                return Err(CoreError::InvalidRoutingRule {
+                    idx,
+                    reason: format!(
+                        "unreachable since rule {shadowing_idx} matches all its requests"
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The route of the first rule matching the request
+    pub fn route_of<Q: ?Sized>(
+        &self,
+        method: &Q,
+        params: &[&str],
+    ) -> Option<Route>
+    where
+        M: PartialEq<Q>,
+    {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(method, params))
+            .map(|rule| rule.route)
+    }
+}
@@ -9,11 +9,23 @@ pub enum GuideStrategy {
     Ephemeral,
     /// Forward to both chain and ephemeral
     Both,
+    /// Forward to ephemeral and only to chain if ephemeral fails or has no
+    /// result
+    EphemeralThenChain,
+    /// Forward to chain and only to ephemeral if chain fails or has no result
+    ChainThenEphemeral,
     /// Forward to ephemeral if that validator has the account of given address,
     /// otherwise forward to chain
     /// - *param.0*: address
     /// - *param.1*: is_subscription
     TryEphemeralForAccount(String, bool),
+    /// Forward to ephemeral if that validator has all accounts of given
+    /// addresses, to chain if it has none of them (to both for subscriptions
+    /// since the accounts may be created after the request) and to both if it
+    /// has only some of them
+    /// - *param.0*: addresses
+    /// - *param.1*: is_subscription
+    TryEphemeralForAccounts(Vec<String>, bool),
     /// Forward to ephemeral if that validator has the program of given address,
     /// otherwise forward to chain
     /// - *param.0*: program_id
@@ -40,4 +52,11 @@ pub enum RequestEndpoint {
     Ephemeral,
     /// Forward to both chain and ephemeral
     Both,
+    /// Forward to ephemeral first and to chain only if ephemeral fails or
+    /// has no result, i.e. for single reads that return `null` for unknown
+    /// accounts
+    EphemeralThenChain,
+    /// Forward to chain first and to ephemeral only if chain fails or has no
+    /// result
+    ChainThenEphemeral,
 }
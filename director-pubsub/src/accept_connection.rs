@@ -3,14 +3,25 @@ use std::sync::Arc;
 use conjunto_core::{
     AccountProvider, RequestEndpoint, SignatureStatusProvider,
 };
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{
+    future,
+    stream::{self, select_all},
+    SinkExt, StreamExt,
+};
 use log::*;
 use tokio::net::TcpStream;
 use tokio_tungstenite::tungstenite::Message;
 
 use crate::{
-    director::DirectorPubsub, errors::DirectorPubsubResult, BackendWebSocket,
-    BackendWebSocketWriter,
+    director::{DirectorPubsub, EphemeralTarget},
+    errors::DirectorPubsubResult,
+    messages::{
+        error_response, JSON_RPC_INTERNAL_ERROR, JSON_RPC_INVALID_PARAMS,
+        JSON_RPC_INVALID_REQUEST,
+    },
+    sequential_requests::{Backend, SequentialRequests},
+    subscriptions::Subscriptions,
+    BackendWebSocket, BackendWebSocketWriter,
 };
 
 pub(crate) async fn accept_connection<
@@ -19,7 +30,7 @@ pub(crate) async fn accept_connection<
 >(
     director: Arc<DirectorPubsub<T, U>>,
     chain_socket: BackendWebSocket,
-    ephem_socket: BackendWebSocket,
+    ephem_sockets: Vec<Option<BackendWebSocket>>,
     incoming_stream: TcpStream,
 ) -> DirectorPubsubResult<()> {
     let addr = incoming_stream.peer_addr()?;
@@ -30,19 +41,55 @@ pub(crate) async fn accept_connection<
 
     let (mut write_client, mut read_client) = client_stream.split();
     let (mut write_chain, mut read_chain) = chain_socket.split();
-    let (mut write_ephem, mut read_ephem) = ephem_socket.split();
+    let mut write_ephems = EphemeralWriters::default();
+    let mut read_ephems = Vec::new();
+    for (idx, socket) in ephem_sockets.into_iter().enumerate() {
+        match socket {
+            Some(socket) => {
+                let (write, read) = socket.split();
+                write_ephems.0.push(Some(write));
+                read_ephems.push((idx, read));
+            }
+            None => write_ephems.0.push(None),
+        }
+    }
+    // Messages of all connected ephemeral validators are tagged with the index
+    // of the validator they came from, `None` signals that its stream ended
+    let mut read_ephem =
+        select_all(read_ephems.into_iter().map(|(idx, read)| {
+            read.map(move |msg| (idx, Some(msg)))
+                .chain(stream::once(future::ready((idx, None))))
+        }));
 
     tokio::spawn(async move {
-        loop {
+        let mut sequential_requests = SequentialRequests::default();
+        let mut subscriptions = Subscriptions::default();
+        'connection: loop {
             tokio::select! {
                 // We pipe both chain and ephemeral messages to the client
                 next = read_chain.next() => {
                     match next {
                         Some(Ok(msg)) => {
                             trace!("Chain message: {:?}", msg);
+                            if let Some((Backend::Ephemeral(idx), request)) = sequential_requests.take_retry(Backend::Chain, &msg) {
+                                trace!("Chain has no result, sending message to ephemeral: {:?}", request);
+                                if !write_ephems.send(EphemeralTarget::Validator(idx), request).await {
+                                    // Nobody else can answer, so the client gets the empty response
+                                    if let Err(err) = write_client.send(msg).await {
+                                        error!("Error sending message to client: {:?}", err);
+                                        break;
+                                    }
+                                }
+                                continue;
+                            }
                             let res = handle_downstream_msg(&mut write_chain, &msg).await;
                             if res.fwd_to_client {
-                                write_client.send(msg).await.unwrap();
+                                if let Some(msg) = subscriptions.forward_response(Backend::Chain, msg) {
+                                    if let Err(err) = write_client.send(msg).await {
+                                        error!("Error sending message to client: {:?}", err);
+                                        break;
+                                    }
+                                }
                             }
                             if res.done {
                                 break;
@@ -54,30 +101,53 @@ pub(crate) async fn accept_connection<
                             trace!("Error reading chain message: {:?}", msg);
                         }
                         None => {
-                            // If either downstream disconnects we need to make the client
+                            // If the chain disconnects we need to make the client
                             // aware and thus disconnect ourselves as well
                             break;
                         }
                     }
                 }
-                next = read_ephem.next() => {
+                next = read_ephem.next(), if !read_ephem.is_empty() => {
                     match next {
-                        Some(Ok(msg)) => {
-                            trace!("Ephem message: {:?}", msg);
-                            let res = handle_downstream_msg(&mut write_ephem, &msg).await;
+                        Some((idx, Some(Ok(msg)))) => {
+                            trace!("Ephem message from validator {}: {:?}", idx, msg);
+                            if let Some((_, request)) = sequential_requests.take_retry(Backend::Ephemeral(idx), &msg) {
+                                trace!("Ephem has no result, sending message to chain: {:?}", request);
+                                if let Err(err) = write_chain.send(request).await {
+                                    error!("Error sending message to chain: {:?}", err);
+                                    break;
+                                }
+                                continue;
+                            }
+                            let res = match write_ephems.get_mut(idx) {
+                                Some(writer) => handle_downstream_msg(writer, &msg).await,
+                                None => HandleDownstreamMsgResult::not_done_fwd(),
+                            };
                             if res.fwd_to_client {
-                                write_client.send(msg).await.unwrap();
+                                if let Some(signature) = subscriptions.landed_signature(Backend::Ephemeral(idx), &msg) {
+                                    director.signature_landed(&signature);
+                                }
+                                if let Some(msg) = subscriptions.forward_response(Backend::Ephemeral(idx), msg) {
+                                    if let Err(err) = write_client.send(msg).await {
+                                        error!("Error sending message to client: {:?}", err);
+                                        break;
+                                    }
+                                }
                             }
+                            // Only this validator is done, the client keeps being
+                            // served by the others
                             if res.done {
-                                break;
+                                write_ephems.disconnect(idx);
                             }
                         }
-                        Some(Err(msg)) => {
-                            trace!("Error reading ephem message: {:?}", msg);
+                        Some((idx, Some(Err(msg)))) => {
+                            trace!("Error reading ephem message from validator {}: {:?}", idx, msg);
                         }
-                        None => {
-                            break;
+                        Some((idx, None)) => {
+                            warn!("Ephemeral validator {} disconnected", idx);
+                            write_ephems.disconnect(idx);
                         }
+                        None => {}
                     }
                 }
                 // For client messages we decide by message content if to send it
@@ -87,22 +157,121 @@ pub(crate) async fn accept_connection<
                         Some(Ok(msg)) => {
                             trace!("Client message: {:?}", msg);
                             use RequestEndpoint::*;
+                            // Unsubscribes go to the backends that confirmed the subscription
+                            if let Some(requests) = subscriptions.take_unsubscribe_requests(&msg) {
+                                let mut sent = 0;
+                                for (backend, request) in requests {
+                                    trace!("Sending unsubscribe to {:?}: {:?}", backend, request);
+                                    match backend {
+                                        Backend::Chain => {
+                                            if let Err(err) = write_chain.send(request).await {
+                                                error!("Error sending message to chain: {:?}", err);
+                                                break 'connection;
+                                            }
+                                            sent += 1;
+                                        }
+                                        Backend::Ephemeral(idx) => {
+                                            if write_ephems.send(EphemeralTarget::Validator(idx), request).await {
+                                                sent += 1;
+                                            }
+                                        }
+                                    }
+                                }
+                                if sent == 0 {
+                                    if let Err(err) = write_client.send(unknown_subscription_response(&msg)).await {
+                                        error!("Error sending message to client: {:?}", err);
+                                        break;
+                                    }
+                                }
+                                subscriptions.expect_responses(&msg, sent);
+                                continue;
+                            }
+                            subscriptions.track_request(&msg);
                             match director.guide_msg(&msg).await {
-                                Some(Chain) => {
+                                Ok(Some((Chain, _))) => {
                                     trace!("Sending message to chain: {:?}", msg);
-                                    write_chain.send(msg).await.unwrap()
+                                    if let Err(err) = write_chain.send(msg).await {
+                                        error!("Error sending message to chain: {:?}", err);
+                                        break;
+                                    }
                                 },
-                                Some(Ephemeral) => {
-                                    trace!("Sending message to ephemeral: {:?}", msg);
-                                    write_ephem.send(msg).await.unwrap();
+                                Ok(Some((Ephemeral, target))) => {
+                                    trace!("Sending message to ephemeral {:?}: {:?}", target, msg);
+                                    if !write_ephems.send(target, msg.clone()).await {
+                                        let response = unreachable_validator_response(&msg, target);
+                                        if let Err(err) = write_client.send(response).await {
+                                            error!("Error sending message to client: {:?}", err);
+                                            break;
+                                        }
+                                    }
+                                }
+                                Ok(Some((Both, target))) => {
+                                    trace!("Sending message to chain and ephemeral {:?}: {:?}", target, msg);
+                                    if write_ephems.send(target, msg.clone()).await {
+                                        subscriptions.expect_responses(&msg, 2);
+                                    }
+                                    if let Err(err) = write_chain.send(msg).await {
+                                        error!("Error sending message to chain: {:?}", err);
+                                        break;
+                                    }
                                 }
-                                Some(Both) => {
-                                    trace!("Sending message to chain and ephemeral: {:?}", msg);
-                                    write_chain.send(msg.clone()).await.unwrap();
-                                    write_ephem.send(msg).await.unwrap();
+                                Ok(Some((endpoint @ (EphemeralThenChain | ChainThenEphemeral), target))) => {
+                                    let validator = match target {
+                                        EphemeralTarget::Validator(idx) => idx,
+                                        EphemeralTarget::All => 0,
+                                    };
+                                    let (first, second) = Backend::sequence_of(&endpoint, validator)
+                                        .expect("endpoint is sequential");
+                                    if !sequential_requests.insert(&msg, first, second) {
+                                        // We could not tell the responses of both requests apart
+                                        let response = pending_id_response(&msg);
+                                        if let Err(err) = write_client.send(response).await {
+                                            error!("Error sending message to client: {:?}", err);
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                    trace!("Sending message to {:?} first: {:?}", first, msg);
+                                    match first {
+                                        Backend::Chain => {
+                                            if let Err(err) = write_chain.send(msg).await {
+                                                error!("Error sending message to chain: {:?}", err);
+                                                break;
+                                            }
+                                        }
+                                        Backend::Ephemeral(idx) => {
+                                            if !write_ephems.send(EphemeralTarget::Validator(idx), msg.clone()).await {
+                                                // The ephemeral validator cannot answer, so we
+                                                // only ask chain
+                                                sequential_requests.remove(&msg);
+                                                if let Err(err) = write_chain.send(msg).await {
+                                                    error!("Error sending message to chain: {:?}", err);
+                                                    break;
+                                                }
+                                            }
+                                        }
+                                    }
                                 }
                                 // If client sends a "close" message we return None as endpoint
-                                None => break
+                                Ok(None) => break,
+                                // The fallback policy says to let the client know that
+                                // we could not figure out where the message should go
+                                Err(err) => {
+                                    warn!("Failed to guide message: {:?}", err);
+                                    let request = msg.to_text().unwrap_or_default();
+                                    let response = error_response(
+                                        request,
+                                        JSON_RPC_INTERNAL_ERROR,
+                                        &format!("Failed to guide request: {}", err),
+                                    );
+                                    if let Err(err) = write_client
+                                        .send(Message::Text(response))
+                                        .await
+                                    {
+                                        error!("Error sending guide error to client: {:?}", err);
+                                        break;
+                                    }
+                                }
                             }
                         }
                         Some(Err(err)) => {
@@ -121,6 +290,77 @@ pub(crate) async fn accept_connection<
     Ok(())
 }
 
+/// Write halves of the ephemeral validator sockets by validator index, `None`
+/// if the validator is unreachable or disconnected
+#[derive(Default)]
+struct EphemeralWriters(Vec<Option<BackendWebSocketWriter>>);
+
+impl EphemeralWriters {
+    /// Sends the message to the targeted validators, returns `false` if none of
+    /// them is connected
+    async fn send(&mut self, target: EphemeralTarget, msg: Message) -> bool {
+        let mut sent = false;
+        for (idx, writer) in self.0.iter_mut().enumerate() {
+            if !target.includes(idx) {
+                continue;
+            }
+            if let Some(ws) = writer {
+                match ws.send(msg.clone()).await {
+                    Ok(()) => sent = true,
+                    Err(err) => {
+                        warn!(
+                            "Failed to send to ephemeral validator {}: {:?}",
+                            idx, err
+                        );
+                        *writer = None;
+                    }
+                }
+            }
+        }
+        sent
+    }
+
+    fn get_mut(&mut self, idx: usize) -> Option<&mut BackendWebSocketWriter> {
+        self.0.get_mut(idx).and_then(Option::as_mut)
+    }
+
+    fn disconnect(&mut self, idx: usize) {
+        if let Some(writer) = self.0.get_mut(idx) {
+            *writer = None;
+        }
+    }
+}
+
+fn unreachable_validator_response(
+    request: &Message,
+    target: EphemeralTarget,
+) -> Message {
+    let response = error_response(
+        request.to_text().unwrap_or_default(),
+        JSON_RPC_INTERNAL_ERROR,
+        &format!("Ephemeral validator {:?} is not connected", target),
+    );
+    Message::Text(response)
+}
+
+fn unknown_subscription_response(request: &Message) -> Message {
+    let response = error_response(
+        request.to_text().unwrap_or_default(),
+        JSON_RPC_INVALID_PARAMS,
+        "Invalid subscription id",
+    );
+    Message::Text(response)
+}
+
+fn pending_id_response(request: &Message) -> Message {
+    let response = error_response(
+        request.to_text().unwrap_or_default(),
+        JSON_RPC_INVALID_REQUEST,
+        "A request with the same id is still pending",
+    );
+    Message::Text(response)
+}
+
 struct HandleDownstreamMsgResult {
     done: bool,
     fwd_to_client: bool,
@@ -166,3 +406,276 @@ async fn handle_downstream_msg(
         Message::Frame(_frame) => HandleDownstreamMsgResult::not_done_fwd(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use conjunto_test_tools::{
+        account_provider_stub::AccountProviderStub,
+        signature_status_provider_stub::SignatureStatusProviderStub,
+    };
+    use serde_json::Value;
+    use tokio::{
+        net::TcpListener,
+        sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    };
+    use tokio_tungstenite::{accept_async, connect_async};
+    use url::Url;
+
+    use super::*;
+    use crate::{
+        director::DirectorPubsubConfig,
+        routing_rules::{Route, RoutingRule, RoutingRules},
+        ClientSubMethod,
+    };
+
+    type Received = UnboundedSender<(Backend, Value)>;
+
+    async fn connect(addr: std::net::SocketAddr) -> BackendWebSocket {
+        let url = Url::parse(&format!("ws://{}", addr)).unwrap();
+        let (socket, _) = connect_async(url).await.unwrap();
+        socket
+    }
+
+    /// Records the requests it receives and answers them with the response
+    /// built from the request, if any
+    async fn mock_backend(
+        backend: Backend,
+        received: Received,
+        respond: fn(&Value) -> Option<Value>,
+    ) -> BackendWebSocket {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(stream).await.unwrap();
+            while let Some(Ok(Message::Text(request))) = ws.next().await {
+                let request: Value = serde_json::from_str(&request).unwrap();
+                received.send((backend, request.clone())).unwrap();
+                if let Some(response) = respond(&request) {
+                    ws.send(Message::Text(response.to_string())).await.unwrap();
+                }
+            }
+        });
+        connect(addr).await
+    }
+
+    async fn connect_client(
+        chain: BackendWebSocket,
+        ephem: BackendWebSocket,
+    ) -> BackendWebSocket {
+        connect_client_with_rule(
+            chain,
+            ephem,
+            ClientSubMethod::Ping,
+            Route::EphemeralThenChain,
+        )
+        .await
+    }
+
+    async fn connect_client_with_rule(
+        chain: BackendWebSocket,
+        ephem: BackendWebSocket,
+        method: ClientSubMethod,
+        route: Route,
+    ) -> BackendWebSocket {
+        let config = DirectorPubsubConfig {
+            guide_cache_config: None,
+            routing_rules: RoutingRules {
+                rules: vec![RoutingRule {
+                    method,
+                    param: None,
+                    route,
+                }],
+            },
+            ..DirectorPubsubConfig::devnet()
+        };
+        let director = Arc::new(DirectorPubsub::with_providers(
+            config,
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            accept_connection(director, chain, vec![Some(ephem)], stream)
+                .await
+                .unwrap();
+        });
+        connect(addr).await
+    }
+
+    fn ping(id: u64) -> Message {
+        Message::Text(
+            serde_json::json! {{ "jsonrpc": "2.0", "id": id, "method": "ping" }}
+                .to_string(),
+        )
+    }
+
+    async fn next_json(client: &mut BackendWebSocket) -> Value {
+        let msg = client.next().await.unwrap().unwrap();
+        serde_json::from_str(msg.to_text().unwrap()).unwrap()
+    }
+
+    async fn next_received(
+        received: &mut UnboundedReceiver<(Backend, Value)>,
+    ) -> (Backend, Value) {
+        received.recv().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_then_chain_retries_on_chain() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let chain = mock_backend(Backend::Chain, sender.clone(), |request| {
+            Some(serde_json::json! {{
+                "jsonrpc": "2.0", "id": request["id"], "result": "pong"
+            }})
+        })
+        .await;
+        let ephem = mock_backend(Backend::Ephemeral(0), sender, |request| {
+            Some(serde_json::json! {{
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": -32601, "message": "Method not found" }
+            }})
+        })
+        .await;
+        let mut client = connect_client(chain, ephem).await;
+
+        client.send(ping(1)).await.unwrap();
+
+        // The client only sees the chain response
+        let response = next_json(&mut client).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"], "pong");
+        assert_eq!(next_received(&mut received).await.0, Backend::Ephemeral(0));
+        assert_eq!(next_received(&mut received).await.0, Backend::Chain);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_rejected_by_ephemeral_is_owned_by_chain() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let chain = mock_backend(Backend::Chain, sender.clone(), |request| {
+            let result = match request["method"].as_str() {
+                Some("accountSubscribe") => Value::from(7),
+                _ => Value::from(true),
+            };
+            Some(serde_json::json! {{
+                "jsonrpc": "2.0", "id": request["id"], "result": result
+            }})
+        })
+        .await;
+        let ephem = mock_backend(Backend::Ephemeral(0), sender, |request| {
+            Some(serde_json::json! {{
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": -32601, "message": "Method not found" }
+            }})
+        })
+        .await;
+        let mut client = connect_client_with_rule(
+            chain,
+            ephem,
+            ClientSubMethod::AccountSubscribe,
+            Route::EphemeralThenChain,
+        )
+        .await;
+
+        client
+            .send(Message::Text(
+                serde_json::json! {{
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "accountSubscribe",
+                    "params": ["SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ"]
+                }}
+                .to_string(),
+            ))
+            .await
+            .unwrap();
+
+        // The client only sees the chain confirmation with its own id
+        let response = next_json(&mut client).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"], 0);
+        assert_eq!(next_received(&mut received).await.0, Backend::Ephemeral(0));
+        assert_eq!(next_received(&mut received).await.0, Backend::Chain);
+
+        // Only chain owns the subscription
+        client
+            .send(Message::Text(
+                serde_json::json! {{
+                    "jsonrpc": "2.0",
+                    "id": 2,
+                    "method": "accountUnsubscribe",
+                    "params": [0]
+                }}
+                .to_string(),
+            ))
+            .await
+            .unwrap();
+        let response = next_json(&mut client).await;
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["result"], true);
+        let (backend, request) = next_received(&mut received).await;
+        assert_eq!(backend, Backend::Chain);
+        assert_eq!(request["params"][0], 7);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_confirmed_by_ephemeral_is_not_sent_to_chain() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let chain =
+            mock_backend(Backend::Chain, sender.clone(), |_| None).await;
+        let ephem = mock_backend(Backend::Ephemeral(0), sender, |request| {
+            Some(serde_json::json! {{
+                "jsonrpc": "2.0", "id": request["id"], "result": 3
+            }})
+        })
+        .await;
+        let mut client = connect_client_with_rule(
+            chain,
+            ephem,
+            ClientSubMethod::SlotSubscribe,
+            Route::EphemeralThenChain,
+        )
+        .await;
+
+        client
+            .send(Message::Text(
+                serde_json::json! {{
+                    "jsonrpc": "2.0", "id": 1, "method": "slotSubscribe"
+                }}
+                .to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let response = next_json(&mut client).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"], 0);
+        assert_eq!(next_received(&mut received).await.0, Backend::Ephemeral(0));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reused_pending_id_is_rejected() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let chain =
+            mock_backend(Backend::Chain, sender.clone(), |_| None).await;
+        // Keeps the first request pending
+        let ephem = mock_backend(Backend::Ephemeral(0), sender, |_| None).await;
+        let mut client = connect_client(chain, ephem).await;
+
+        client.send(ping(1)).await.unwrap();
+        client.send(ping(1)).await.unwrap();
+
+        let response = next_json(&mut client).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["error"]["code"], JSON_RPC_INVALID_REQUEST);
+        assert_eq!(next_received(&mut received).await.0, Backend::Ephemeral(0));
+        assert!(received.try_recv().is_err());
+    }
+}
@@ -2,24 +2,56 @@ use conjunto_addresses::cluster::RpcCluster;
 use conjunto_core::{
     AccountProvider, RequestEndpoint, SignatureStatusProvider,
 };
-use conjunto_guidepoint::GuideStrategyResolver;
+use conjunto_guidepoint::{
+    AccountResolutionMode, FallbackPolicy, GuideCacheConfig,
+    GuideStrategyResolver,
+};
+use conjunto_lockbox::{
+    account_chain_snapshot_provider::AccountChainSnapshotProvider,
+    delegation_record_parser_impl::DelegationRecordParserImpl,
+};
 use conjunto_providers::{
     rpc_account_provider::RpcAccountProvider,
     rpc_provider_config::RpcProviderConfig,
     rpc_signature_status_provider::RpcSignatureStatusProvider,
+    validator_registry::ValidatorRegistry,
 };
 use log::*;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use url::Url;
 
 use crate::{
-    errors::DirectorPubsubResult,
-    guide_strategy::guide_strategy_from_pubsub_msg, BackendWebSocket,
+    errors::{DirectorPubsubError, DirectorPubsubResult},
+    guide_strategy::guide_strategy_from_pubsub_msg,
+    routing_rules::RoutingRules,
+    BackendWebSocket,
 };
 
 pub struct DirectorPubsubConfig {
     pub chain_cluster: RpcCluster,
     pub ephem_rpc_provider_config: RpcProviderConfig,
+    /// Authority of the default ephemeral validator, requests for accounts
+    /// delegated to it are sent there. If not set it receives them as long
+    /// as the validator registry is empty
+    pub ephem_authority: Option<Pubkey>,
+    /// Additional ephemeral validators, requests for accounts delegated to
+    /// them or only found in them are sent to that validator
+    pub validator_registry: ValidatorRegistry,
+    /// How account requests are guided, modes other than the default look up
+    /// the delegation state on the chain cluster
+    pub account_resolution_mode: AccountResolutionMode,
+    /// Caches ephemeral lookups when set, i.e. so resubscribing clients don't
+    /// hit the ephemeral validator for each account again. Chain delegation
+    /// lookups are never cached, so it has no effect with the
+    /// `ChainDelegation` account resolution mode
+    pub guide_cache_config: Option<GuideCacheConfig>,
+    /// Where messages go when looking up their account, program or signature
+    /// fails, the client receives an error if the policy says so
+    pub fallback_policy: FallbackPolicy,
+    /// Override the built-in strategies of the methods, validated when the
+    /// server starts
+    pub routing_rules: RoutingRules,
 }
 
 impl DirectorPubsubConfig {
@@ -27,6 +59,31 @@ impl DirectorPubsubConfig {
         Self {
             chain_cluster: RpcCluster::Devnet,
             ephem_rpc_provider_config: RpcProviderConfig::magicblock_devnet(),
+            ephem_authority: None,
+            validator_registry: ValidatorRegistry::default(),
+            account_resolution_mode: AccountResolutionMode::default(),
+            guide_cache_config: Some(GuideCacheConfig::default()),
+            fallback_policy: FallbackPolicy::default(),
+            routing_rules: RoutingRules::default(),
+        }
+    }
+}
+
+/// The ephemeral validators a message is sent to if its endpoint includes
+/// the ephemeral one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EphemeralTarget {
+    /// Control messages like close and ping go to all of them
+    All,
+    /// Index of the validator in [DirectorPubsub::ephemeral_ws_urls]
+    Validator(usize),
+}
+
+impl EphemeralTarget {
+    pub fn includes(&self, idx: usize) -> bool {
+        match self {
+            EphemeralTarget::All => true,
+            EphemeralTarget::Validator(validator) => *validator == idx,
         }
     }
 }
@@ -58,20 +115,66 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
         ephemeral_account_provider: T,
         ephemeral_signature_status_provider: U,
     ) -> Self {
-        let guide_strategy_resolver = GuideStrategyResolver::new(
+        let mut guide_strategy_resolver = GuideStrategyResolver::new(
             ephemeral_account_provider,
             ephemeral_signature_status_provider,
-        );
+        )
+        .with_fallback_policy(config.fallback_policy);
+        // Accounts missing in the default validator may live in any of the
+        // registered ones, validators sharing its URL hold the same accounts
+        let default_ws_url = config.ephem_rpc_provider_config.ws_url();
+        let mut registered_validators = config
+            .validator_registry
+            .iter()
+            .filter(|(_, provider_config)| {
+                provider_config.ws_url() != default_ws_url
+            })
+            .collect::<Vec<_>>();
+        // Registry iteration order is arbitrary
+        registered_validators.sort_by_key(|(authority, _)| **authority);
+        for (authority, provider_config) in registered_validators {
+            guide_strategy_resolver = guide_strategy_resolver
+                .with_validator_account_provider(
+                    *authority,
+                    RpcAccountProvider::new(provider_config.clone()),
+                );
+        }
+        if config.account_resolution_mode
+            != AccountResolutionMode::EphemeralAccount
+        {
+            let chain_state_provider = AccountChainSnapshotProvider::new(
+                RpcAccountProvider::new(RpcProviderConfig::new(
+                    config.chain_cluster.clone(),
+                    None,
+                )),
+                DelegationRecordParserImpl,
+            );
+            guide_strategy_resolver = guide_strategy_resolver
+                .with_account_resolution_mode(
+                    config.account_resolution_mode,
+                    chain_state_provider,
+                );
+        }
+        if let Some(guide_cache_config) = config.guide_cache_config {
+            guide_strategy_resolver =
+                guide_strategy_resolver.with_cache(guide_cache_config);
+        }
         Self {
             config,
             guide_strategy_resolver,
         }
     }
 
+    /// Forgets cached lookups that are outdated once the transaction of the
+    /// signature landed in the ephemeral validator
+    pub(crate) fn signature_landed(&self, signature: &Signature) {
+        self.guide_strategy_resolver.signature_landed(signature);
+    }
+
     pub(super) async fn guide_msg(
         &self,
         msg: &Message,
-    ) -> Option<RequestEndpoint> {
+    ) -> DirectorPubsubResult<Option<(RequestEndpoint, EphemeralTarget)>> {
         use Message::*;
         let msg = match msg {
             Text(txt) => txt,
@@ -79,22 +182,65 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
             // for both endpoints to get the proper response from at least one
             Close(code) => {
                 debug!("Close client: {:?}", code);
-                return Some(RequestEndpoint::Both);
+                return Ok(Some((RequestEndpoint::Both, EphemeralTarget::All)));
             }
             // We don't know which chain the ping/pong msg is responding to
             // at this point, so we send to both
-            Ping(_) => return Some(RequestEndpoint::Both),
-            Pong(_) => return Some(RequestEndpoint::Both),
+            Ping(_) | Pong(_) => {
+                return Ok(Some((RequestEndpoint::Both, EphemeralTarget::All)))
+            }
 
             // If in doubt just pass on to chain
-            Binary(_) => return Some(RequestEndpoint::Chain),
-            Frame(_) => return Some(RequestEndpoint::Chain),
+            Binary(_) | Frame(_) => {
+                return Ok(Some((
+                    RequestEndpoint::Chain,
+                    EphemeralTarget::Validator(0),
+                )))
+            }
         };
-        let strategy = guide_strategy_from_pubsub_msg(msg.as_str());
-        let endpoint = self.guide_strategy_resolver.resolve(&strategy).await;
+        let strategy = guide_strategy_from_pubsub_msg(
+            msg.as_str(),
+            &self.config.routing_rules,
+        );
+        let (endpoint, authority) = self
+            .guide_strategy_resolver
+            .resolve_with_authority(&strategy)
+            .await?;
+        let validator = self.ephemeral_validator_of(authority.as_ref())?;
         trace!("Message '{}", msg);
-        debug!("Guiding message to: {:?}", endpoint);
-        Some(endpoint)
+        debug!(
+            "Guiding message to: {:?} (ephemeral validator {})",
+            endpoint, validator
+        );
+        Ok(Some((endpoint, EphemeralTarget::Validator(validator))))
+    }
+
+    /// Index of the validator with the given authority in
+    /// [Self::ephemeral_ws_urls], the default validator if we don't know the
+    /// authority
+    pub(crate) fn ephemeral_validator_of(
+        &self,
+        authority: Option<&Pubkey>,
+    ) -> DirectorPubsubResult<usize> {
+        let authority = match authority {
+            Some(authority) => authority,
+            None => return Ok(0),
+        };
+        if let Some(config) = self.config.validator_registry.get(authority) {
+            let ws_url = config.ws_url();
+            return Ok(self
+                .ephemeral_ws_urls()
+                .iter()
+                .position(|url| *url == ws_url)
+                .expect("registered validators are part of the ws urls"));
+        }
+        match &self.config.ephem_authority {
+            Some(ephem_authority) if ephem_authority == authority => Ok(0),
+            // Without any authorities configured the default validator is
+            // the only one we can send to
+            None if self.config.validator_registry.is_empty() => Ok(0),
+            _ => Err(DirectorPubsubError::UnknownValidator(*authority)),
+        }
     }
 
     pub async fn try_chain_client(
@@ -112,6 +258,53 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
         let (socket, _) = connect_async(Url::parse(url)?).await?;
         Ok(socket)
     }
+
+    /// Connects to the default ephemeral validator and all validators of the
+    /// registry, validators that cannot be reached are `None` so the others
+    /// keep their index of [Self::ephemeral_ws_urls]
+    pub async fn try_ephemeral_clients(&self) -> Vec<Option<BackendWebSocket>> {
+        let mut sockets = Vec::new();
+        for url in self.ephemeral_ws_urls() {
+            let socket = match Url::parse(url) {
+                Ok(url) => connect_async(url)
+                    .await
+                    .map(|(socket, _)| socket)
+                    .map_err(DirectorPubsubError::from),
+                Err(err) => Err(err.into()),
+            };
+            match socket {
+                Ok(socket) => sockets.push(Some(socket)),
+                Err(err) => {
+                    warn!(
+                        "Failed to connect to ephemeral validator {}: {:?}",
+                        url, err
+                    );
+                    sockets.push(None);
+                }
+            }
+        }
+        sockets
+    }
+
+    /// The websocket URLs of all ephemeral validators without duplicates,
+    /// starting with the default one
+    pub(crate) fn ephemeral_ws_urls(&self) -> Vec<&str> {
+        let mut urls = vec![self.config.ephem_rpc_provider_config.ws_url()];
+        let mut registered_urls = self
+            .config
+            .validator_registry
+            .iter()
+            .map(|(_, config)| config.ws_url())
+            .collect::<Vec<_>>();
+        // Registry iteration order is arbitrary
+        registered_urls.sort();
+        for url in registered_urls {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        urls
+    }
 }
 
 #[cfg(test)]
@@ -120,10 +313,11 @@ mod tests {
 
     use conjunto_test_tools::{
         account_provider_stub::AccountProviderStub,
+        accounts::account_with_data,
         signature_status_provider_stub::SignatureStatusProviderStub,
     };
     use serde_json::Value;
-    use solana_sdk::signature::Signature;
+    use solana_sdk::{pubkey::Pubkey, signature::Signature};
 
     use super::*;
 
@@ -136,7 +330,7 @@ mod tests {
         expected: &RequestEndpoint,
     ) {
         let msg = Message::Text(msg_val.to_string());
-        let actual = director.guide_msg(&msg).await.unwrap();
+        let (actual, _) = director.guide_msg(&msg).await.unwrap().unwrap();
         assert_eq!(&actual, expected);
     }
 
@@ -210,5 +404,136 @@ mod tests {
         guide_and_assert(&director, subscribe, &RequestEndpoint::Chain).await;
     }
 
+    // -----------------
+    // logsSubscribe
+    // -----------------
+    fn subscribe_logs_mentioning(pubkeys: &[Pubkey]) -> Value {
+        let mentions = pubkeys
+            .iter()
+            .map(|pubkey| pubkey.to_string())
+            .collect::<Vec<_>>();
+        serde_json::json! {{
+            "method": "logsSubscribe",
+            "params": [ { "mentions": mentions }, { "commitment": "finalized" }]
+        }}
+    }
+
+    #[tokio::test]
+    async fn test_guide_subscribe_logs_mentioning_accounts() {
+        let ephemeral = Pubkey::new_unique();
+        let missing = Pubkey::new_unique();
+        let mut account_provider = AccountProviderStub::default();
+        account_provider.add(ephemeral, account_with_data());
+
+        let director = DirectorPubsub::with_providers(
+            DirectorPubsubConfig::devnet(),
+            account_provider,
+            SignatureStatusProviderStub::default(),
+        );
+        guide_and_assert(
+            &director,
+            subscribe_logs_mentioning(&[ephemeral]),
+            &RequestEndpoint::Ephemeral,
+        )
+        .await;
+        guide_and_assert(
+            &director,
+            subscribe_logs_mentioning(&[ephemeral, missing]),
+            &RequestEndpoint::Both,
+        )
+        .await;
+    }
+
     // TODO(thlorenz): Add more tests for other pubsub messages
+
+    // -----------------
+    // Validator Registry
+    // -----------------
+    fn custom_config(ws_url: &str) -> RpcProviderConfig {
+        RpcProviderConfig::new(
+            RpcCluster::Custom(
+                "http://localhost:8899".to_string(),
+                ws_url.to_string(),
+            ),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_ephemeral_ws_urls_include_registry_without_duplicates() {
+        let mut config = DirectorPubsubConfig::devnet();
+        let default_ws_url =
+            config.ephem_rpc_provider_config.ws_url().to_string();
+        config.validator_registry = ValidatorRegistry::new()
+            .with_validator(
+                Pubkey::new_unique(),
+                custom_config("ws://localhost:7800"),
+            )
+            .with_validator(
+                Pubkey::new_unique(),
+                custom_config(&default_ws_url),
+            )
+            .with_validator(
+                Pubkey::new_unique(),
+                custom_config("ws://localhost:7800"),
+            );
+
+        let director = DirectorPubsub::with_providers(
+            config,
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        );
+        assert_eq!(
+            director.ephemeral_ws_urls(),
+            vec![default_ws_url.as_str(), "ws://localhost:7800"]
+        );
+    }
+
+    #[test]
+    fn test_ephemeral_validator_of_authority() {
+        let ephem_authority = Pubkey::new_unique();
+        let registered = Pubkey::new_unique();
+        let unknown = Pubkey::new_unique();
+        let mut config = DirectorPubsubConfig::devnet();
+        config.ephem_authority = Some(ephem_authority);
+        config.validator_registry = ValidatorRegistry::new()
+            .with_validator(registered, custom_config("ws://localhost:7800"));
+
+        let director = DirectorPubsub::with_providers(
+            config,
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        );
+        assert_eq!(director.ephemeral_validator_of(None).unwrap(), 0);
+        assert_eq!(
+            director
+                .ephemeral_validator_of(Some(&ephem_authority))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            director.ephemeral_validator_of(Some(&registered)).unwrap(),
+            1
+        );
+        assert!(matches!(
+            director.ephemeral_validator_of(Some(&unknown)),
+            Err(DirectorPubsubError::UnknownValidator(authority))
+                if authority == unknown
+        ));
+    }
+
+    #[test]
+    fn test_ephemeral_validator_of_authority_without_registry() {
+        let director = DirectorPubsub::with_providers(
+            DirectorPubsubConfig::devnet(),
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        );
+        assert_eq!(
+            director
+                .ephemeral_validator_of(Some(&Pubkey::new_unique()))
+                .unwrap(),
+            0
+        );
+    }
 }
@@ -12,7 +12,15 @@ pub enum DirectorPubsubError {
     URLParseError(#[from] url::ParseError),
     #[error("SerdeJSONError")]
     SerdeJSONError(#[from] serde_json::Error),
+    #[error("GuidepointError")]
+    GuidepointError(#[from] conjunto_guidepoint::errors::GuidepointError),
 
     #[error("ParseClientSubscription error: {0}")]
     ParseClientSubscription(String),
+
+    #[error("No ephemeral validator registered for authority {0}")]
+    UnknownValidator(solana_sdk::pubkey::Pubkey),
+
+    #[error("ConjuntoCoreError: {0}")]
+    ConjuntoCoreError(#[from] conjunto_core::errors::CoreError),
 }
@@ -2,9 +2,14 @@ use conjunto_core::GuideStrategy;
 use log::*;
 use solana_rpc_client_api::config::RpcTransactionLogsFilter;
 
-use crate::messages::ParsedClientMessage;
+use crate::{messages::ParsedClientMessage, routing_rules::RoutingRules};
 
-pub fn guide_strategy_from_pubsub_msg(msg: &str) -> GuideStrategy {
+/// Finds the strategy of the first matching routing rule and falls back to
+/// the built-in strategy of the method
+pub fn guide_strategy_from_pubsub_msg(
+    msg: &str,
+    routing_rules: &RoutingRules,
+) -> GuideStrategy {
     let parsed = match ParsedClientMessage::try_from(msg) {
         Ok(method) => method,
         Err(err) => {
@@ -14,17 +19,21 @@ pub fn guide_strategy_from_pubsub_msg(msg: &str) -> GuideStrategy {
             return GuideStrategy::Chain;
         }
     };
+    routing_rules
+        .route_of(&parsed.method(), &parsed.params())
+        .and_then(|route| route.guide_strategy())
+        .unwrap_or_else(|| built_in_guide_strategy(parsed))
+}
+
+fn built_in_guide_strategy(parsed: ParsedClientMessage) -> GuideStrategy {
     use ParsedClientMessage::*;
     match parsed {
         // We don't know who the Ping/Pong is responding to so we forward to both
         Ping | Pong => GuideStrategy::Both,
 
-        // Unsubscribe methods have to go to both chain and ephemeral
-        // since we don't track subscription ids
-        // This results in invalid unsub requests, but for now this is fine
-        // The only way to improve this would be to parse all messages coming
-        // from the backend in order to pull out subscription ids and store
-        // which backend they belong to
+        // Unsubscribes are sent to the backend that confirmed the subscription
+        // id before they are guided, we only get here if we could not parse
+        // the id in which case we send them to both
         AccountUnsubscribe
         | BlockUnsubscribe
         | LogsUnsubscribe
@@ -63,12 +72,8 @@ pub fn guide_strategy_from_pubsub_msg(msg: &str) -> GuideStrategy {
             match filter {
                 All => GuideStrategy::Ephemeral,
                 AllWithVotes => GuideStrategy::Chain,
-                Mentions(sigs) => {
-                    // NOTE: only one mentioned sig is supported
-                    GuideStrategy::TryEphemeralForSignature(
-                        sigs[0].to_string(),
-                        true,
-                    )
+                Mentions(addresses) => {
+                    GuideStrategy::TryEphemeralForAccounts(addresses, true)
                 }
             }
         }
@@ -82,7 +87,10 @@ mod tests {
     use super::*;
 
     fn guide_and_assert(msg: Value, expected: &GuideStrategy) {
-        let actual = guide_strategy_from_pubsub_msg(msg.to_string().as_str());
+        let actual = guide_strategy_from_pubsub_msg(
+            msg.to_string().as_str(),
+            &RoutingRules::default(),
+        );
         assert_eq!(&actual, expected);
     }
 
@@ -136,12 +144,12 @@ mod tests {
                 "method": "logsSubscribe",
                 "params": [
                     {
-                        "mentions": ["2EBVM6cB8vAAD93Ktr6Vd8p67XPbQzCJX47MpReuiCXJAtcjaxpvWpcg9Ege1Nr5Tk3a2GFrByT7WPBjdsTycY9b"]
+                        "mentions": ["SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ"]
                     }
                 ]
             }},
-            &GuideStrategy::TryEphemeralForSignature(
-                "2EBVM6cB8vAAD93Ktr6Vd8p67XPbQzCJX47MpReuiCXJAtcjaxpvWpcg9Ege1Nr5Tk3a2GFrByT7WPBjdsTycY9b".to_string(),
+            &GuideStrategy::TryEphemeralForAccounts(
+                vec!["SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ".to_string()],
                 true,
             ),
         );
@@ -188,6 +196,51 @@ mod tests {
             &GuideStrategy::Both,
         );
     }
+    #[test]
+    fn test_guide_with_routing_rules() {
+        let routing_rules = RoutingRules::from_json_str(
+            r#"{ "rules": [
+                { "method": "programSubscribe", "param": "11111111111111111111111111111111", "route": "builtIn" },
+                { "method": "programSubscribe", "route": "ephemeral" },
+                { "method": "slotSubscribe", "route": "chain" }
+            ] }"#,
+        )
+        .unwrap();
+        let guide = |msg: Value| {
+            guide_strategy_from_pubsub_msg(
+                msg.to_string().as_str(),
+                &routing_rules,
+            )
+        };
+
+        assert_eq!(
+            guide(serde_json::json! {{ "method": "slotSubscribe" }}),
+            GuideStrategy::Chain
+        );
+        assert_eq!(
+            guide(serde_json::json! {{
+                "method": "programSubscribe",
+                "params": ["SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ"]
+            }}),
+            GuideStrategy::Ephemeral
+        );
+        assert_eq!(
+            guide(serde_json::json! {{
+                "method": "programSubscribe",
+                "params": ["11111111111111111111111111111111"]
+            }}),
+            GuideStrategy::TryEphemeralForProgram(
+                "11111111111111111111111111111111".to_string(),
+                true,
+            )
+        );
+        // Methods without rules keep their built-in strategy
+        assert_eq!(
+            guide(serde_json::json! {{ "method": "rootSubscribe" }}),
+            GuideStrategy::Chain
+        );
+    }
+
     #[test]
     fn test_guide_account_unknown() {
         guide_and_assert(
@@ -18,6 +18,11 @@ pub mod director;
 pub mod errors;
 mod guide_strategy;
 mod messages;
+pub mod routing_rules;
+mod sequential_requests;
+mod subscriptions;
+
+pub use messages::ClientSubMethod;
 
 pub type BackendWebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 pub type BackendWebSocketWriter =
@@ -32,6 +37,7 @@ pub async fn start_pubsub_server<
     config: DirectorPubsubConfig,
     url: Option<&str>,
 ) -> DirectorPubsubResult<(String, JoinHandle<()>)> {
+    config.routing_rules.validate()?;
     let url = url.unwrap_or(DEFAULT_DIRECTOR_PUBSUB_URL);
     let listener = TcpListener::bind(&url).await?;
     let director = Arc::new(DirectorPubsub::<T, U>::new(config));
@@ -44,17 +50,13 @@ pub async fn start_pubsub_server<
                 }
                 Ok(client) => client,
             };
-            let ephem_client = match director.try_ephemeral_client().await {
-                Err(err) => {
-                    error!("Failed to connect to ephemeral client: {}", err);
-                    continue;
-                }
-                Ok(client) => client,
-            };
+            // Unreachable ephemeral validators are skipped, the client is
+            // served by the remaining ones
+            let ephem_clients = director.try_ephemeral_clients().await;
             tokio::spawn(accept_connection::accept_connection(
                 director.clone(),
                 chain_client,
-                ephem_client,
+                ephem_clients,
                 stream,
             ));
         }
@@ -12,7 +12,7 @@ pub struct ClientSubMethodMessage {
     pub method: ClientSubMethod,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ClientSubMethod {
     Ping,
@@ -37,6 +37,24 @@ pub enum ClientSubMethod {
     VoteUnsubscribe,
 }
 
+impl ClientSubMethod {
+    pub fn is_unsubscribe(&self) -> bool {
+        use ClientSubMethod::*;
+        matches!(
+            self,
+            AccountUnsubscribe
+                | BlockUnsubscribe
+                | LogsUnsubscribe
+                | ProgramUnsubscribe
+                | RootUnsubscribe
+                | SignatureUnsubscribe
+                | SlotUnsubscribe
+                | SlotsUpdatesUnsubscribe
+                | VoteUnsubscribe
+        )
+    }
+}
+
 impl TryFrom<&str> for ClientSubMethod {
     type Error = serde_json::Error;
 
@@ -112,6 +130,99 @@ impl TryFrom<&str> for ClientSubWithParams {
     }
 }
 
+// -----------------
+// Error Response
+// -----------------
+#[derive(Deserialize)]
+struct ClientRequestId {
+    #[serde(default)]
+    id: serde_json::Value,
+}
+
+/// JSON-RPC internal error, used when we fail to guide a request
+pub const JSON_RPC_INTERNAL_ERROR: i64 = -32603;
+
+/// JSON-RPC invalid params error, used when the client unsubscribes from an
+/// unknown subscription
+pub const JSON_RPC_INVALID_PARAMS: i64 = -32602;
+
+/// JSON-RPC invalid request error, used when the client reuses the id of a
+/// request that is still pending
+pub const JSON_RPC_INVALID_REQUEST: i64 = -32600;
+
+/// The id of the client request, `null` if we cannot find one
+pub fn request_id(request: &str) -> serde_json::Value {
+    serde_json::from_str::<ClientRequestId>(request)
+        .map(|request| request.id)
+        .unwrap_or_default()
+}
+
+/// Builds the error response to the client request, using its id if we can
+/// find one
+pub fn error_response(request: &str, code: i64, message: &str) -> String {
+    let id = request_id(request);
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+    .to_string()
+}
+
+// -----------------
+// BackendResponse
+// -----------------
+/// Response of a backend to a client request, notifications don't have an id
+#[derive(Debug, Deserialize)]
+pub struct BackendResponse {
+    #[serde(default)]
+    pub id: serde_json::Value,
+    #[serde(default)]
+    pub result: serde_json::Value,
+    #[serde(default)]
+    pub error: Option<serde_json::Value>,
+}
+
+impl BackendResponse {
+    /// True if the backend failed to respond or had nothing to respond with
+    pub fn is_empty(&self) -> bool {
+        self.error.is_some() || self.result.is_null()
+    }
+}
+
+// -----------------
+// Subscription Ids
+// -----------------
+#[derive(Deserialize)]
+struct ClientUnsubscribeMessage {
+    method: ClientSubMethod,
+    params: (u64,),
+}
+
+/// Confirmation of a subscribe request, the result is the subscription id.
+/// Notifications only have a result inside their params and unsubscribe
+/// responses a boolean one, so neither parses
+#[derive(Deserialize)]
+struct SubscriptionConfirmation {
+    result: u64,
+}
+
+/// The subscription id the client wants to unsubscribe from, `None` for
+/// other messages
+pub fn unsubscribe_id(request: &str) -> Option<u64> {
+    serde_json::from_str::<ClientUnsubscribeMessage>(request)
+        .ok()
+        .filter(|msg| msg.method.is_unsubscribe())
+        .map(|msg| msg.params.0)
+}
+
+/// The subscription id a backend confirmed, `None` for other messages
+pub fn subscription_id(response: &str) -> Option<u64> {
+    serde_json::from_str::<SubscriptionConfirmation>(response)
+        .ok()
+        .map(|confirmation| confirmation.result)
+}
+
 // -----------------
 // ParsedClientSub
 // -----------------
@@ -148,6 +259,48 @@ pub enum ParsedClientMessage {
     VoteUnsubscribe,
 }
 
+impl ParsedClientMessage {
+    pub fn method(&self) -> ClientSubMethod {
+        use ParsedClientMessage::*;
+        match self {
+            Ping => ClientSubMethod::Ping,
+            Pong => ClientSubMethod::Pong,
+            AccountSubscribe { .. } => ClientSubMethod::AccountSubscribe,
+            AccountUnsubscribe => ClientSubMethod::AccountUnsubscribe,
+            BlockSubscribe => ClientSubMethod::BlockSubscribe,
+            BlockUnsubscribe => ClientSubMethod::BlockUnsubscribe,
+            LogsSubscribe { .. } => ClientSubMethod::LogsSubscribe,
+            LogsUnsubscribe => ClientSubMethod::LogsUnsubscribe,
+            ProgramSubscribe { .. } => ClientSubMethod::ProgramSubscribe,
+            ProgramUnsubscribe => ClientSubMethod::ProgramUnsubscribe,
+            RootSubscribe => ClientSubMethod::RootSubscribe,
+            RootUnsubscribe => ClientSubMethod::RootUnsubscribe,
+            SignatureSubscribe { .. } => ClientSubMethod::SignatureSubscribe,
+            SignatureUnsubscribe => ClientSubMethod::SignatureUnsubscribe,
+            SlotSubscribe => ClientSubMethod::SlotSubscribe,
+            SlotUnsubscribe => ClientSubMethod::SlotUnsubscribe,
+            SlotsUpdatesSubscribe => ClientSubMethod::SlotsUpdatesSubscribe,
+            SlotsUpdatesUnsubscribe => ClientSubMethod::SlotsUpdatesUnsubscribe,
+            VoteSubscribe => ClientSubMethod::VoteSubscribe,
+            VoteUnsubscribe => ClientSubMethod::VoteUnsubscribe,
+        }
+    }
+
+    /// The addresses, program ids or signatures the message refers to
+    pub fn params(&self) -> Vec<&str> {
+        use ParsedClientMessage::*;
+        match self {
+            AccountSubscribe { address } => vec![address.as_str()],
+            ProgramSubscribe { program_id } => vec![program_id.as_str()],
+            SignatureSubscribe { signature } => vec![signature.as_str()],
+            LogsSubscribe {
+                filter: RpcTransactionLogsFilter::Mentions(addresses),
+            } => addresses.iter().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+}
+
 impl TryFrom<&str> for ParsedClientMessage {
     type Error = DirectorPubsubError;
 
@@ -438,6 +591,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_error_response() {
+        let response: Value = serde_json::from_str(&error_response(
+            r#"{ "jsonrpc": "2.0", "id": 42, "method": "accountSubscribe" }"#,
+            JSON_RPC_INTERNAL_ERROR,
+            "Failed to guide",
+        ))
+        .unwrap();
+        assert_eq!(
+            response,
+            serde_json::json! {{
+                "jsonrpc": "2.0",
+                "error": { "code": -32603, "message": "Failed to guide" },
+                "id": 42
+            }}
+        );
+
+        let response: Value = serde_json::from_str(&error_response(
+            "<not json>",
+            JSON_RPC_INTERNAL_ERROR,
+            "Failed to guide",
+        ))
+        .unwrap();
+        assert_eq!(response["id"], Value::Null);
+    }
+
+    #[test]
+    fn test_subscription_ids() {
+        let unsubscribe = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "accountUnsubscribe",
+            "params": [42]
+        }};
+        assert_eq!(unsubscribe_id(&unsubscribe.to_string()), Some(42));
+        let subscribe = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "slotSubscribe",
+            "params": [42]
+        }};
+        assert_eq!(unsubscribe_id(&subscribe.to_string()), None);
+
+        let confirmation = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": 42
+        }};
+        assert_eq!(subscription_id(&confirmation.to_string()), Some(42));
+        let unsubscribed = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 2,
+            "result": true
+        }};
+        assert_eq!(subscription_id(&unsubscribed.to_string()), None);
+        let notification = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "method": "slotNotification",
+            "params": { "result": { "slot": 7 }, "subscription": 42 }
+        }};
+        assert_eq!(subscription_id(&notification.to_string()), None);
+    }
+
     #[test]
     fn test_non_parametrized() {
         parse_and_assert(
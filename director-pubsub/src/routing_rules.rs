@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use conjunto_core::routing_rules::RoutableMethod;
+pub use conjunto_core::routing_rules::Route;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+use crate::messages::ClientSubMethod;
+
+/// Overrides the built-in strategies of pubsub methods, the first rule
+/// matching a message decides where it goes.
+/// Loaded from JSON, i.e. `{ "rules": [{ "method": "slotSubscribe",
+/// "route": "chain" }] }` or the equivalent TOML.
+/// For `logsSubscribe` any of the mentions needs to match the param of a
+/// rule.
+pub type RoutingRules =
+    conjunto_core::routing_rules::RoutingRules<ClientSubMethod>;
+pub type RoutingRule =
+    conjunto_core::routing_rules::RoutingRule<ClientSubMethod>;
+
+impl RoutableMethod for ClientSubMethod {
+    /// Unsubscribes cannot be routed and only subscribes with an address,
+    /// program id or signature take a param
+    fn validate_rule(
+        &self,
+        param: Option<&str>,
+        _route: Route,
+    ) -> Result<(), String> {
+        use ClientSubMethod::*;
+        if self.is_unsubscribe() {
+            return Err(format!(
+                "{:?} is always sent to the backend owning the subscription",
+                self
+            ));
+        }
+        let param = match param {
+            Some(param) => param,
+            None => return Ok(()),
+        };
+        match self {
+            AccountSubscribe | ProgramSubscribe | LogsSubscribe => {
+                Pubkey::from_str(param)
+                    .map(|_| ())
+                    .map_err(|err| format!("invalid address {param}: {err}"))
+            }
+            SignatureSubscribe => Signature::from_str(param)
+                .map(|_| ())
+                .map_err(|err| format!("invalid signature {param}: {err}")),
+            method => Err(format!("{method:?} does not take a param")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conjunto_core::errors::CoreError;
+    use solana_rpc_client_api::config::RpcTransactionLogsFilter;
+
+    use super::*;
+    use crate::messages::ParsedClientMessage;
+
+    fn route_of(
+        routing_rules: &RoutingRules,
+        parsed: &ParsedClientMessage,
+    ) -> Option<Route> {
+        routing_rules.route_of(&parsed.method(), &parsed.params())
+    }
+
+    const PROGRAM_ID: &str = "SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ";
+
+    #[test]
+    fn test_parse_json_and_toml_rules() {
+        let json = RoutingRules::from_json_str(&format!(
+            r#"{{
+                "rules": [
+                    {{ "method": "programSubscribe", "param": "{PROGRAM_ID}", "route": "ephemeral" }},
+                    {{ "method": "programSubscribe", "route": "chain" }},
+                    {{ "method": "slotSubscribe", "route": "both" }}
+                ]
+            }}"#
+        ))
+        .unwrap();
+        let toml = RoutingRules::from_toml_str(&format!(
+            r#"
+            [[rules]]
+            method = "programSubscribe"
+            param = "{PROGRAM_ID}"
+            route = "ephemeral"
+
+            [[rules]]
+            method = "programSubscribe"
+            route = "chain"
+
+            [[rules]]
+            method = "slotSubscribe"
+            route = "both"
+            "#
+        ))
+        .unwrap();
+
+        assert_eq!(json, toml);
+        assert_eq!(
+            json.rules[0],
+            RoutingRule {
+                method: ClientSubMethod::ProgramSubscribe,
+                param: Some(PROGRAM_ID.to_string()),
+                route: Route::Ephemeral,
+            }
+        );
+    }
+
+    #[test]
+    fn test_first_matching_rule_decides() {
+        let routing_rules = RoutingRules {
+            rules: vec![
+                RoutingRule {
+                    method: ClientSubMethod::LogsSubscribe,
+                    param: Some(PROGRAM_ID.to_string()),
+                    route: Route::BuiltIn,
+                },
+                RoutingRule {
+                    method: ClientSubMethod::LogsSubscribe,
+                    param: None,
+                    route: Route::Chain,
+                },
+            ],
+        };
+        let mentioning =
+            |addresses: Vec<&str>| ParsedClientMessage::LogsSubscribe {
+                filter: RpcTransactionLogsFilter::Mentions(
+                    addresses.into_iter().map(String::from).collect(),
+                ),
+            };
+
+        assert_eq!(
+            route_of(
+                &routing_rules,
+                &mentioning(vec![
+                    &Pubkey::new_unique().to_string(),
+                    PROGRAM_ID
+                ])
+            ),
+            Some(Route::BuiltIn)
+        );
+        assert_eq!(
+            route_of(
+                &routing_rules,
+                &mentioning(vec![&Pubkey::new_unique().to_string()])
+            ),
+            Some(Route::Chain)
+        );
+        assert_eq!(
+            route_of(&routing_rules, &ParsedClientMessage::SlotSubscribe),
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let assert_invalid = |json: &str, expected_idx: usize| {
+            match RoutingRules::from_json_str(json) {
+                Err(CoreError::InvalidRoutingRule { idx, .. }) => {
+                    assert_eq!(idx, expected_idx)
+                }
+                result => panic!("unexpected result {:?}", result),
+            }
+        };
+
+        // Params need to be valid
+        assert_invalid(
+            r#"{ "rules": [{ "method": "accountSubscribe", "param": "<not a pubkey>", "route": "chain" }] }"#,
+            0,
+        );
+        // Only methods with params can match them
+        assert_invalid(
+            &format!(
+                r#"{{ "rules": [{{ "method": "slotSubscribe", "param": "{PROGRAM_ID}", "route": "chain" }}] }}"#
+            ),
+            0,
+        );
+        // Unsubscribes follow their subscription
+        assert_invalid(
+            r#"{ "rules": [{ "method": "accountUnsubscribe", "route": "chain" }] }"#,
+            0,
+        );
+        // Rules after a rule for the whole method are never used
+        assert_invalid(
+            &format!(
+                r#"{{ "rules": [
+                    {{ "method": "programSubscribe", "route": "chain" }},
+                    {{ "method": "programSubscribe", "param": "{PROGRAM_ID}", "route": "ephemeral" }}
+                ] }}"#
+            ),
+            1,
+        );
+
+        // Unknown methods and routes fail to parse
+        assert!(matches!(
+            RoutingRules::from_json_str(
+                r#"{ "rules": [{ "method": "someNewSubscribe", "route": "chain" }] }"#
+            ),
+            Err(CoreError::SerdeJSONError(_))
+        ));
+        assert!(matches!(
+            RoutingRules::from_toml_str(
+                "[[rules]]\nmethod = \"slotSubscribe\"\nroute = \"elsewhere\"\n"
+            ),
+            Err(CoreError::TomlError(_))
+        ));
+    }
+}
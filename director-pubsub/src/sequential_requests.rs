@@ -0,0 +1,225 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use conjunto_core::RequestEndpoint;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::messages::{request_id, BackendResponse};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Backend {
+    Chain,
+    /// Index of the ephemeral validator
+    Ephemeral(usize),
+}
+
+impl Backend {
+    /// The backends a request is sent to in order if the endpoint is
+    /// sequential, the second one is only asked if the first one has no result
+    pub fn sequence_of(
+        endpoint: &RequestEndpoint,
+        validator: usize,
+    ) -> Option<(Self, Self)> {
+        match endpoint {
+            RequestEndpoint::EphemeralThenChain => {
+                Some((Backend::Ephemeral(validator), Backend::Chain))
+            }
+            RequestEndpoint::ChainThenEphemeral => {
+                Some((Backend::Chain, Backend::Ephemeral(validator)))
+            }
+            _ => None,
+        }
+    }
+}
+
+struct PendingRequest {
+    first: Backend,
+    second: Backend,
+    msg: Message,
+}
+
+/// Client requests that were sent to one backend and are sent to the other
+/// one only if the first one fails or responds with `null`
+#[derive(Default)]
+pub(crate) struct SequentialRequests {
+    /// By the serialized request id
+    pending: HashMap<String, PendingRequest>,
+}
+
+impl SequentialRequests {
+    /// Remembers the request until the first backend responds, requests
+    /// without id are not tracked since we cannot match their responses.
+    /// A request whose id is still pending is not tracked either and
+    /// returns `false`, the pending one keeps waiting for its response.
+    pub fn insert(
+        &mut self,
+        msg: &Message,
+        first: Backend,
+        second: Backend,
+    ) -> bool {
+        let id = match msg.to_text().map(request_id) {
+            Ok(id) if !id.is_null() => id,
+            _ => return true,
+        };
+        match self.pending.entry(id.to_string()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(PendingRequest {
+                    first,
+                    second,
+                    msg: msg.clone(),
+                });
+                true
+            }
+        }
+    }
+
+    /// Stops tracking the request, i.e. when it could not be sent
+    pub fn remove(&mut self, msg: &Message) {
+        if let Ok(id) = msg.to_text().map(request_id) {
+            self.pending.remove(&id.to_string());
+        }
+    }
+
+    /// Checks the response of the backend to a pending request.
+    /// Returns the request and the backend to send it to if the response is
+    /// empty, in that case the response must not be forwarded to the client.
+    pub fn take_retry(
+        &mut self,
+        backend: Backend,
+        response: &Message,
+    ) -> Option<(Backend, Message)> {
+        if self.is_empty() {
+            return None;
+        }
+        let response =
+            serde_json::from_str::<BackendResponse>(response.to_text().ok()?)
+                .ok()?;
+        let id = response.id.to_string();
+        if self.pending.get(&id)?.first != backend {
+            return None;
+        }
+        let pending = self.pending.remove(&id)?;
+        response.is_empty().then_some((pending.second, pending.msg))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn text(value: Value) -> Message {
+        Message::Text(value.to_string())
+    }
+
+    fn request(id: u64) -> Message {
+        text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "accountSubscribe",
+            "params": ["SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ"]
+        }})
+    }
+
+    #[test]
+    fn test_failed_request_is_retried_on_second_backend() {
+        let mut requests = SequentialRequests::default();
+        assert!(requests.insert(
+            &request(1),
+            Backend::Ephemeral(1),
+            Backend::Chain
+        ));
+
+        // Responses of other backends and notifications are ignored
+        let error = text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32602, "message": "Invalid param" }
+        }});
+        assert!(requests.take_retry(Backend::Chain, &error).is_none());
+        assert!(requests.take_retry(Backend::Ephemeral(0), &error).is_none());
+        let notification = text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "method": "accountNotification",
+            "params": { "result": null, "subscription": 1 }
+        }});
+        assert!(requests
+            .take_retry(Backend::Ephemeral(1), &notification)
+            .is_none());
+        assert!(!requests.is_empty());
+
+        assert_eq!(
+            requests.take_retry(Backend::Ephemeral(1), &error),
+            Some((Backend::Chain, request(1)))
+        );
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn test_successful_request_is_not_retried() {
+        let mut requests = SequentialRequests::default();
+        assert!(requests.insert(
+            &request(1),
+            Backend::Chain,
+            Backend::Ephemeral(0)
+        ));
+
+        let confirmation = text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": 42
+        }});
+        assert!(requests.take_retry(Backend::Chain, &confirmation).is_none());
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn test_reused_pending_id_is_rejected() {
+        let mut requests = SequentialRequests::default();
+        assert!(requests.insert(
+            &request(1),
+            Backend::Chain,
+            Backend::Ephemeral(0)
+        ));
+        assert!(!requests.insert(
+            &request(1),
+            Backend::Ephemeral(0),
+            Backend::Chain
+        ));
+
+        // The first request keeps waiting for the chain response
+        let empty = text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": null
+        }});
+        assert!(requests.take_retry(Backend::Ephemeral(0), &empty).is_none());
+        assert_eq!(
+            requests.take_retry(Backend::Chain, &empty),
+            Some((Backend::Ephemeral(0), request(1)))
+        );
+        assert!(requests.insert(
+            &request(1),
+            Backend::Chain,
+            Backend::Ephemeral(0)
+        ));
+    }
+
+    #[test]
+    fn test_backend_sequence_of_endpoint() {
+        assert_eq!(
+            Backend::sequence_of(&RequestEndpoint::EphemeralThenChain, 2),
+            Some((Backend::Ephemeral(2), Backend::Chain))
+        );
+        assert_eq!(
+            Backend::sequence_of(&RequestEndpoint::ChainThenEphemeral, 2),
+            Some((Backend::Chain, Backend::Ephemeral(2)))
+        );
+        assert_eq!(Backend::sequence_of(&RequestEndpoint::Both, 0), None);
+    }
+}
@@ -0,0 +1,498 @@
+use std::{collections::HashMap, str::FromStr};
+
+use log::*;
+use serde_json::Value;
+use solana_sdk::signature::Signature;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    messages::{
+        request_id, subscription_id, unsubscribe_id, ParsedClientMessage,
+    },
+    sequential_requests::Backend,
+};
+
+/// Client request that was sent to several backends, only one of their
+/// responses is forwarded to the client
+#[derive(Debug, Default)]
+struct PendingResponses {
+    /// Backends that have not responded yet
+    remaining: usize,
+    /// Set once a response was forwarded
+    answered: bool,
+    /// The id the client got for the subscription if the request is a
+    /// subscribe that was confirmed already
+    client_sub_id: Option<u64>,
+}
+
+/// Subscriptions of a client connection.
+/// Each backend numbers its subscriptions on its own, so the client gets ids
+/// of this connection that are mapped to the backends that confirmed them.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    next_client_sub_id: u64,
+    /// Backend subscription ids by the id the client knows
+    backend_sub_ids: HashMap<u64, Vec<(Backend, u64)>>,
+    /// The id the client knows by backend and backend subscription id
+    client_sub_ids: HashMap<(Backend, u64), u64>,
+    /// By the serialized request id
+    pending: HashMap<String, PendingResponses>,
+    /// Signatures of subscribe requests that are not confirmed yet, by the
+    /// serialized request id
+    pending_signatures: HashMap<String, Signature>,
+    /// Signatures of signature subscriptions by the id the client knows
+    signatures: HashMap<u64, Signature>,
+}
+
+impl Subscriptions {
+    /// Merges the responses of the backends the request was sent to into one,
+    /// requests without id are not tracked since we cannot match their
+    /// responses
+    pub fn expect_responses(&mut self, request: &Message, backends: usize) {
+        if backends < 2 {
+            return;
+        }
+        let id = match request.to_text().map(request_id) {
+            Ok(id) if !id.is_null() => id,
+            _ => return,
+        };
+        self.pending.insert(
+            id.to_string(),
+            PendingResponses {
+                remaining: backends,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Remembers the signature of a `signatureSubscribe` until it is
+    /// confirmed, so we know which transaction its notifications refer to
+    pub fn track_request(&mut self, request: &Message) {
+        let text = match request.to_text() {
+            Ok(text) => text,
+            Err(_) => return,
+        };
+        let signature = match ParsedClientMessage::try_from(text) {
+            Ok(ParsedClientMessage::SignatureSubscribe { signature }) => {
+                Signature::from_str(&signature)
+            }
+            _ => return,
+        };
+        let id = request_id(text);
+        if let (Ok(signature), false) = (signature, id.is_null()) {
+            self.pending_signatures.insert(id.to_string(), signature);
+        }
+    }
+
+    /// The signature whose transaction landed in the ephemeral validator if
+    /// the message is the final notification of its subscription
+    pub fn landed_signature(
+        &self,
+        backend: Backend,
+        notification: &Message,
+    ) -> Option<Signature> {
+        if !matches!(backend, Backend::Ephemeral(_)) {
+            return None;
+        }
+        let value =
+            serde_json::from_str::<Value>(notification.to_text().ok()?).ok()?;
+        if !is_final_notification(&value) {
+            return None;
+        }
+        let sub_id = value["params"]["subscription"].as_u64()?;
+        let client_sub_id = self.client_sub_ids.get(&(backend, sub_id))?;
+        self.signatures.get(client_sub_id).copied()
+    }
+
+    /// Rewrites subscription ids of the backend message to the ones the client
+    /// knows. Returns `None` if the message must not be forwarded, i.e. since
+    /// another backend answered the same request already or the subscription
+    /// is unknown.
+    pub fn forward_response(
+        &mut self,
+        backend: Backend,
+        response: Message,
+    ) -> Option<Message> {
+        let text = match response.to_text() {
+            Ok(text) => text,
+            Err(_) => return Some(response),
+        };
+        let mut value = match serde_json::from_str::<Value>(text) {
+            Ok(value @ Value::Object(_)) => value,
+            _ => return Some(response),
+        };
+
+        // Notification
+        if let Some(sub_id) = value["params"]["subscription"].as_u64() {
+            let client_sub_id = match self
+                .client_sub_ids
+                .get(&(backend, sub_id))
+            {
+                Some(client_sub_id) => *client_sub_id,
+                None => {
+                    trace!(
+                        "Dropping notification of unknown subscription {} of {:?}",
+                        sub_id,
+                        backend
+                    );
+                    return None;
+                }
+            };
+            if is_final_notification(&value) {
+                self.remove(client_sub_id);
+            }
+            value["params"]["subscription"] = client_sub_id.into();
+            return Some(Message::Text(value.to_string()));
+        }
+
+        let request_id = value["id"].to_string();
+        let sub_id = match subscription_id(text) {
+            Some(sub_id) => sub_id,
+            // Response to any other request
+            None => {
+                let succeeded = value.get("error").is_none();
+                let forward = self.settle(&request_id, succeeded);
+                if forward && !succeeded {
+                    self.pending_signatures.remove(&request_id);
+                }
+                return forward.then_some(response);
+            }
+        };
+
+        // Subscription confirmation, if another backend confirmed the same
+        // request already the client knows the subscription
+        let confirmed = self
+            .pending
+            .get(&request_id)
+            .and_then(|pending| pending.client_sub_id);
+        let forward = self.settle(&request_id, true);
+        let client_sub_id =
+            confirmed.unwrap_or_else(|| self.next_client_sub_id());
+        self.backend_sub_ids
+            .entry(client_sub_id)
+            .or_default()
+            .push((backend, sub_id));
+        self.client_sub_ids.insert((backend, sub_id), client_sub_id);
+        if let Some(signature) = self.pending_signatures.remove(&request_id) {
+            self.signatures.insert(client_sub_id, signature);
+        }
+        if let Some(pending) = self.pending.get_mut(&request_id) {
+            pending.client_sub_id = Some(client_sub_id);
+        }
+        if !forward {
+            return None;
+        }
+        value["result"] = client_sub_id.into();
+        Some(Message::Text(value.to_string()))
+    }
+
+    /// The unsubscribe requests for each backend owning the subscription the
+    /// client wants to unsubscribe from, `None` for other messages and an
+    /// empty list if the subscription is unknown
+    pub fn take_unsubscribe_requests(
+        &mut self,
+        request: &Message,
+    ) -> Option<Vec<(Backend, Message)>> {
+        let text = request.to_text().ok()?;
+        let client_sub_id = unsubscribe_id(text)?;
+        let mut value = serde_json::from_str::<Value>(text).ok()?;
+        let requests = self
+            .remove(client_sub_id)
+            .into_iter()
+            .map(|(backend, sub_id)| {
+                value["params"][0] = sub_id.into();
+                (backend, Message::Text(value.to_string()))
+            })
+            .collect();
+        Some(requests)
+    }
+
+    fn next_client_sub_id(&mut self) -> u64 {
+        let client_sub_id = self.next_client_sub_id;
+        self.next_client_sub_id += 1;
+        client_sub_id
+    }
+
+    fn remove(&mut self, client_sub_id: u64) -> Vec<(Backend, u64)> {
+        let backend_sub_ids = self
+            .backend_sub_ids
+            .remove(&client_sub_id)
+            .unwrap_or_default();
+        for backend_sub_id in &backend_sub_ids {
+            self.client_sub_ids.remove(backend_sub_id);
+        }
+        self.signatures.remove(&client_sub_id);
+        backend_sub_ids
+    }
+
+    /// Counts the response towards its pending request, returns `true` if it
+    /// is forwarded to the client. The first successful response is
+    /// forwarded, an error only if no other backend can answer anymore.
+    fn settle(&mut self, request_id: &str, succeeded: bool) -> bool {
+        let pending = match self.pending.get_mut(request_id) {
+            Some(pending) => pending,
+            None => return true,
+        };
+        pending.remaining = pending.remaining.saturating_sub(1);
+        let forward =
+            !pending.answered && (succeeded || pending.remaining == 0);
+        pending.answered |= forward;
+        if pending.remaining == 0 {
+            self.pending.remove(request_id);
+        }
+        forward
+    }
+}
+
+/// Signature subscriptions end with the notification of the processed
+/// transaction, only the one that it was received has a string value
+fn is_final_notification(notification: &Value) -> bool {
+    notification["method"] == "signatureNotification"
+        && !notification["params"]["result"]["value"].is_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: Value) -> Message {
+        Message::Text(value.to_string())
+    }
+
+    fn json(msg: &Message) -> Value {
+        serde_json::from_str(msg.to_text().unwrap()).unwrap()
+    }
+
+    fn confirmation(id: u64, sub_id: u64) -> Message {
+        text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": sub_id
+        }})
+    }
+
+    fn subscribe(id: u64) -> Message {
+        text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "slotSubscribe"
+        }})
+    }
+
+    fn unsubscribe(sub_id: u64) -> Message {
+        text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 9,
+            "method": "slotUnsubscribe",
+            "params": [sub_id]
+        }})
+    }
+
+    fn notification(method: &str, sub_id: u64, value: Value) -> Message {
+        text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": { "result": { "value": value }, "subscription": sub_id }
+        }})
+    }
+
+    fn client_sub_id(response: Option<Message>) -> u64 {
+        json(&response.unwrap())["result"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn test_backend_sub_ids_are_rewritten() {
+        let mut subscriptions = Subscriptions::default();
+        // Both backends use the same id for different subscriptions
+        let chain = client_sub_id(
+            subscriptions.forward_response(Backend::Chain, confirmation(1, 7)),
+        );
+        let ephem = client_sub_id(
+            subscriptions
+                .forward_response(Backend::Ephemeral(1), confirmation(2, 7)),
+        );
+        assert_ne!(chain, ephem);
+
+        let forwarded = subscriptions
+            .forward_response(
+                Backend::Ephemeral(1),
+                notification("slotNotification", 7, Value::Null),
+            )
+            .unwrap();
+        assert_eq!(json(&forwarded)["params"]["subscription"], ephem);
+        // Notifications of unknown subscriptions are dropped
+        assert!(subscriptions
+            .forward_response(
+                Backend::Ephemeral(0),
+                notification("slotNotification", 7, Value::Null),
+            )
+            .is_none());
+
+        let requests = subscriptions
+            .take_unsubscribe_requests(&unsubscribe(ephem))
+            .unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, Backend::Ephemeral(1));
+        assert_eq!(json(&requests[0].1)["params"][0], 7);
+        // Once unsubscribed the id is no longer known
+        assert_eq!(
+            subscriptions.take_unsubscribe_requests(&unsubscribe(ephem)),
+            Some(vec![])
+        );
+        assert_eq!(
+            subscriptions
+                .take_unsubscribe_requests(&unsubscribe(chain))
+                .unwrap()[0]
+                .0,
+            Backend::Chain
+        );
+    }
+
+    #[test]
+    fn test_confirmations_of_several_backends_are_merged() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.expect_responses(&subscribe(1), 2);
+
+        let client_sub_id = client_sub_id(
+            subscriptions.forward_response(Backend::Chain, confirmation(1, 3)),
+        );
+        assert!(subscriptions
+            .forward_response(Backend::Ephemeral(0), confirmation(1, 5))
+            .is_none());
+
+        let requests = subscriptions
+            .take_unsubscribe_requests(&unsubscribe(client_sub_id))
+            .unwrap();
+        let sub_ids = requests
+            .iter()
+            .map(|(backend, request)| {
+                (*backend, json(request)["params"][0].clone())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sub_ids,
+            vec![
+                (Backend::Chain, Value::from(3)),
+                (Backend::Ephemeral(0), Value::from(5))
+            ]
+        );
+        subscriptions.expect_responses(&unsubscribe(client_sub_id), 2);
+        let unsubscribed = text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 9,
+            "result": true
+        }});
+        assert!(subscriptions
+            .forward_response(Backend::Chain, unsubscribed.clone())
+            .is_some());
+        assert!(subscriptions
+            .forward_response(Backend::Ephemeral(0), unsubscribed)
+            .is_none());
+    }
+
+    #[test]
+    fn test_error_is_forwarded_if_no_backend_confirms() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.expect_responses(&subscribe(1), 2);
+        let error = text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32601, "message": "Method not found" }
+        }});
+
+        assert!(subscriptions
+            .forward_response(Backend::Ephemeral(0), error.clone())
+            .is_none());
+        assert!(subscriptions
+            .forward_response(Backend::Chain, error)
+            .is_some());
+    }
+
+    #[test]
+    fn test_signature_subscription_ends_with_final_notification() {
+        let mut subscriptions = Subscriptions::default();
+        let client_sub_id = client_sub_id(
+            subscriptions
+                .forward_response(Backend::Ephemeral(0), confirmation(1, 4)),
+        );
+
+        let received = notification(
+            "signatureNotification",
+            4,
+            Value::from("receivedSignature"),
+        );
+        assert!(subscriptions
+            .forward_response(Backend::Ephemeral(0), received)
+            .is_some());
+        let processed = notification(
+            "signatureNotification",
+            4,
+            serde_json::json! {{ "err": null }},
+        );
+        let forwarded = subscriptions
+            .forward_response(Backend::Ephemeral(0), processed)
+            .unwrap();
+        assert_eq!(json(&forwarded)["params"]["subscription"], client_sub_id);
+
+        assert_eq!(
+            subscriptions
+                .take_unsubscribe_requests(&unsubscribe(client_sub_id)),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_landed_signature_of_final_ephemeral_notification() {
+        let signature = Signature::new_unique();
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.track_request(&text(serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "signatureSubscribe",
+            "params": [signature.to_string()]
+        }}));
+        subscriptions
+            .forward_response(Backend::Ephemeral(0), confirmation(1, 4));
+
+        let received = notification(
+            "signatureNotification",
+            4,
+            Value::from("receivedSignature"),
+        );
+        assert_eq!(
+            subscriptions.landed_signature(Backend::Ephemeral(0), &received),
+            None
+        );
+        let processed = notification(
+            "signatureNotification",
+            4,
+            serde_json::json! {{ "err": null }},
+        );
+        // Only transactions landing in the ephemeral validator matter
+        assert_eq!(
+            subscriptions.landed_signature(Backend::Chain, &processed),
+            None
+        );
+        assert_eq!(
+            subscriptions.landed_signature(Backend::Ephemeral(0), &processed),
+            Some(signature)
+        );
+    }
+
+    #[test]
+    fn test_other_messages_are_forwarded_unchanged() {
+        let mut subscriptions = Subscriptions::default();
+        let pong = text(
+            serde_json::json! {{ "jsonrpc": "2.0", "id": 1, "result": "pong" }},
+        );
+
+        assert_eq!(
+            subscriptions.forward_response(Backend::Chain, pong.clone()),
+            Some(pong)
+        );
+        assert_eq!(
+            subscriptions.take_unsubscribe_requests(&subscribe(1)),
+            None
+        );
+    }
+}
@@ -10,4 +10,9 @@ pub enum DirectorRpcError {
     JsonRpcClientError(#[from] jsonrpsee::core::client::Error),
     #[error("StdIoError")]
     StdIoError(#[from] std::io::Error),
+    #[error("SerdeJSONError")]
+    SerdeJSONError(#[from] serde_json::Error),
+
+    #[error("ConjuntoCoreError: {0}")]
+    ConjuntoCoreError(#[from] conjunto_core::errors::CoreError),
 }
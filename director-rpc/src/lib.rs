@@ -1,4 +1,3 @@
-mod decoders;
 pub mod errors;
 pub mod rpc;
 mod utils;
@@ -1,25 +1,50 @@
-use conjunto_transwise::endpoint::Endpoint;
+use conjunto_transwise::{
+    endpoint::{Endpoint, UnroutableReason},
+    endpoint_trace::EndpointTrace,
+    transaction_split::TransactionSplit,
+    wire_transaction::{deserialize_wire_transaction, WireEncoding},
+    AccountProvider, DelegationRecordParser,
+};
 use jsonrpsee::{
     core::{client::ClientT, RegisterMethodError, RpcResult},
     RpcModule,
 };
 use log::*;
+use serde::Serialize;
 use solana_rpc_client_api::config::RpcSendTransactionConfig;
 use solana_sdk::transaction::VersionedTransaction;
-use solana_transaction_status::UiTransactionEncoding;
+use solana_transaction_status::{
+    TransactionBinaryEncoding, UiTransactionEncoding,
+};
 
 use super::DirectorRpc;
 use crate::{
-    decoders::decode_and_deserialize,
     rpc::params::SendTransactionParams,
     utils::{
         invalid_params, server_error, server_error_with_data, ServerErrorCode,
     },
 };
 
-pub fn register_guide_methods(
-    module: &mut RpcModule<DirectorRpc>,
-) -> Result<(), RegisterMethodError> {
+/// Error data returned when a transaction cannot be routed, the error message
+/// holds the human readable version of the reason.
+/// The accounts are part of the trace which serializes pubkeys as strings
+/// like the reason does.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionUnroutableErrorData<'a> {
+    reason: &'a UnroutableReason,
+    trace: EndpointTrace,
+    /// How the transaction could be split into routable transactions
+    split: Option<TransactionSplit>,
+}
+
+pub fn register_guide_methods<T, U>(
+    module: &mut RpcModule<DirectorRpc<T, U>>,
+) -> Result<(), RegisterMethodError>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
     module.register_async_method(
         "sendTransaction",
         |params, rpc| async move {
@@ -31,53 +56,42 @@ pub fn register_guide_methods(
         },
     )?;
 
+    module.register_async_method(
+        "explainTransaction",
+        |params, rpc| async move {
+            debug!("explain_transaction rpc request received {:#?}", params);
+            let SendTransactionParams(data, config) =
+                params.parse::<SendTransactionParams>()?;
+
+            rpc.explain_transaction(data, config).await
+        },
+    )?;
+
     Ok(())
 }
 
-impl DirectorRpc {
+impl<T, U> DirectorRpc<T, U>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
     async fn send_transaction(
         &self,
         data: String,
         config: Option<RpcSendTransactionConfig>,
     ) -> RpcResult<String> {
         debug!("send_transaction rpc request received");
-        // 1. Deserialize Transaction
-        let RpcSendTransactionConfig {
-            skip_preflight: _,
-            preflight_commitment: _,
-            encoding,
-            max_retries: _,
-            min_context_slot: _,
-        } = config.unwrap_or_default();
-
-        let tx_encoding = encoding.unwrap_or(UiTransactionEncoding::Base58);
+        // 1. Determine Endpoint to be used for this Transaction
+        let (versioned_tx, endpoint) =
+            self.guide_transaction(&data, config).await?;
 
-        let binary_encoding = tx_encoding.into_binary_encoding().ok_or_else(|| {
-                invalid_params(format!(
-                    "unsupported encoding: {tx_encoding}. Supported encodings: base58, base64"
-                ))
-            })?;
-        let (_, versioned_tx) = decode_and_deserialize::<VersionedTransaction>(
-            &data,
-            binary_encoding,
-        )?;
-
-        // 2. Determine Endpoint to be used for this Transaction
-        let endpoint = match self
-            .transwise
-            .guide_versioned_transaction(&versioned_tx)
-            .await
-        {
-            Ok(endpoint) => endpoint,
-            Err(err) => {
-                return Err(server_error(
-                    format!("error: {err}"),
-                    ServerErrorCode::FailedToFetchEndpointInformation,
-                ));
-            }
-        };
-        // 3. Route transaction accordingly
+        // 2. Route transaction accordingly
         info!("endpoint: {:#?}", endpoint);
+        for warning in
+            endpoint.warnings_with_config(self.transwise.endpoint_config())
+        {
+            warn!("{}", warning);
+        }
         match &endpoint {
             Endpoint::Chain { .. } => Ok(self
                 .rpc_chain_client
@@ -90,8 +104,15 @@ impl DirectorRpc {
                         endpoint,
                     )
                 })?),
-            Endpoint::Ephemeral { .. } => Ok(self
-                .rpc_ephem_client
+            Endpoint::Ephemeral { authority, .. } => Ok(self
+                .rpc_ephem_client_for(authority)
+                .ok_or_else(|| {
+                    server_error_with_data(
+                        format!("No ephemeral validator registered for authority {authority}"),
+                        ServerErrorCode::UnknownValidator,
+                        endpoint.trace_with_config(self.transwise.endpoint_config()),
+                    )
+                })?
                 .request("sendTransaction", SendTransactionParams(data, config))
                 .await
                 .map_err(|err| {
@@ -101,11 +122,339 @@ impl DirectorRpc {
                         endpoint,
                     )
                 })?),
-            Endpoint::Unroutable { .. } => Err(server_error_with_data(
-                "Transaction is unroutable".to_string(),
-                ServerErrorCode::TransactionUnroutable,
-                endpoint,
-            )),
+            Endpoint::Unroutable { reason, .. } => {
+                let split = TransactionSplit::try_from_versioned_transaction(
+                    &versioned_tx,
+                    &endpoint,
+                )
+                .unwrap_or_else(|err| {
+                    // The split is only a hint, the reason still needs to
+                    // reach the client
+                    warn!("Failed to split unroutable transaction: {err}");
+                    None
+                });
+                Err(server_error_with_data(
+                    format!("Transaction is unroutable: {reason}"),
+                    ServerErrorCode::TransactionUnroutable,
+                    TransactionUnroutableErrorData {
+                        reason,
+                        trace: endpoint.trace_with_config(self.transwise.endpoint_config()),
+                        split,
+                    },
+                ))
+            }
         }
     }
+
+    async fn explain_transaction(
+        &self,
+        data: String,
+        config: Option<RpcSendTransactionConfig>,
+    ) -> RpcResult<EndpointTrace> {
+        debug!("explain_transaction rpc request received");
+        let (_, endpoint) = self.guide_transaction(&data, config).await?;
+        Ok(endpoint.trace_with_config(self.transwise.endpoint_config()))
+    }
+
+    /// Deserializes the transaction and determines the endpoint it should be
+    /// sent to
+    async fn guide_transaction(
+        &self,
+        data: &str,
+        config: Option<RpcSendTransactionConfig>,
+    ) -> RpcResult<(VersionedTransaction, Endpoint)> {
+        // 1. Deserialize Transaction
+        let RpcSendTransactionConfig {
+            skip_preflight: _,
+            preflight_commitment: _,
+            encoding,
+            max_retries: _,
+            min_context_slot: _,
+        } = config.unwrap_or_default();
+
+        let tx_encoding = encoding.unwrap_or(UiTransactionEncoding::Base58);
+
+        let wire_encoding = match tx_encoding.into_binary_encoding() {
+            Some(TransactionBinaryEncoding::Base58) => WireEncoding::Base58,
+            Some(TransactionBinaryEncoding::Base64) => WireEncoding::Base64,
+            None => {
+                return Err(invalid_params(format!(
+                    "unsupported encoding: {tx_encoding}. Supported encodings: base58, base64"
+                )))
+            }
+        };
+        let versioned_tx =
+            deserialize_wire_transaction(data.as_bytes(), wire_encoding)
+                .map_err(|err| invalid_params(err.to_string()))?;
+
+        // 2. Determine Endpoint to be used for this Transaction
+        let endpoint = self
+            .transwise
+            .guide_versioned_transaction(&versioned_tx)
+            .await
+            .map_err(|err| {
+                server_error(
+                    format!("error: {err}"),
+                    ServerErrorCode::FailedToFetchEndpointInformation,
+                )
+            })?;
+        Ok((versioned_tx, endpoint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conjunto_addresses::cluster::RpcCluster;
+    use conjunto_providers::{
+        rpc_provider_config::RpcProviderConfig,
+        validator_registry::ValidatorRegistry,
+    };
+    use conjunto_test_tools::{
+        account_provider_stub::AccountProviderStub,
+        accounts::{
+            account_owned_by_delegation_program, account_with_data,
+            delegated_account_ids, program_account,
+        },
+        delegation_record_parser_stub::DelegationRecordParserStub,
+    };
+    use conjunto_transwise::{
+        endpoint_trace::{AccountInfluence, EndpointKind},
+        transwise::Transwise,
+        CommitFrequency, DelegationRecord,
+    };
+    use jsonrpsee::core::server::MethodsError;
+    use serde_json::Value;
+    use solana_sdk::{
+        account::Account,
+        bpf_loader,
+        hash::Hash,
+        instruction::{AccountMeta, Instruction},
+        message::Message,
+        pubkey::Pubkey,
+        signature::Keypair,
+        system_instruction, system_transaction,
+        transaction::Transaction,
+    };
+
+    use super::*;
+    use crate::rpc::{create_rpc_module_with_transwise, DirectorConfig};
+
+    fn rpc_module_with_accounts(
+        accounts: Vec<(Pubkey, Account)>,
+    ) -> RpcModule<DirectorRpc<AccountProviderStub, DelegationRecordParserStub>>
+    {
+        rpc_module_with_delegated_accounts(accounts, None)
+    }
+
+    fn rpc_module_with_delegated_accounts(
+        accounts: Vec<(Pubkey, Account)>,
+        delegation_record: Option<DelegationRecord>,
+    ) -> RpcModule<DirectorRpc<AccountProviderStub, DelegationRecordParserStub>>
+    {
+        rpc_module_with_config(
+            DirectorConfig::devnet(),
+            accounts,
+            delegation_record,
+        )
+    }
+
+    fn rpc_module_with_config(
+        config: DirectorConfig,
+        accounts: Vec<(Pubkey, Account)>,
+        delegation_record: Option<DelegationRecord>,
+    ) -> RpcModule<DirectorRpc<AccountProviderStub, DelegationRecordParserStub>>
+    {
+        let mut account_provider = AccountProviderStub::default();
+        for (pubkey, account) in accounts {
+            account_provider.add(pubkey, account);
+        }
+        let transwise = Transwise::with_providers(
+            account_provider,
+            DelegationRecordParserStub::new(delegation_record),
+        );
+        create_rpc_module_with_transwise(config, transwise).unwrap()
+    }
+
+    fn encoded_transfer(to: &Pubkey) -> String {
+        let tx = system_transaction::transfer(
+            &Keypair::new(),
+            to,
+            42,
+            Hash::default(),
+        );
+        bs58::encode(bincode::serialize(&tx).unwrap()).into_string()
+    }
+
+    #[tokio::test]
+    async fn test_explain_transaction_to_undelegated_account() {
+        let to = Pubkey::new_unique();
+        let module = rpc_module_with_accounts(vec![(to, account_with_data())]);
+
+        let trace: EndpointTrace = module
+            .call(
+                "explainTransaction",
+                SendTransactionParams(encoded_transfer(&to), None),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(trace.endpoint, EndpointKind::Chain);
+        let to_trace = trace
+            .accounts
+            .iter()
+            .find(|account| account.pubkey == to)
+            .unwrap();
+        assert_eq!(to_trace.influence, AccountInfluence::RequiresChain);
+    }
+
+    #[tokio::test]
+    async fn test_send_unroutable_transaction() {
+        let to = Pubkey::new_unique();
+        let program = Account {
+            owner: bpf_loader::id(),
+            data: vec![1, 2, 3],
+            ..program_account()
+        };
+        let module = rpc_module_with_accounts(vec![(to, program)]);
+
+        let result = module
+            .call::<_, String>(
+                "sendTransaction",
+                SendTransactionParams(encoded_transfer(&to), None),
+            )
+            .await;
+
+        let err = match result {
+            Err(MethodsError::JsonRpc(err)) => err,
+            _ => panic!("expected a JSON RPC error, got {:?}", result),
+        };
+        assert_eq!(err.code(), ServerErrorCode::TransactionUnroutable as i32);
+
+        let data: Value =
+            serde_json::from_str(err.data().unwrap().get()).unwrap();
+        assert_eq!(
+            data["reason"]["ContainsProgramAccountsAsWritable"]
+                ["writableProgramPubkeys"],
+            serde_json::json!([to.to_string()])
+        );
+        assert_eq!(data["trace"]["endpoint"], "unroutable");
+    }
+
+    #[tokio::test]
+    async fn test_send_transaction_writing_undelegated_and_delegated() {
+        let payer = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let (delegated, delegation_record) = delegated_account_ids();
+        let module = rpc_module_with_delegated_accounts(
+            vec![
+                (to, account_with_data()),
+                (delegated, account_owned_by_delegation_program()),
+                (delegation_record, account_owned_by_delegation_program()),
+            ],
+            Some(DelegationRecord {
+                authority: Pubkey::new_unique(),
+                owner: Pubkey::new_unique(),
+                delegation_slot: 0,
+                commit_frequency: CommitFrequency::Millis(1_000),
+            }),
+        );
+        let tx = Transaction::new_unsigned(Message::new(
+            &[
+                system_instruction::transfer(&payer, &to, 42),
+                Instruction::new_with_bytes(
+                    Pubkey::new_unique(),
+                    &[],
+                    vec![AccountMeta::new(delegated, false)],
+                ),
+            ],
+            Some(&payer),
+        ));
+        let data = bs58::encode(bincode::serialize(&tx).unwrap()).into_string();
+
+        let result = module
+            .call::<_, String>(
+                "sendTransaction",
+                SendTransactionParams(data, None),
+            )
+            .await;
+
+        let err = match result {
+            Err(MethodsError::JsonRpc(err)) => err,
+            _ => panic!("expected a JSON RPC error, got {:?}", result),
+        };
+        let data: Value =
+            serde_json::from_str(err.data().unwrap().get()).unwrap();
+        assert_eq!(
+            data["split"]["chain"]["instructionIndexes"],
+            serde_json::json!([0])
+        );
+        assert_eq!(
+            data["split"]["ephemeral"]["instructionIndexes"],
+            serde_json::json!([1])
+        );
+        assert_eq!(
+            data["split"]["ephemeral"]["writable"],
+            serde_json::json!([payer.to_string(), delegated.to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_transaction_delegated_to_unknown_validator() {
+        let payer = Pubkey::new_unique();
+        let (delegated, delegation_record) = delegated_account_ids();
+        let registered_validator = || {
+            RpcProviderConfig::new(
+                RpcCluster::Custom(
+                    "http://localhost:7799".to_string(),
+                    "ws://localhost:7800".to_string(),
+                ),
+                None,
+            )
+        };
+        let config = DirectorConfig {
+            ephem_authority: Some(Pubkey::new_unique()),
+            validator_registry: ValidatorRegistry::new()
+                .with_validator(Pubkey::new_unique(), registered_validator()),
+            ..DirectorConfig::devnet()
+        };
+        let unknown_authority = Pubkey::new_unique();
+        let module = rpc_module_with_config(
+            config,
+            vec![
+                (delegated, account_owned_by_delegation_program()),
+                (delegation_record, account_owned_by_delegation_program()),
+            ],
+            Some(DelegationRecord {
+                authority: unknown_authority,
+                owner: Pubkey::new_unique(),
+                delegation_slot: 0,
+                commit_frequency: CommitFrequency::Millis(1_000),
+            }),
+        );
+        let tx = Transaction::new_unsigned(Message::new(
+            &[Instruction::new_with_bytes(
+                Pubkey::new_unique(),
+                &[],
+                vec![AccountMeta::new(delegated, false)],
+            )],
+            Some(&payer),
+        ));
+        let data = bs58::encode(bincode::serialize(&tx).unwrap()).into_string();
+
+        let result = module
+            .call::<_, String>(
+                "sendTransaction",
+                SendTransactionParams(data, None),
+            )
+            .await;
+
+        // The transaction must not end up on a validator the account isn't
+        // delegated to
+        let err = match result {
+            Err(MethodsError::JsonRpc(err)) => err,
+            _ => panic!("expected a JSON RPC error, got {:?}", result),
+        };
+        assert_eq!(err.code(), ServerErrorCode::UnknownValidator as i32);
+        assert!(err.message().contains(&unknown_authority.to_string()));
+    }
 }
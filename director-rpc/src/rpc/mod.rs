@@ -1,23 +1,51 @@
+use std::{collections::HashMap, time::Duration};
+
 use conjunto_addresses::cluster::RpcCluster;
-use conjunto_providers::rpc_provider_config::RpcProviderConfig;
-use conjunto_transwise::transwise::Transwise;
+use conjunto_lockbox::delegation_record_parser_impl::DelegationRecordParserImpl;
+use conjunto_providers::{
+    rpc_account_provider::RpcAccountProvider,
+    rpc_provider_config::RpcProviderConfig,
+    validator_registry::ValidatorRegistry,
+};
+use conjunto_transwise::{
+    endpoint::EndpointConfig, transwise::Transwise, AccountProvider,
+    DelegationRecordParser,
+};
 use jsonrpsee::{
     http_client::{HttpClient, HttpClientBuilder},
     RpcModule,
 };
+use solana_sdk::pubkey::Pubkey;
 
 use self::{
     guide::register_guide_methods, passthrough::register_passthrough_methods,
+    routing_rules::RoutingRules,
 };
 use crate::errors::DirectorRpcResult;
 
 pub mod guide;
 mod params;
 pub mod passthrough;
+pub mod routing_rules;
 
 pub struct DirectorConfig {
+    /// Default ephemeral validator, used to fetch account states
     pub ephem_rpc_provider_config: RpcProviderConfig,
+    /// Authority of the default ephemeral validator, transactions for it are
+    /// sent there. If not set it receives all ephemeral transactions as long
+    /// as the validator registry is empty
+    pub ephem_authority: Option<Pubkey>,
     pub chain_cluster: RpcCluster,
+    pub validator_registry: ValidatorRegistry,
+    /// How long the endpoint of a transaction is reused when clients retry
+    /// sending it, no caching if `None`
+    pub endpoint_cache_ttl: Option<Duration>,
+    /// How transactions are routed, i.e. the programs that only run on chain
+    /// and the policy for chain transactions reading delegated accounts
+    pub endpoint_config: EndpointConfig,
+    /// Override the built-in endpoints of the forwarded methods, validated
+    /// when the module is created
+    pub routing_rules: RoutingRules,
 }
 
 impl DirectorConfig {
@@ -25,30 +53,105 @@ impl DirectorConfig {
         Self {
             chain_cluster: RpcCluster::Devnet,
             ephem_rpc_provider_config: RpcProviderConfig::magicblock_devnet(),
+            ephem_authority: None,
+            validator_registry: ValidatorRegistry::default(),
+            endpoint_cache_ttl: Some(Duration::from_secs(2)),
+            endpoint_config: EndpointConfig::default(),
+            routing_rules: RoutingRules::default(),
         }
     }
 }
 
-pub struct DirectorRpc {
-    pub(super) transwise: Transwise,
+pub struct DirectorRpc<T, U>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
+    pub(super) transwise: Transwise<T, U>,
     pub(super) rpc_chain_client: HttpClient,
     pub(super) rpc_ephem_client: HttpClient,
+    pub(super) ephem_authority: Option<Pubkey>,
+    pub(super) rpc_ephem_clients_by_authority: HashMap<Pubkey, HttpClient>,
+    pub(super) routing_rules: RoutingRules,
+}
+
+impl<T, U> DirectorRpc<T, U>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
+    /// The client of the validator with the given authority, `None` if we
+    /// don't know that validator
+    pub(super) fn rpc_ephem_client_for(
+        &self,
+        authority: &Pubkey,
+    ) -> Option<&HttpClient> {
+        if let Some(client) = self.rpc_ephem_clients_by_authority.get(authority)
+        {
+            return Some(client);
+        }
+        match &self.ephem_authority {
+            Some(ephem_authority) if ephem_authority == authority => {
+                Some(&self.rpc_ephem_client)
+            }
+            // Without any authorities configured the default validator is
+            // the only one we can send to
+            None if self.rpc_ephem_clients_by_authority.is_empty() => {
+                Some(&self.rpc_ephem_client)
+            }
+            _ => None,
+        }
+    }
 }
 
 pub fn create_rpc_module(
     config: DirectorConfig,
-) -> DirectorRpcResult<RpcModule<DirectorRpc>> {
+) -> DirectorRpcResult<
+    RpcModule<DirectorRpc<RpcAccountProvider, DelegationRecordParserImpl>>,
+> {
+    let mut transwise =
+        Transwise::new(config.ephem_rpc_provider_config.clone())
+            .with_endpoint_config(config.endpoint_config.clone());
+    if let Some(ttl) = config.endpoint_cache_ttl {
+        transwise = transwise.with_endpoint_cache_ttl(ttl);
+    }
+    create_rpc_module_with_transwise(config, transwise)
+}
+
+/// Creates the RPC module with a custom [Transwise], i.e. to use different
+/// account providers than the RPC of the ephemeral validator. The endpoint
+/// config and cache of the provided [Transwise] are used as is.
+pub fn create_rpc_module_with_transwise<T, U>(
+    config: DirectorConfig,
+    transwise: Transwise<T, U>,
+) -> DirectorRpcResult<RpcModule<DirectorRpc<T, U>>>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
+    config.routing_rules.validate()?;
     let ephem_url = config.ephem_rpc_provider_config.url().to_string();
-    let transwise = Transwise::new(config.ephem_rpc_provider_config);
 
     let rpc_ephem_client = HttpClientBuilder::default().build(ephem_url)?;
     let rpc_chain_client =
         HttpClientBuilder::default().build(config.chain_cluster.url())?;
+    let rpc_ephem_clients_by_authority = config
+        .validator_registry
+        .iter()
+        .map(|(authority, provider_config)| {
+            HttpClientBuilder::default()
+                .build(provider_config.url())
+                .map(|client| (*authority, client))
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
 
     let director = DirectorRpc {
         transwise,
         rpc_ephem_client,
         rpc_chain_client,
+        ephem_authority: config.ephem_authority,
+        rpc_ephem_clients_by_authority,
+        routing_rules: config.routing_rules,
     };
 
     let mut module = RpcModule::new(director);
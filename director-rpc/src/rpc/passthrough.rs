@@ -1,10 +1,14 @@
+use conjunto_core::RequestEndpoint;
+use conjunto_transwise::{AccountProvider, DelegationRecordParser};
 use jsonrpsee::{
     core::{client::ClientT, ClientError, RegisterMethodError},
+    http_client::HttpClient,
     types::{ErrorObjectOwned, Params},
     RpcModule,
 };
 use log::*;
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 use solana_account_decoder::{parse_token::UiTokenAmount, UiAccount};
 use solana_rpc_client_api::response::{
     OptionalContext, Response as RpcResponse, RpcAccountBalance,
@@ -38,11 +42,16 @@ type BlockCommitmentArray = [u64; MAX_LOCKOUT_HISTORY + 1];
 // -----------------
 // register_passthrough_methods
 // -----------------
-async fn passthrough_impl<'a, R: DeserializeOwned>(
-    method: &'a str,
+async fn passthrough_impl<R, T, U>(
+    method: &str,
     params: Params<'static>,
-    rpc: &DirectorRpc,
-) -> Result<R, ErrorObjectOwned> {
+    rpc: &DirectorRpc<T, U>,
+) -> Result<R, ErrorObjectOwned>
+where
+    R: DeserializeOwned,
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
     let params = RawParams(params);
     match rpc
         .rpc_chain_client
@@ -61,9 +70,118 @@ async fn passthrough_impl<'a, R: DeserializeOwned>(
     }
 }
 
-pub fn register_passthrough_methods(
-    module: &mut RpcModule<DirectorRpc>,
-) -> Result<(), RegisterMethodError> {
+/// Forwards the request to the endpoint of the first routing rule matching
+/// it, otherwise to the built-in endpoint of the method
+async fn routed_impl<R, T, U>(
+    method: &str,
+    params: Params<'static>,
+    rpc: &DirectorRpc<T, U>,
+    built_in: RequestEndpoint,
+) -> Result<R, ErrorObjectOwned>
+where
+    R: DeserializeOwned,
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
+    let first_param = first_param(&params);
+    let endpoint = rpc
+        .routing_rules
+        .route_of(method, first_param.as_deref().as_slice())
+        .and_then(|route| route.endpoint())
+        .unwrap_or(built_in);
+    forward_impl(method, params, rpc, &endpoint).await
+}
+
+/// The first positional param if it is a string, i.e. an address
+fn first_param(params: &Params) -> Option<String> {
+    let params = serde_json::from_str::<Value>(params.as_str()?).ok()?;
+    params.get(0)?.as_str().map(String::from)
+}
+
+/// Forwards the request to the endpoint.
+/// Sequential endpoints only ask the second backend if the first one fails or
+/// has no result, [RequestEndpoint::Both] is answered by chain since we can
+/// only return one response.
+async fn forward_impl<R, T, U>(
+    method: &str,
+    params: Params<'static>,
+    rpc: &DirectorRpc<T, U>,
+    endpoint: &RequestEndpoint,
+) -> Result<R, ErrorObjectOwned>
+where
+    R: DeserializeOwned,
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
+    use RequestEndpoint::*;
+    let (first, second) = match endpoint {
+        Chain | Both => return passthrough_impl(method, params, rpc).await,
+        Ephemeral => ((&rpc.rpc_ephem_client, "ephemeral"), None),
+        EphemeralThenChain => (
+            (&rpc.rpc_ephem_client, "ephemeral"),
+            Some((&rpc.rpc_chain_client, "on-chain")),
+        ),
+        ChainThenEphemeral => (
+            (&rpc.rpc_chain_client, "on-chain"),
+            Some((&rpc.rpc_ephem_client, "ephemeral")),
+        ),
+    };
+    let mut result = request_value(first.0, method, params.clone()).await;
+    let mut backend = first.1;
+    if let Some((client, second_backend)) = second {
+        if is_empty_result(&result) {
+            debug!(
+                "{} has no result for {}, asking {}",
+                first.1, method, second_backend
+            );
+            result = request_value(client, method, params).await;
+            backend = second_backend;
+        }
+    }
+    match result {
+        Ok(value) => serde_json::from_value(value).map_err(|err| {
+            server_error(
+                format!("Failed to parse response of {backend} RPC: {err:?}"),
+                ServerErrorCode::RpcClientError,
+            )
+        }),
+        // Pass RPC JSON errors through directly
+        Err(ClientError::Call(err)) => Err(err),
+        Err(err) => Err(server_error(
+            format!("Failed to forward to {backend} RPC: {err:?}"),
+            ServerErrorCode::RpcClientError,
+        )),
+    }
+}
+
+async fn request_value(
+    client: &HttpClient,
+    method: &str,
+    params: Params<'static>,
+) -> Result<Value, ClientError> {
+    client
+        .request::<Value, RawParams>(method, RawParams(params))
+        .await
+}
+
+/// True if the backend failed or has nothing to answer with, i.e. it returned
+/// `null` or a response with a `null` value for an unknown account
+fn is_empty_result(result: &Result<Value, ClientError>) -> bool {
+    match result {
+        Ok(value) => {
+            value.is_null() || value.get("value").is_some_and(Value::is_null)
+        }
+        Err(_) => true,
+    }
+}
+
+pub fn register_passthrough_methods<T, U>(
+    module: &mut RpcModule<DirectorRpc<T, U>>,
+) -> Result<(), RegisterMethodError>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Send + Sync + 'static,
+{
     macro_rules! passthrough {
         ($method:literal, $return_type:ty) => {
             module.register_async_method(
@@ -71,8 +189,29 @@ pub fn register_passthrough_methods(
                 |params, rpc| async move {
                     debug!("{}", $method);
                     trace!("{:#?}", params);
-                    passthrough_impl::<$return_type>($method, params, &rpc)
-                        .await
+                    routed_impl::<$return_type, T, U>(
+                        $method,
+                        params,
+                        &rpc,
+                        RequestEndpoint::Chain,
+                    )
+                    .await
+                },
+            )?;
+        };
+    }
+
+    macro_rules! guided {
+        ($method:literal, $return_type:ty, $endpoint:expr) => {
+            module.register_async_method(
+                $method,
+                |params, rpc| async move {
+                    debug!("{}", $method);
+                    trace!("{:#?}", params);
+                    routed_impl::<$return_type, T, U>(
+                        $method, params, &rpc, $endpoint,
+                    )
+                    .await
                 },
             )?;
         };
@@ -96,8 +235,11 @@ pub fn register_passthrough_methods(
     // - Both:  for requests that return an array of results first fill from ephem and try the
     //          remaining ones from chain
 
-    // TODO: guide TryEphem
-    passthrough!("getAccountInfo", RpcResponse<Option<UiAccount>>);
+    guided!(
+        "getAccountInfo",
+        RpcResponse<Option<UiAccount>>,
+        RequestEndpoint::EphemeralThenChain
+    );
     // TODO: guide TryEphem
     passthrough!("getBalance", RpcResponse<u64>);
     passthrough!("getBlock", Option<UiConfirmedBlock>);
@@ -187,3 +329,193 @@ pub fn register_passthrough_methods(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use conjunto_addresses::cluster::RpcCluster;
+    use conjunto_providers::rpc_provider_config::RpcProviderConfig;
+    use conjunto_test_tools::{
+        account_provider_stub::AccountProviderStub,
+        delegation_record_parser_stub::DelegationRecordParserStub,
+    };
+    use conjunto_transwise::transwise::Transwise;
+    use jsonrpsee::{
+        core::params::ArrayParams,
+        server::{Server, ServerHandle},
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::rpc::{
+        create_rpc_module_with_transwise,
+        routing_rules::{Route, RoutingRule, RoutingRules, ROUTABLE_METHODS},
+        DirectorConfig,
+    };
+
+    /// Backend answering `getAccountInfo` with the given value and counting
+    /// the requests it receives
+    async fn start_backend(
+        value: Value,
+    ) -> (RpcCluster, Arc<AtomicUsize>, ServerHandle) {
+        let server = Server::builder()
+            .http_only()
+            .build("127.0.0.1:0".parse::<SocketAddr>().unwrap())
+            .await
+            .unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+        let request_count = Arc::new(AtomicUsize::new(0));
+        let mut module = RpcModule::new((value, request_count.clone()));
+        module
+            .register_async_method(
+                "getAccountInfo",
+                |_params, backend| async move {
+                    backend.1.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, ErrorObjectOwned>(serde_json::json!({
+                        "context": { "slot": 1 },
+                        "value": backend.0,
+                    }))
+                },
+            )
+            .unwrap();
+        let handle = server.start(module);
+        (
+            RpcCluster::Custom(url, "ws://127.0.0.1:0".to_string()),
+            request_count,
+            handle,
+        )
+    }
+
+    fn account_value(lamports: u64) -> Value {
+        serde_json::json!({
+            "lamports": lamports,
+            "data": ["", "base64"],
+            "owner": Pubkey::default().to_string(),
+            "executable": false,
+            "rentEpoch": 0,
+            "space": 0,
+        })
+    }
+
+    fn create_module(
+        config: DirectorConfig,
+    ) -> RpcModule<DirectorRpc<AccountProviderStub, DelegationRecordParserStub>>
+    {
+        create_rpc_module_with_transwise(
+            config,
+            Transwise::with_providers(
+                AccountProviderStub::default(),
+                DelegationRecordParserStub::default(),
+            ),
+        )
+        .unwrap()
+    }
+
+    async fn get_account_info(
+        ephem_value: Value,
+        chain_value: Value,
+        routing_rules: RoutingRules,
+    ) -> (Option<UiAccount>, usize, usize) {
+        let (ephem_cluster, ephem_requests, _ephem_handle) =
+            start_backend(ephem_value).await;
+        let (chain_cluster, chain_requests, _chain_handle) =
+            start_backend(chain_value).await;
+        let config = DirectorConfig {
+            ephem_rpc_provider_config: RpcProviderConfig::new(
+                ephem_cluster,
+                None,
+            ),
+            chain_cluster,
+            routing_rules,
+            ..DirectorConfig::devnet()
+        };
+        let module = create_module(config);
+
+        let mut params = ArrayParams::new();
+        params.insert(Pubkey::new_unique().to_string()).unwrap();
+        let response: RpcResponse<Option<UiAccount>> =
+            module.call("getAccountInfo", params).await.unwrap();
+        (
+            response.value,
+            ephem_requests.load(Ordering::SeqCst),
+            chain_requests.load(Ordering::SeqCst),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_get_account_info_found_in_ephemeral() {
+        let (account, ephem_requests, chain_requests) = get_account_info(
+            account_value(1),
+            account_value(2),
+            RoutingRules::default(),
+        )
+        .await;
+        assert_eq!(account.unwrap().lamports, 1);
+        assert_eq!((ephem_requests, chain_requests), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_get_account_info_falls_back_to_chain() {
+        let (account, ephem_requests, chain_requests) = get_account_info(
+            Value::Null,
+            account_value(2),
+            RoutingRules::default(),
+        )
+        .await;
+        assert_eq!(account.unwrap().lamports, 2);
+        assert_eq!((ephem_requests, chain_requests), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_get_account_info_routed_by_rule() {
+        let routing_rules = RoutingRules {
+            rules: vec![RoutingRule {
+                method: "getAccountInfo".into(),
+                param: None,
+                route: Route::Chain,
+            }],
+        };
+        let (account, ephem_requests, chain_requests) =
+            get_account_info(account_value(1), account_value(2), routing_rules)
+                .await;
+        assert_eq!(account.unwrap().lamports, 2);
+        assert_eq!((ephem_requests, chain_requests), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_routable_methods_are_forwarded() {
+        let module = create_module(DirectorConfig::devnet());
+        let mut forwarded = module
+            .method_names()
+            .filter(|method| {
+                !["sendTransaction", "explainTransaction"].contains(method)
+            })
+            .collect::<Vec<_>>();
+        forwarded.sort_unstable();
+        let mut routable = ROUTABLE_METHODS.to_vec();
+        routable.sort_unstable();
+        assert_eq!(forwarded, routable);
+    }
+
+    #[test]
+    fn test_is_empty_result() {
+        assert!(is_empty_result(&Ok(Value::Null)));
+        assert!(is_empty_result(&Ok(serde_json::json!({
+            "context": { "slot": 1 },
+            "value": null,
+        }))));
+        assert!(is_empty_result(&Err(ClientError::RequestTimeout)));
+        assert!(!is_empty_result(&Ok(serde_json::json!(0))));
+        assert!(!is_empty_result(&Ok(serde_json::json!({
+            "context": { "slot": 1 },
+            "value": [null],
+        }))));
+    }
+}
@@ -0,0 +1,251 @@
+use std::str::FromStr;
+
+use conjunto_core::routing_rules::RoutableMethod;
+pub use conjunto_core::routing_rules::Route;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// Methods that are forwarded to a backend and can thus be routed, the
+/// transaction methods are always guided by `Transwise`
+pub const ROUTABLE_METHODS: &[&str] = &[
+    "getAccountInfo",
+    "getBalance",
+    "getBlock",
+    "getBlockCommitment",
+    "getBlockHeight",
+    "getBlockProduction",
+    "getBlockTime",
+    "getBlocks",
+    "getBlocksWithLimit",
+    "getClusterNodes",
+    "getEpochInfo",
+    "getEpochSchedule",
+    "getFeeForMessage",
+    "getFirstAvailableBlock",
+    "getGenesisHash",
+    "getHealth",
+    "getHighestSnapshotSlot",
+    "getIdentity",
+    "getInflationGovernor",
+    "getInflationRate",
+    "getInflationReward",
+    "getLargestAccounts",
+    "getLatestBlockhash",
+    "getLeaderSchedule",
+    "getMaxRetransmitSlot",
+    "getMaxShredInsertSlot",
+    "getMinimumBalanceForRentExemption",
+    "getMultipleAccounts",
+    "getProgramAccounts",
+    "getRecentPerformanceSamples",
+    "getRecentPrioritizationFees",
+    "getSignatureStatuses",
+    "getSignaturesForAddress",
+    "getSlot",
+    "getSlotLeader",
+    "getSlotLeaders",
+    "getStakeActivation",
+    "getStakeMinimumDelegation",
+    "getSupply",
+    "getTokenAccountBalance",
+    "getTokenAccountsByDelegate",
+    "getTokenAccountsByOwner",
+    "getTokenLargestAccounts",
+    "getTokenSupply",
+    "getTransactionCount",
+    "getVersion",
+    "getVoteAccounts",
+    "isBlockhashValid",
+    "minimumLedgerSlot",
+    "requestAirdrop",
+    "simulateTransaction",
+];
+
+/// Methods whose first param is an address a rule can match
+const ADDRESS_PARAM_METHODS: &[&str] = &[
+    "getAccountInfo",
+    "getBalance",
+    "getProgramAccounts",
+    "getSignaturesForAddress",
+    "getStakeActivation",
+    "getTokenAccountBalance",
+    "getTokenAccountsByDelegate",
+    "getTokenAccountsByOwner",
+    "getTokenLargestAccounts",
+    "getTokenSupply",
+];
+
+/// A forwarded RPC method, i.e. `getAccountInfo`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct RpcMethod(pub String);
+
+impl From<&str> for RpcMethod {
+    fn from(method: &str) -> Self {
+        Self(method.to_string())
+    }
+}
+
+impl PartialEq<str> for RpcMethod {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl RoutableMethod for RpcMethod {
+    /// Only forwarded methods can be routed, params are only matched against
+    /// the address of methods taking one and there is no route to both since
+    /// only one response can be returned
+    fn validate_rule(
+        &self,
+        param: Option<&str>,
+        route: Route,
+    ) -> Result<(), String> {
+        let method = self.0.as_str();
+        if !ROUTABLE_METHODS.contains(&method) {
+            return Err(format!("{method} cannot be routed"));
+        }
+        if route == Route::Both {
+            return Err(format!(
+                "{method} cannot be routed to both since only one response can be returned"
+            ));
+        }
+        let param = match param {
+            Some(param) => param,
+            None => return Ok(()),
+        };
+        if !ADDRESS_PARAM_METHODS.contains(&method) {
+            return Err(format!("{method} does not take an address param"));
+        }
+        Pubkey::from_str(param)
+            .map(|_| ())
+            .map_err(|err| format!("invalid address {param}: {err}"))
+    }
+}
+
+/// Overrides the built-in endpoints of RPC methods, the first rule matching
+/// a request decides where it goes.
+/// Loaded from JSON, i.e. `{ "rules": [{ "method": "getSlot",
+/// "route": "ephemeral" }] }` or the equivalent TOML.
+pub type RoutingRules = conjunto_core::routing_rules::RoutingRules<RpcMethod>;
+pub type RoutingRule = conjunto_core::routing_rules::RoutingRule<RpcMethod>;
+
+#[cfg(test)]
+mod tests {
+    use conjunto_core::errors::CoreError;
+
+    use super::*;
+
+    const ADDRESS: &str = "SoLXmnP9JvL6vJ7TN1VqtTxqsc2izmPfF9CsMDEuRzJ";
+
+    #[test]
+    fn test_parse_json_and_toml_rules() {
+        let json = RoutingRules::from_json_str(&format!(
+            r#"{{
+                "rules": [
+                    {{ "method": "getAccountInfo", "param": "{ADDRESS}", "route": "chain" }},
+                    {{ "method": "getSlot", "route": "ephemeral" }}
+                ]
+            }}"#
+        ))
+        .unwrap();
+        let toml = RoutingRules::from_toml_str(&format!(
+            r#"
+            [[rules]]
+            method = "getAccountInfo"
+            param = "{ADDRESS}"
+            route = "chain"
+
+            [[rules]]
+            method = "getSlot"
+            route = "ephemeral"
+            "#
+        ))
+        .unwrap();
+
+        assert_eq!(json, toml);
+        assert_eq!(
+            json.rules[0],
+            RoutingRule {
+                method: "getAccountInfo".into(),
+                param: Some(ADDRESS.to_string()),
+                route: Route::Chain,
+            }
+        );
+    }
+
+    #[test]
+    fn test_first_matching_rule_decides() {
+        let routing_rules = RoutingRules {
+            rules: vec![
+                RoutingRule {
+                    method: "getBalance".into(),
+                    param: Some(ADDRESS.to_string()),
+                    route: Route::BuiltIn,
+                },
+                RoutingRule {
+                    method: "getBalance".into(),
+                    param: None,
+                    route: Route::EphemeralThenChain,
+                },
+            ],
+        };
+
+        assert_eq!(
+            routing_rules.route_of("getBalance", &[ADDRESS]),
+            Some(Route::BuiltIn)
+        );
+        assert_eq!(
+            routing_rules.route_of(
+                "getBalance",
+                &[Pubkey::new_unique().to_string().as_str()]
+            ),
+            Some(Route::EphemeralThenChain)
+        );
+        assert_eq!(routing_rules.route_of("getSlot", &[]), None);
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let assert_invalid = |json: &str, expected_idx: usize| {
+            match RoutingRules::from_json_str(json) {
+                Err(CoreError::InvalidRoutingRule { idx, .. }) => {
+                    assert_eq!(idx, expected_idx)
+                }
+                result => panic!("unexpected result {:?}", result),
+            }
+        };
+
+        // Transactions are always guided by their accounts
+        assert_invalid(
+            r#"{ "rules": [{ "method": "sendTransaction", "route": "chain" }] }"#,
+            0,
+        );
+        // Params need to be valid addresses of methods taking one
+        assert_invalid(
+            r#"{ "rules": [{ "method": "getAccountInfo", "param": "<not a pubkey>", "route": "chain" }] }"#,
+            0,
+        );
+        assert_invalid(
+            &format!(
+                r#"{{ "rules": [{{ "method": "getSlot", "param": "{ADDRESS}", "route": "chain" }}] }}"#
+            ),
+            0,
+        );
+        // Rules after a rule for the whole method are never used
+        assert_invalid(
+            &format!(
+                r#"{{ "rules": [
+                    {{ "method": "getAccountInfo", "route": "chain" }},
+                    {{ "method": "getAccountInfo", "param": "{ADDRESS}", "route": "ephemeral" }}
+                ] }}"#
+            ),
+            1,
+        );
+        // Only one response can be returned, so there is no route to both
+        assert_invalid(
+            r#"{ "rules": [{ "method": "getSlot", "route": "both" }] }"#,
+            0,
+        );
+    }
+}
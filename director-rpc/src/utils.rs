@@ -10,6 +10,7 @@ pub enum ServerErrorCode {
     FailedToFetchEndpointInformation = 0,
     TransactionUnroutable = 1,
     RpcClientError = 2,
+    UnknownValidator = 3,
 }
 
 pub fn server_error(msg: String, code: ServerErrorCode) -> ErrorObjectOwned {
@@ -1,7 +1,11 @@
 use conjunto_director_pubsub::{
-    director::DirectorPubsubConfig, start_pubsub_server,
+    director::DirectorPubsubConfig, routing_rules::RoutingRules,
+    start_pubsub_server,
+};
+use conjunto_director_rpc::{
+    rpc::{routing_rules::RoutingRules as RpcRoutingRules, DirectorConfig},
+    start_rpc_server,
 };
-use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
 use conjunto_providers::{
     rpc_account_provider::RpcAccountProvider,
     rpc_signature_status_provider::RpcSignatureStatusProvider,
@@ -12,18 +16,31 @@ use log::*;
 async fn main() {
     env_logger::init();
 
+    let mut rpc_config = DirectorConfig::devnet();
+    // Optional .toml or .json file overriding where RPC methods are routed
+    if let Ok(path) = std::env::var("RPC_ROUTING_RULES") {
+        rpc_config.routing_rules = RpcRoutingRules::from_file(&path)
+            .unwrap_or_else(|err| {
+                panic!("Invalid routing rules in {}: {}", path, err)
+            });
+    }
     let (rpc_addr, rpc_handle) =
-        start_rpc_server(DirectorConfig::devnet(), None)
-            .await
-            .unwrap();
+        start_rpc_server(rpc_config, None).await.unwrap();
 
-    let (pubsub_addr, pubsub_handle) =
-        start_pubsub_server::<RpcAccountProvider, RpcSignatureStatusProvider>(
-            DirectorPubsubConfig::devnet(),
-            None,
-        )
-        .await
-        .unwrap();
+    let mut pubsub_config = DirectorPubsubConfig::devnet();
+    // Optional .toml or .json file overriding where pubsub methods are routed
+    if let Ok(path) = std::env::var("PUBSUB_ROUTING_RULES") {
+        pubsub_config.routing_rules = RoutingRules::from_file(&path)
+            .unwrap_or_else(|err| {
+                panic!("Invalid routing rules in {}: {}", path, err)
+            });
+    }
+    let (pubsub_addr, pubsub_handle) = start_pubsub_server::<
+        RpcAccountProvider,
+        RpcSignatureStatusProvider,
+    >(pubsub_config, None)
+    .await
+    .unwrap();
     info!("RPC Server running on: {}", rpc_addr);
     info!("Pubsub Server running on: {}", pubsub_addr);
 
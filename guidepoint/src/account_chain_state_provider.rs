@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
+use conjunto_lockbox::{
+    account_chain_snapshot_provider::AccountChainSnapshotProvider,
+    account_chain_state::AccountChainState, errors::LockboxResult,
+};
+use solana_sdk::pubkey::Pubkey;
+
+/// Provides the state of accounts on chain, i.e. if they are delegated
+#[async_trait]
+pub trait AccountChainStateProvider:
+    std::marker::Sync + std::marker::Send + 'static
+{
+    async fn try_fetch_chain_state_of_pubkey(
+        &self,
+        pubkey: &Pubkey,
+    ) -> LockboxResult<AccountChainState>;
+
+    /// Fetches the states of all accounts, implementations should do so with
+    /// a single request
+    async fn try_fetch_chain_states_of_pubkeys(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> LockboxResult<Vec<AccountChainState>> {
+        let mut chain_states = Vec::with_capacity(pubkeys.len());
+        for pubkey in pubkeys {
+            chain_states
+                .push(self.try_fetch_chain_state_of_pubkey(pubkey).await?);
+        }
+        Ok(chain_states)
+    }
+}
+
+#[async_trait]
+impl<T, U> AccountChainStateProvider for AccountChainSnapshotProvider<T, U>
+where
+    T: AccountProvider,
+    U: DelegationRecordParser + Sync + Send + 'static,
+{
+    async fn try_fetch_chain_state_of_pubkey(
+        &self,
+        pubkey: &Pubkey,
+    ) -> LockboxResult<AccountChainState> {
+        self.try_fetch_chain_snapshot_of_pubkey(pubkey, None)
+            .await
+            .map(|chain_snapshot| chain_snapshot.chain_state)
+    }
+
+    async fn try_fetch_chain_states_of_pubkeys(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> LockboxResult<Vec<AccountChainState>> {
+        self.try_fetch_chain_snapshots_of_pubkeys(pubkeys, None)
+            .await
+            .map(|chain_snapshots| {
+                chain_snapshots
+                    .into_iter()
+                    .map(|chain_snapshot| chain_snapshot.chain_state)
+                    .collect()
+            })
+    }
+}
@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::fallback_policy::StrategyKind;
+
+pub type GuidepointResult<T> = std::result::Result<T, GuidepointError>;
+
+#[derive(Error, Debug)]
+pub enum GuidepointError {
+    #[error("ConjuntoCoreError")]
+    ConjuntoCoreError(#[from] conjunto_core::errors::CoreError),
+    #[error("LockboxError")]
+    LockboxError(#[from] conjunto_lockbox::errors::LockboxError),
+    #[error(
+        "Resolving the {strategy_kind} lookup timed out after {timeout:?}"
+    )]
+    ResolutionTimedOut {
+        strategy_kind: StrategyKind,
+        timeout: Duration,
+    },
+}
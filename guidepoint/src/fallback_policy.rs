@@ -0,0 +1,103 @@
+use std::{fmt, time::Duration};
+
+use conjunto_core::{GuideStrategy, RequestEndpoint};
+
+/// Strategies that need to look something up in order to be resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StrategyKind {
+    Account,
+    Program,
+    Signature,
+}
+
+impl StrategyKind {
+    pub fn of_strategy(strategy: &GuideStrategy) -> Option<Self> {
+        use GuideStrategy::*;
+        match strategy {
+            Chain | Ephemeral | Both | EphemeralThenChain
+            | ChainThenEphemeral => None,
+            TryEphemeralForAccount(..) | TryEphemeralForAccounts(..) => {
+                Some(Self::Account)
+            }
+            TryEphemeralForProgram(..) => Some(Self::Program),
+            TryEphemeralForSignature(..) => Some(Self::Signature),
+        }
+    }
+
+    /// Whether the strategy guides a subscription rather than a single request
+    pub fn is_subscription(strategy: &GuideStrategy) -> bool {
+        use GuideStrategy::*;
+        match strategy {
+            Chain | Ephemeral | Both | EphemeralThenChain
+            | ChainThenEphemeral => false,
+            TryEphemeralForAccount(_, is_subscription)
+            | TryEphemeralForAccounts(_, is_subscription)
+            | TryEphemeralForProgram(_, is_subscription)
+            | TryEphemeralForSignature(_, is_subscription) => *is_subscription,
+        }
+    }
+}
+
+impl fmt::Display for StrategyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrategyKind::Account => write!(f, "account"),
+            StrategyKind::Program => write!(f, "program"),
+            StrategyKind::Signature => write!(f, "signature"),
+        }
+    }
+}
+
+/// Where a request goes when its lookup fails or times out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFallback {
+    #[default]
+    Chain,
+    Both,
+    Ephemeral,
+    /// Return the error to the client instead of guiding the request
+    Error,
+}
+
+impl ErrorFallback {
+    pub fn endpoint(&self) -> Option<RequestEndpoint> {
+        match self {
+            ErrorFallback::Chain => Some(RequestEndpoint::Chain),
+            ErrorFallback::Both => Some(RequestEndpoint::Both),
+            ErrorFallback::Ephemeral => Some(RequestEndpoint::Ephemeral),
+            ErrorFallback::Error => None,
+        }
+    }
+}
+
+/// Decides how failed lookups are resolved per strategy kind, separately for
+/// subscriptions and single requests.
+/// By default all of them go to chain and lookups never time out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FallbackPolicy {
+    pub account_request: ErrorFallback,
+    pub account_subscription: ErrorFallback,
+    pub program_request: ErrorFallback,
+    pub program_subscription: ErrorFallback,
+    pub signature_request: ErrorFallback,
+    pub signature_subscription: ErrorFallback,
+    /// Lookups that take longer are treated as failed
+    pub resolution_timeout: Option<Duration>,
+}
+
+impl FallbackPolicy {
+    pub fn fallback_of(
+        &self,
+        strategy_kind: StrategyKind,
+        is_subscription: bool,
+    ) -> ErrorFallback {
+        match (strategy_kind, is_subscription) {
+            (StrategyKind::Account, false) => self.account_request,
+            (StrategyKind::Account, true) => self.account_subscription,
+            (StrategyKind::Program, false) => self.program_request,
+            (StrategyKind::Program, true) => self.program_subscription,
+            (StrategyKind::Signature, false) => self.signature_request,
+            (StrategyKind::Signature, true) => self.signature_subscription,
+        }
+    }
+}
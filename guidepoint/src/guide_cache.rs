@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuideCacheConfig {
+    /// How long accounts found in the ephemeral validator and signatures that
+    /// landed there are remembered
+    pub positive_ttl: Duration,
+    /// How long missing accounts and signatures are remembered, should be
+    /// short since they may appear at any time
+    pub negative_ttl: Duration,
+    /// Forget all missing accounts once a signature landed in the ephemeral
+    /// validator. We only know the signature and not the accounts its
+    /// transaction created, so this may refetch unrelated accounts. If
+    /// disabled, created accounts may be treated as missing for up to the
+    /// negative TTL.
+    pub forget_missing_accounts_on_landed: bool,
+}
+
+impl Default for GuideCacheConfig {
+    fn default() -> Self {
+        Self {
+            positive_ttl: Duration::from_secs(5),
+            negative_ttl: Duration::from_secs(1),
+            forget_missing_accounts_on_landed: true,
+        }
+    }
+}
+
+/// Result of looking up an account in the ephemeral validators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountLookup {
+    /// The authority is the one of the registered validator holding the
+    /// account, `None` for the default ephemeral validator
+    Found {
+        executable: bool,
+        authority: Option<Pubkey>,
+    },
+    Missing,
+}
+
+struct CachedLookup<T> {
+    lookup: T,
+    cached_at: Instant,
+}
+
+struct CachedLookups<K, T> {
+    entries: HashMap<K, CachedLookup<T>>,
+    last_sweep: Instant,
+}
+
+impl<K, T> CachedLookups<K, T> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+}
+
+/// Caches the ephemeral account and signature lookups of the
+/// [crate::GuideStrategyResolver], i.e. while clients resubscribe after a
+/// reconnect.
+/// Only lookups in the ephemeral validator are cached, chain state lookups
+/// for delegations are always fetched.
+/// Failed lookups are never cached, expired ones are swept at most once per
+/// the longer TTL.
+pub struct GuideCache {
+    config: GuideCacheConfig,
+    accounts: RwLock<CachedLookups<Pubkey, AccountLookup>>,
+    signatures: RwLock<CachedLookups<Signature, bool>>,
+}
+
+impl GuideCache {
+    pub fn new(config: GuideCacheConfig) -> Self {
+        Self {
+            config,
+            accounts: RwLock::new(CachedLookups::new()),
+            signatures: RwLock::new(CachedLookups::new()),
+        }
+    }
+
+    pub fn config(&self) -> &GuideCacheConfig {
+        &self.config
+    }
+
+    pub fn get_account(&self, pubkey: &Pubkey) -> Option<AccountLookup> {
+        get(&self.accounts, pubkey, |lookup| {
+            self.ttl(matches!(lookup, AccountLookup::Found { .. }))
+        })
+    }
+
+    pub fn insert_account(&self, pubkey: Pubkey, lookup: AccountLookup) {
+        insert(
+            &self.accounts,
+            pubkey,
+            lookup,
+            self.sweep_interval(),
+            |lookup| self.ttl(matches!(lookup, AccountLookup::Found { .. })),
+        );
+    }
+
+    /// Returns if the signature landed in the ephemeral validator
+    pub fn get_signature(&self, signature: &Signature) -> Option<bool> {
+        get(&self.signatures, signature, |landed| self.ttl(*landed))
+    }
+
+    /// Caches if the signature landed, once it did the transaction may have
+    /// created accounts that were missing before, see [Self::signature_landed]
+    pub fn insert_signature(&self, signature: Signature, landed: bool) {
+        if landed {
+            self.signature_landed(&signature);
+        }
+        insert(
+            &self.signatures,
+            signature,
+            landed,
+            self.sweep_interval(),
+            |landed| self.ttl(*landed),
+        );
+    }
+
+    /// Forgets the signature and all missing accounts unless configured
+    /// otherwise, see [GuideCacheConfig::forget_missing_accounts_on_landed].
+    /// Found accounts are kept until they expire, transactions rarely remove
+    /// accounts from the ephemeral validator and the positive TTL bounds how
+    /// long a stale entry routes requests there.
+    pub fn signature_landed(&self, signature: &Signature) {
+        self.signatures
+            .write()
+            .expect("RwLock of signatures poisoned")
+            .entries
+            .remove(signature);
+        if self.config.forget_missing_accounts_on_landed {
+            self.accounts
+                .write()
+                .expect("RwLock of accounts poisoned")
+                .entries
+                .retain(|_, cached| cached.lookup != AccountLookup::Missing);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.accounts
+            .write()
+            .expect("RwLock of accounts poisoned")
+            .entries
+            .clear();
+        self.signatures
+            .write()
+            .expect("RwLock of signatures poisoned")
+            .entries
+            .clear();
+    }
+
+    /// Every lookup cached before the last sweep expired after this long
+    fn sweep_interval(&self) -> Duration {
+        self.config.positive_ttl.max(self.config.negative_ttl)
+    }
+
+    fn ttl(&self, is_positive: bool) -> Duration {
+        if is_positive {
+            self.config.positive_ttl
+        } else {
+            self.config.negative_ttl
+        }
+    }
+}
+
+fn get<K: Eq + Hash, T: Copy>(
+    cache: &RwLock<CachedLookups<K, T>>,
+    key: &K,
+    ttl: impl Fn(&T) -> Duration,
+) -> Option<T> {
+    cache
+        .read()
+        .expect("RwLock of guide cache poisoned")
+        .entries
+        .get(key)
+        .filter(|cached| cached.cached_at.elapsed() < ttl(&cached.lookup))
+        .map(|cached| cached.lookup)
+}
+
+/// Caches the lookup and removes all expired ones if the last sweep is at
+/// least the sweep interval ago
+fn insert<K: Eq + Hash, T>(
+    cache: &RwLock<CachedLookups<K, T>>,
+    key: K,
+    lookup: T,
+    sweep_interval: Duration,
+    ttl: impl Fn(&T) -> Duration,
+) {
+    let mut cache = cache.write().expect("RwLock of guide cache poisoned");
+    if cache.last_sweep.elapsed() >= sweep_interval {
+        cache.entries.retain(|_, cached| {
+            cached.cached_at.elapsed() < ttl(&cached.lookup)
+        });
+        cache.last_sweep = Instant::now();
+    }
+    cache.entries.insert(
+        key,
+        CachedLookup {
+            lookup,
+            cached_at: Instant::now(),
+        },
+    );
+}
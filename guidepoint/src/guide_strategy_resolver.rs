@@ -1,12 +1,75 @@
+use std::slice;
+
 use conjunto_core::{
-    AccountProvider, GuideStrategy, RequestEndpoint, SignatureStatusProvider,
+    errors::CoreResult, AccountProvider, GuideStrategy, RequestEndpoint,
+    SignatureStatusProvider,
 };
+use conjunto_lockbox::account_chain_state::AccountChainState;
 use log::*;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+use crate::{
+    account_chain_state_provider::AccountChainStateProvider,
+    errors::{GuidepointError, GuidepointResult},
+    fallback_policy::{FallbackPolicy, StrategyKind},
+    guide_cache::{AccountLookup, GuideCache, GuideCacheConfig},
+};
+
+/// Decides where requests for a specific account are guided to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountResolutionMode {
+    /// Ephemeral if the ephemeral validator has the account
+    #[default]
+    EphemeralAccount,
+    /// Ephemeral if the account is delegated on chain, chain if it exists
+    /// there undelegated
+    ChainDelegation,
+    /// Like [AccountResolutionMode::ChainDelegation], but asks the ephemeral
+    /// validator for accounts missing on chain or whose chain state could not
+    /// be fetched
+    Combined,
+}
+
+/// Where an account of a request for multiple accounts lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AccountLocation {
+    /// With the authority of the validator if it is delegated
+    Ephemeral(Option<Pubkey>),
+    Chain,
+    /// Neither side has the account yet
+    Missing,
+}
+
+impl AccountLocation {
+    fn authority(&self) -> Option<Pubkey> {
+        match self {
+            AccountLocation::Ephemeral(authority) => *authority,
+            _ => None,
+        }
+    }
+}
+
+impl From<AccountLookup> for AccountLocation {
+    fn from(lookup: AccountLookup) -> Self {
+        match lookup {
+            AccountLookup::Found { authority, .. } => {
+                AccountLocation::Ephemeral(authority)
+            }
+            AccountLookup::Missing => AccountLocation::Missing,
+        }
+    }
+}
 
 pub struct GuideStrategyResolver<T: AccountProvider, U: SignatureStatusProvider>
 {
     pub ephemeral_account_provider: T,
     pub ephemeral_signature_status_provider: U,
+    /// Providers of the registered validators by their authority
+    validator_account_providers: Vec<(Pubkey, Box<dyn AccountProvider>)>,
+    account_resolution_mode: AccountResolutionMode,
+    chain_state_provider: Option<Box<dyn AccountChainStateProvider>>,
+    cache: Option<GuideCache>,
+    fallback_policy: FallbackPolicy,
 }
 
 impl<T: AccountProvider, U: SignatureStatusProvider>
@@ -19,97 +82,538 @@ impl<T: AccountProvider, U: SignatureStatusProvider>
         Self {
             ephemeral_account_provider,
             ephemeral_signature_status_provider,
+            validator_account_providers: vec![],
+            account_resolution_mode: AccountResolutionMode::default(),
+            chain_state_provider: None,
+            cache: None,
+            fallback_policy: FallbackPolicy::default(),
         }
     }
 
-    pub async fn resolve(&self, strategy: &GuideStrategy) -> RequestEndpoint {
+    /// Looks up accounts missing in the default ephemeral validator in the
+    /// validator with the given authority as well, the first validator
+    /// holding an account decides where its requests go
+    pub fn with_validator_account_provider(
+        mut self,
+        authority: Pubkey,
+        account_provider: impl AccountProvider,
+    ) -> Self {
+        self.validator_account_providers
+            .push((authority, Box::new(account_provider)));
+        self
+    }
+
+    /// Caches ephemeral account and signature lookups.
+    /// Chain state lookups of [AccountResolutionMode::ChainDelegation] and
+    /// [AccountResolutionMode::Combined] are not cached, so the cache has no
+    /// effect in [AccountResolutionMode::ChainDelegation] mode.
+    pub fn with_cache(mut self, config: GuideCacheConfig) -> Self {
+        self.cache = Some(GuideCache::new(config));
+        self
+    }
+
+    /// Guides account requests according to the mode, the provider is used to
+    /// look up the delegation state on chain
+    pub fn with_account_resolution_mode(
+        mut self,
+        account_resolution_mode: AccountResolutionMode,
+        chain_state_provider: impl AccountChainStateProvider,
+    ) -> Self {
+        self.account_resolution_mode = account_resolution_mode;
+        self.chain_state_provider = Some(Box::new(chain_state_provider));
+        self
+    }
+
+    /// Decides where requests go when their lookup fails or times out
+    pub fn with_fallback_policy(
+        mut self,
+        fallback_policy: FallbackPolicy,
+    ) -> Self {
+        self.fallback_policy = fallback_policy;
+        self
+    }
+
+    pub fn fallback_policy(&self) -> &FallbackPolicy {
+        &self.fallback_policy
+    }
+
+    pub fn account_resolution_mode(&self) -> AccountResolutionMode {
+        self.account_resolution_mode
+    }
+
+    pub fn cache(&self) -> Option<&GuideCache> {
+        self.cache.as_ref()
+    }
+
+    /// Invalidates cached lookups that are outdated once the signature
+    /// landed, i.e. when a signature notification is observed
+    pub fn signature_landed(&self, signature: &Signature) {
+        if let Some(cache) = &self.cache {
+            cache.signature_landed(signature);
+        }
+    }
+
+    /// Resolves the strategy into an endpoint, failed lookups are resolved
+    /// according to the [FallbackPolicy] and only result in an error if it
+    /// says so
+    pub async fn resolve(
+        &self,
+        strategy: &GuideStrategy,
+    ) -> GuidepointResult<RequestEndpoint> {
+        self.resolve_with_authority(strategy)
+            .await
+            .map(|(endpoint, _)| endpoint)
+    }
+
+    /// Like [Self::resolve], but also returns the authority of the ephemeral
+    /// validator the request needs to go to if the delegation record of the
+    /// account told us or a registered validator holds it, `None` means the
+    /// default ephemeral validator
+    pub async fn resolve_with_authority(
+        &self,
+        strategy: &GuideStrategy,
+    ) -> GuidepointResult<(RequestEndpoint, Option<Pubkey>)> {
+        let strategy_kind = match StrategyKind::of_strategy(strategy) {
+            Some(strategy_kind) => strategy_kind,
+            None => return self.try_resolve(strategy).await,
+        };
+        let resolution = self.try_resolve(strategy);
+        let result = match self.fallback_policy.resolution_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, resolution)
+                .await
+                .unwrap_or(Err(GuidepointError::ResolutionTimedOut {
+                    strategy_kind,
+                    timeout,
+                })),
+            None => resolution.await,
+        };
+        result.or_else(|err| {
+            let fallback = self.fallback_policy.fallback_of(
+                strategy_kind,
+                StrategyKind::is_subscription(strategy),
+            );
+            warn!(
+                "Failed to resolve {} lookup, falling back to {:?}: {:?}",
+                strategy_kind, fallback, err
+            );
+            fallback
+                .endpoint()
+                .map(|endpoint| (endpoint, None))
+                .ok_or(err)
+        })
+    }
+
+    async fn try_resolve(
+        &self,
+        strategy: &GuideStrategy,
+    ) -> GuidepointResult<(RequestEndpoint, Option<Pubkey>)> {
         use GuideStrategy::*;
 
-        match strategy {
+        let endpoint = match strategy {
             Chain => RequestEndpoint::Chain,
             Ephemeral => RequestEndpoint::Ephemeral,
             Both => RequestEndpoint::Both,
+            EphemeralThenChain => RequestEndpoint::EphemeralThenChain,
+            ChainThenEphemeral => RequestEndpoint::ChainThenEphemeral,
             TryEphemeralForAccount(address, is_subscription) => {
-                self.guide_by_address(address, false, *is_subscription)
+                return self.guide_by_account(address, *is_subscription).await
+            }
+            TryEphemeralForAccounts(addresses, is_subscription) => {
+                return self
+                    .guide_by_addresses(addresses, *is_subscription)
                     .await
             }
             TryEphemeralForProgram(program_id, is_subscription) => {
-                self.guide_by_address(program_id, true, *is_subscription)
+                return self
+                    .guide_by_address(program_id, true, *is_subscription)
                     .await
             }
             TryEphemeralForSignature(signature, is_subscription) => {
                 self.guide_by_signature(signature.as_str(), *is_subscription)
-                    .await
+                    .await?
             }
-        }
+        };
+        Ok((endpoint, None))
     }
 
     async fn guide_by_signature(
         &self,
         signature: &str,
         is_subscription: bool,
-    ) -> RequestEndpoint {
+    ) -> GuidepointResult<RequestEndpoint> {
         let signature = match signature.parse() {
             Ok(signature) => signature,
-            Err(_) => return RequestEndpoint::Chain,
+            Err(_) => return Ok(RequestEndpoint::Chain),
         };
-        match self
-            .ephemeral_signature_status_provider
-            .get_signature_status(&signature)
+        let endpoint = if self.lookup_signature(&signature).await? {
+            RequestEndpoint::Ephemeral
+        } else if is_subscription {
+            // Wait for any of the backends to see an update to the signature
+            RequestEndpoint::Both
+        } else {
+            RequestEndpoint::Chain
+        };
+        Ok(endpoint)
+    }
+
+    /// Also returns the authority the account is delegated to if we looked
+    /// it up on chain or of the registered validator holding it
+    async fn guide_by_account(
+        &self,
+        address: &str,
+        is_subscription: bool,
+    ) -> GuidepointResult<(RequestEndpoint, Option<Pubkey>)> {
+        use AccountResolutionMode::*;
+        let chain_state_provider =
+            match (self.account_resolution_mode, &self.chain_state_provider) {
+                (ChainDelegation | Combined, Some(chain_state_provider)) => {
+                    chain_state_provider
+                }
+                _ => {
+                    return self
+                        .guide_by_address(address, false, is_subscription)
+                        .await
+                }
+            };
+        let pubkey = match address.parse() {
+            Ok(pubkey) => pubkey,
+            Err(_) => return Ok((RequestEndpoint::Chain, None)),
+        };
+        match chain_state_provider
+            .try_fetch_chain_state_of_pubkey(&pubkey)
             .await
         {
-            Ok(Some(_)) => RequestEndpoint::Ephemeral,
-            // Wait for any of the backends to see an update to the signature
-            Ok(None) if is_subscription => RequestEndpoint::Both,
-            Ok(None) => RequestEndpoint::Chain,
-            Err(err) => {
-                warn!("Error while fetching signature status: {:?}", err);
-                // In case of an error the signature does not exist or the RPC client
-                // ran into an issue. In both cases we default to chain
-                RequestEndpoint::Chain
+            // The ephemeral validator clones delegated accounts on demand, so
+            // it doesn't need to have the account yet
+            Ok(AccountChainState::Delegated {
+                delegation_record, ..
+            }) => Ok((
+                RequestEndpoint::Ephemeral,
+                Some(delegation_record.authority),
+            )),
+            // The ephemeral validator may hold a stale copy of an account that
+            // was undelegated in the meantime
+            Ok(AccountChainState::Undelegated { .. }) => {
+                Ok((RequestEndpoint::Chain, None))
+            }
+            Ok(AccountChainState::FeePayer { lamports, .. })
+                if lamports > 0 =>
+            {
+                Ok((RequestEndpoint::Chain, None))
             }
+            // The account doesn't exist on chain, it may only exist in the
+            // ephemeral validator or be created on either side
+            Ok(AccountChainState::FeePayer { .. }) => {
+                match self.account_resolution_mode {
+                    Combined => {
+                        self.guide_by_address(address, false, is_subscription)
+                            .await
+                    }
+                    _ if is_subscription => Ok((RequestEndpoint::Both, None)),
+                    _ => Ok((RequestEndpoint::Chain, None)),
+                }
+            }
+            Err(err) => match self.account_resolution_mode {
+                Combined => {
+                    warn!(
+                        "Error while fetching account chain state: {:?}",
+                        err
+                    );
+                    self.guide_by_address(address, false, is_subscription).await
+                }
+                _ => Err(err.into()),
+            },
         }
     }
 
+    /// Also returns the authority of the registered validator holding the
+    /// account
     async fn guide_by_address(
         &self,
         address: &str,
         is_program: bool,
         is_subscription: bool,
-    ) -> RequestEndpoint {
+    ) -> GuidepointResult<(RequestEndpoint, Option<Pubkey>)> {
         // If we find an invalid pubkey provided as an address then we forward
         // that to chain which will provide an error to the user
         let pubkey = match address.parse() {
             Ok(pubkey) => pubkey,
-            Err(_) => return RequestEndpoint::Chain,
+            Err(_) => return Ok((RequestEndpoint::Chain, None)),
         };
-        let account = match self
-            .ephemeral_account_provider
-            .get_account(&pubkey, None)
-            .await
+        let (executable, authority) = match self.lookup_account(&pubkey).await?
         {
-            Ok((_, Some(acc))) => acc,
+            AccountLookup::Found {
+                executable,
+                authority,
+            } => (executable, authority),
             // If the ephemeral validator does not have he account then we go to chain for
             // single requests and to both for subscriptions (since the account may be created
             // after the subscription)
-            Ok((_, None)) => {
+            AccountLookup::Missing => {
                 if is_subscription {
-                    return RequestEndpoint::Both;
+                    return Ok((RequestEndpoint::Both, None));
                 } else {
-                    return RequestEndpoint::Chain;
+                    return Ok((RequestEndpoint::Chain, None));
                 }
             }
-            Err(err) => {
-                warn!("Error while fetching account: {:?}", err);
-                // In case of an error the account does not exist or the RPC client
-                // ran into an issue. In both cases we default to chain
-                return RequestEndpoint::Chain;
-            }
         };
-        if is_program && !account.executable {
-            RequestEndpoint::Chain
+        if is_program && !executable {
+            Ok((RequestEndpoint::Chain, None))
         } else {
-            RequestEndpoint::Ephemeral
+            Ok((RequestEndpoint::Ephemeral, authority))
+        }
+    }
+
+    /// Locates all accounts at once according to the
+    /// [AccountResolutionMode], the request is only ephemeral if all of them
+    /// are. If they are all delegated to the same validator its authority is
+    /// returned as well.
+    async fn guide_by_addresses(
+        &self,
+        addresses: &[String],
+        is_subscription: bool,
+    ) -> GuidepointResult<(RequestEndpoint, Option<Pubkey>)> {
+        // Invalid addresses are forwarded to chain which will provide an
+        // error to the user
+        let pubkeys = match addresses
+            .iter()
+            .map(|address| address.parse())
+            .collect::<Result<Vec<Pubkey>, _>>()
+        {
+            Ok(pubkeys) if !pubkeys.is_empty() => pubkeys,
+            _ => return Ok((RequestEndpoint::Chain, None)),
+        };
+        use AccountResolutionMode::*;
+        let locations =
+            match (self.account_resolution_mode, &self.chain_state_provider) {
+                (ChainDelegation | Combined, Some(chain_state_provider)) => {
+                    self.locate_by_chain_states(
+                        chain_state_provider.as_ref(),
+                        &pubkeys,
+                    )
+                    .await?
+                }
+                _ => self
+                    .lookup_accounts(&pubkeys)
+                    .await?
+                    .into_iter()
+                    .map(AccountLocation::from)
+                    .collect(),
+            };
+
+        let ephemeral_count = locations
+            .iter()
+            .filter(|location| {
+                matches!(location, AccountLocation::Ephemeral(_))
+            })
+            .count();
+        let has_missing = locations
+            .iter()
+            .any(|location| matches!(location, AccountLocation::Missing));
+        if ephemeral_count == locations.len() {
+            let authority = locations[0].authority();
+            let authority = locations
+                .iter()
+                .all(|location| location.authority() == authority)
+                .then_some(authority)
+                .flatten();
+            return Ok((RequestEndpoint::Ephemeral, authority));
+        }
+        // Missing accounts may be created on either side after subscribing
+        let endpoint =
+            if ephemeral_count > 0 || (has_missing && is_subscription) {
+                RequestEndpoint::Both
+            } else {
+                RequestEndpoint::Chain
+            };
+        Ok((endpoint, None))
+    }
+
+    /// Locates the accounts by their delegation state on chain with a single
+    /// request. In [AccountResolutionMode::Combined] the accounts missing on
+    /// chain or whose state could not be fetched are looked up in the
+    /// ephemeral validator.
+    async fn locate_by_chain_states(
+        &self,
+        chain_state_provider: &dyn AccountChainStateProvider,
+        pubkeys: &[Pubkey],
+    ) -> GuidepointResult<Vec<AccountLocation>> {
+        let is_combined =
+            self.account_resolution_mode == AccountResolutionMode::Combined;
+        let chain_states: Vec<Option<AccountChainState>> =
+            match chain_state_provider
+                .try_fetch_chain_states_of_pubkeys(pubkeys)
+                .await
+            {
+                Ok(chain_states) => {
+                    chain_states.into_iter().map(Some).collect()
+                }
+                Err(err) if is_combined => {
+                    warn!(
+                        "Error while fetching account chain states: {:?}",
+                        err
+                    );
+                    pubkeys.iter().map(|_| None).collect()
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+        let mut locations = Vec::with_capacity(pubkeys.len());
+        let mut unresolved = vec![];
+        for (idx, chain_state) in chain_states.into_iter().enumerate() {
+            let location = match chain_state {
+                Some(AccountChainState::Delegated {
+                    delegation_record,
+                    ..
+                }) => AccountLocation::Ephemeral(Some(
+                    delegation_record.authority,
+                )),
+                Some(AccountChainState::Undelegated { .. }) => {
+                    AccountLocation::Chain
+                }
+                Some(AccountChainState::FeePayer { lamports, .. })
+                    if lamports > 0 =>
+                {
+                    AccountLocation::Chain
+                }
+                _ => {
+                    if is_combined {
+                        unresolved.push(idx);
+                    }
+                    AccountLocation::Missing
+                }
+            };
+            locations.push(location);
+        }
+        if !unresolved.is_empty() {
+            let unresolved_pubkeys = unresolved
+                .iter()
+                .map(|idx| pubkeys[*idx])
+                .collect::<Vec<_>>();
+            let lookups = self.lookup_accounts(&unresolved_pubkeys).await?;
+            for (idx, lookup) in unresolved.into_iter().zip(lookups) {
+                locations[idx] = lookup.into();
+            }
+        }
+        Ok(locations)
+    }
+
+    /// Looks up the accounts that aren't cached with a single request per
+    /// validator, only the accounts missing in the default ephemeral
+    /// validator are looked up in the registered ones
+    async fn lookup_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> CoreResult<Vec<AccountLookup>> {
+        let mut lookups = pubkeys
+            .iter()
+            .map(|pubkey| {
+                self.cache
+                    .as_ref()
+                    .and_then(|cache| cache.get_account(pubkey))
+            })
+            .collect::<Vec<_>>();
+        let uncached = lookups
+            .iter()
+            .enumerate()
+            .filter(|(_, lookup)| lookup.is_none())
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        if uncached.is_empty() {
+            return Ok(lookups.into_iter().flatten().collect());
+        }
+
+        let mut missing = uncached.clone();
+        let validators = std::iter::once((
+            None,
+            &self.ephemeral_account_provider as &dyn AccountProvider,
+        ))
+        .chain(self.validator_account_providers.iter().map(
+            |(authority, account_provider)| {
+                (Some(*authority), account_provider.as_ref())
+            },
+        ));
+        for (authority, account_provider) in validators {
+            if missing.is_empty() {
+                break;
+            }
+            let missing_pubkeys =
+                missing.iter().map(|idx| pubkeys[*idx]).collect::<Vec<_>>();
+            let accounts = match account_provider
+                .get_multiple_accounts(&missing_pubkeys, None)
+                .await
+            {
+                Ok((_, accounts)) => accounts,
+                // The default validator decides if the lookup failed, the
+                // registered ones only add accounts it doesn't have
+                Err(err) => match authority {
+                    Some(authority) => {
+                        warn!(
+                            "Failed to look up accounts in the ephemeral validator {}: {:?}",
+                            authority, err
+                        );
+                        continue;
+                    }
+                    None => return Err(err),
+                },
+            };
+            let mut accounts = accounts.into_iter();
+            let mut still_missing = vec![];
+            for idx in missing {
+                match accounts.next().flatten() {
+                    Some(account) => {
+                        lookups[idx] = Some(AccountLookup::Found {
+                            executable: account.executable,
+                            authority,
+                        })
+                    }
+                    None => still_missing.push(idx),
+                }
+            }
+            missing = still_missing;
+        }
+        for idx in missing {
+            lookups[idx] = Some(AccountLookup::Missing);
+        }
+        if let Some(cache) = &self.cache {
+            for idx in uncached {
+                if let Some(lookup) = lookups[idx] {
+                    cache.insert_account(pubkeys[idx], lookup);
+                }
+            }
+        }
+        Ok(lookups.into_iter().flatten().collect())
+    }
+
+    async fn lookup_account(
+        &self,
+        pubkey: &Pubkey,
+    ) -> CoreResult<AccountLookup> {
+        self.lookup_accounts(slice::from_ref(pubkey))
+            .await
+            .map(|mut lookups| lookups.remove(0))
+    }
+
+    /// Returns if the signature landed in the ephemeral validator
+    async fn lookup_signature(
+        &self,
+        signature: &Signature,
+    ) -> CoreResult<bool> {
+        if let Some(landed) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get_signature(signature))
+        {
+            return Ok(landed);
+        }
+        let landed = self
+            .ephemeral_signature_status_provider
+            .get_signature_status(signature)
+            .await?
+            .is_some();
+        if let Some(cache) = &self.cache {
+            cache.insert_signature(*signature, landed);
         }
+        Ok(landed)
     }
 }
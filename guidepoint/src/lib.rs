@@ -1,2 +1,11 @@
+mod account_chain_state_provider;
+pub mod errors;
+mod fallback_policy;
+mod guide_cache;
 mod guide_strategy_resolver;
-pub use guide_strategy_resolver::GuideStrategyResolver;
+pub use account_chain_state_provider::AccountChainStateProvider;
+pub use fallback_policy::{ErrorFallback, FallbackPolicy, StrategyKind};
+pub use guide_cache::{AccountLookup, GuideCache, GuideCacheConfig};
+pub use guide_strategy_resolver::{
+    AccountResolutionMode, GuideStrategyResolver,
+};
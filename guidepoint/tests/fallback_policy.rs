@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use conjunto_core::{
+    errors::CoreResult, AccountProvider, GuideStrategy, RequestEndpoint,
+};
+use conjunto_guidepoint::{
+    errors::GuidepointError, ErrorFallback, FallbackPolicy,
+    GuideStrategyResolver, StrategyKind,
+};
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    signature_status_provider_stub::SignatureStatusProviderStub,
+};
+use solana_sdk::{account::Account, clock::Slot, pubkey::Pubkey};
+
+/// Never responds, like an ephemeral validator that is stuck
+struct PendingAccountProvider;
+
+#[async_trait]
+impl AccountProvider for PendingAccountProvider {
+    async fn get_account(
+        &self,
+        _pubkey: &Pubkey,
+        _min_context_slot: Option<Slot>,
+    ) -> CoreResult<(Slot, Option<Account>)> {
+        std::future::pending().await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        _pubkeys: &[Pubkey],
+        _min_context_slot: Option<Slot>,
+    ) -> CoreResult<(Slot, Vec<Option<Account>>)> {
+        std::future::pending().await
+    }
+}
+
+fn failing_account_resolver(
+    failing: Pubkey,
+    fallback_policy: FallbackPolicy,
+) -> GuideStrategyResolver<AccountProviderStub, SignatureStatusProviderStub> {
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.fail(failing);
+    GuideStrategyResolver::new(
+        account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_fallback_policy(fallback_policy)
+}
+
+#[tokio::test]
+async fn test_failed_lookups_go_to_chain_by_default() {
+    let failing = Pubkey::new_unique();
+    let resolver = failing_account_resolver(failing, FallbackPolicy::default());
+
+    for strategy in [
+        GuideStrategy::TryEphemeralForAccount(failing.to_string(), true),
+        GuideStrategy::TryEphemeralForProgram(failing.to_string(), false),
+    ] {
+        assert_eq!(
+            resolver.resolve(&strategy).await.unwrap(),
+            RequestEndpoint::Chain
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_failed_lookups_fall_back_per_strategy_kind() {
+    let failing = Pubkey::new_unique();
+    let resolver = failing_account_resolver(
+        failing,
+        FallbackPolicy {
+            account_subscription: ErrorFallback::Both,
+            program_subscription: ErrorFallback::Ephemeral,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&GuideStrategy::TryEphemeralForAccount(
+                failing.to_string(),
+                true
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&GuideStrategy::TryEphemeralForProgram(
+                failing.to_string(),
+                true
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+}
+
+#[tokio::test]
+async fn test_failed_lookups_fall_back_per_subscription_and_request() {
+    let failing = Pubkey::new_unique();
+    let resolver = failing_account_resolver(
+        failing,
+        FallbackPolicy {
+            account_request: ErrorFallback::Ephemeral,
+            account_subscription: ErrorFallback::Both,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&GuideStrategy::TryEphemeralForAccount(
+                failing.to_string(),
+                false
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+    assert_eq!(
+        resolver
+            .resolve(&GuideStrategy::TryEphemeralForAccount(
+                failing.to_string(),
+                true
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+}
+
+#[tokio::test]
+async fn test_failed_lookup_returns_error_if_configured() {
+    let failing = Pubkey::new_unique();
+    let resolver = failing_account_resolver(
+        failing,
+        FallbackPolicy {
+            account_subscription: ErrorFallback::Error,
+            ..Default::default()
+        },
+    );
+
+    let result = resolver
+        .resolve(&GuideStrategy::TryEphemeralForAccount(
+            failing.to_string(),
+            true,
+        ))
+        .await;
+    assert!(matches!(result, Err(GuidepointError::ConjuntoCoreError(_))));
+
+    // Strategies that don't need a lookup never fail
+    assert_eq!(
+        resolver.resolve(&GuideStrategy::Both).await.unwrap(),
+        RequestEndpoint::Both
+    );
+}
+
+#[tokio::test]
+async fn test_slow_lookups_time_out() {
+    let timeout = Duration::from_millis(10);
+    let resolver = GuideStrategyResolver::new(
+        PendingAccountProvider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_fallback_policy(FallbackPolicy {
+        account_subscription: ErrorFallback::Both,
+        program_subscription: ErrorFallback::Error,
+        resolution_timeout: Some(timeout),
+        ..Default::default()
+    });
+    let address = Pubkey::new_unique().to_string();
+
+    assert_eq!(
+        resolver
+            .resolve(&GuideStrategy::TryEphemeralForAccount(
+                address.clone(),
+                true
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    let result = resolver
+        .resolve(&GuideStrategy::TryEphemeralForProgram(address, true))
+        .await;
+    match result {
+        Err(GuidepointError::ResolutionTimedOut {
+            strategy_kind,
+            timeout: actual_timeout,
+        }) => {
+            assert_eq!(strategy_kind, StrategyKind::Program);
+            assert_eq!(actual_timeout, timeout);
+        }
+        _ => panic!("unexpected result {:?}", result),
+    }
+}
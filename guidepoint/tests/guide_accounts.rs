@@ -0,0 +1,157 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use conjunto_core::{GuideStrategy, RequestEndpoint};
+use conjunto_guidepoint::{GuideCacheConfig, GuideStrategyResolver};
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub, accounts::account_with_data,
+    signature_status_provider_stub::SignatureStatusProviderStub,
+};
+use solana_sdk::pubkey::Pubkey;
+
+struct Setup {
+    resolver:
+        GuideStrategyResolver<AccountProviderStub, SignatureStatusProviderStub>,
+    account_provider: AccountProviderStub,
+    ephemeral: Vec<Pubkey>,
+    missing: Vec<Pubkey>,
+}
+
+fn setup() -> Setup {
+    let ephemeral = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+    let missing = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut account_provider = AccountProviderStub::default();
+    for pubkey in &ephemeral {
+        account_provider.add(*pubkey, account_with_data());
+    }
+    // Observes the requests made through the resolver's stub
+    let observer = AccountProviderStub {
+        fetched_pubkeys: account_provider.fetched_pubkeys.clone(),
+        max_concurrent_requests: account_provider
+            .max_concurrent_requests
+            .clone(),
+        ..Default::default()
+    };
+    let resolver = GuideStrategyResolver::new(
+        account_provider,
+        SignatureStatusProviderStub::default(),
+    );
+    Setup {
+        resolver,
+        account_provider: observer,
+        ephemeral,
+        missing,
+    }
+}
+
+fn mentions(pubkeys: &[&Pubkey], is_subscription: bool) -> GuideStrategy {
+    GuideStrategy::TryEphemeralForAccounts(
+        pubkeys.iter().map(|pubkey| pubkey.to_string()).collect(),
+        is_subscription,
+    )
+}
+
+#[tokio::test]
+async fn test_guide_accounts_merges_lookups() {
+    let Setup {
+        resolver,
+        ephemeral,
+        missing,
+        ..
+    } = setup();
+
+    let all_ephemeral = mentions(&[&ephemeral[0], &ephemeral[1]], true);
+    assert_eq!(
+        resolver.resolve(&all_ephemeral).await.unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+
+    let some_ephemeral = mentions(&[&ephemeral[0], &missing[0]], false);
+    assert_eq!(
+        resolver.resolve(&some_ephemeral).await.unwrap(),
+        RequestEndpoint::Both
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&missing[0], &missing[1]], true))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&missing[0], &missing[1]], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+}
+
+#[tokio::test]
+async fn test_guide_accounts_with_invalid_or_no_addresses() {
+    let Setup {
+        resolver,
+        ephemeral,
+        ..
+    } = setup();
+
+    let invalid = GuideStrategy::TryEphemeralForAccounts(
+        vec![ephemeral[0].to_string(), "<not a pubkey>".to_string()],
+        true,
+    );
+    assert_eq!(
+        resolver.resolve(&invalid).await.unwrap(),
+        RequestEndpoint::Chain
+    );
+    let empty = GuideStrategy::TryEphemeralForAccounts(vec![], true);
+    assert_eq!(
+        resolver.resolve(&empty).await.unwrap(),
+        RequestEndpoint::Chain
+    );
+}
+
+#[tokio::test]
+async fn test_guide_accounts_fetches_uncached_accounts_at_once() {
+    let Setup {
+        resolver,
+        account_provider,
+        ephemeral,
+        missing,
+    } = setup();
+    let resolver = resolver.with_cache(GuideCacheConfig {
+        positive_ttl: Duration::from_secs(60),
+        negative_ttl: Duration::from_secs(60),
+        ..Default::default()
+    });
+
+    resolver
+        .resolve(&mentions(&[&ephemeral[0], &missing[0]], true))
+        .await
+        .unwrap();
+    assert_eq!(
+        *account_provider.fetched_pubkeys.read().unwrap(),
+        vec![ephemeral[0], missing[0]]
+    );
+    assert_eq!(
+        account_provider
+            .max_concurrent_requests
+            .load(Ordering::SeqCst),
+        1
+    );
+
+    account_provider.fetched_pubkeys.write().unwrap().clear();
+    assert_eq!(
+        resolver
+            .resolve(&mentions(
+                &[&ephemeral[0], &ephemeral[1], &missing[0]],
+                true
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        *account_provider.fetched_pubkeys.read().unwrap(),
+        vec![ephemeral[1]]
+    );
+}
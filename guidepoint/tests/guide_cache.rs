@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use conjunto_core::{GuideStrategy, RequestEndpoint};
+use conjunto_guidepoint::{
+    AccountLookup, GuideCache, GuideCacheConfig, GuideStrategyResolver,
+};
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub, accounts::account_with_data,
+    signature_status_provider_stub::SignatureStatusProviderStub,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+fn account_subscribe(pubkey: &Pubkey) -> GuideStrategy {
+    GuideStrategy::TryEphemeralForAccount(pubkey.to_string(), true)
+}
+
+#[tokio::test]
+async fn test_resubscribing_accounts_uses_cached_lookups() {
+    let found = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(found, account_with_data());
+    let fetched_pubkeys = account_provider.fetched_pubkeys.clone();
+    let resolver = GuideStrategyResolver::new(
+        account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_cache(GuideCacheConfig {
+        positive_ttl: Duration::from_secs(60),
+        negative_ttl: Duration::from_secs(60),
+        ..Default::default()
+    });
+
+    for _ in 0..3 {
+        assert_eq!(
+            resolver.resolve(&account_subscribe(&found)).await.unwrap(),
+            RequestEndpoint::Ephemeral
+        );
+        assert_eq!(
+            resolver
+                .resolve(&account_subscribe(&missing))
+                .await
+                .unwrap(),
+            RequestEndpoint::Both
+        );
+    }
+    assert_eq!(*fetched_pubkeys.read().unwrap(), vec![found, missing]);
+}
+
+#[tokio::test]
+async fn test_landed_signature_invalidates_missing_accounts() {
+    let created = Pubkey::new_unique();
+    let signature = Signature::new_unique();
+    let account_provider = AccountProviderStub::default();
+    let accounts = account_provider.accounts.clone();
+    let mut signature_status_provider = SignatureStatusProviderStub::default();
+    signature_status_provider.add_ok(signature);
+    let resolver =
+        GuideStrategyResolver::new(account_provider, signature_status_provider)
+            .with_cache(GuideCacheConfig {
+                positive_ttl: Duration::from_secs(60),
+                negative_ttl: Duration::from_secs(60),
+                ..Default::default()
+            });
+
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&created))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    // The transaction creating the account lands in the meantime
+    accounts
+        .write()
+        .unwrap()
+        .insert(created, account_with_data());
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&created))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&GuideStrategy::TryEphemeralForSignature(
+                signature.to_string(),
+                true
+            ))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&created))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+}
+
+#[test]
+fn test_negative_lookups_expire_separately() {
+    let cache = GuideCache::new(GuideCacheConfig {
+        positive_ttl: Duration::from_secs(60),
+        negative_ttl: Duration::ZERO,
+        ..Default::default()
+    });
+    let found = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let landed = Signature::new_unique();
+    let pending = Signature::new_unique();
+
+    cache.insert_account(
+        found,
+        AccountLookup::Found {
+            executable: false,
+            authority: None,
+        },
+    );
+    cache.insert_account(missing, AccountLookup::Missing);
+    cache.insert_signature(landed, true);
+    cache.insert_signature(pending, false);
+
+    assert_eq!(
+        cache.get_account(&found),
+        Some(AccountLookup::Found {
+            executable: false,
+            authority: None,
+        })
+    );
+    assert_eq!(cache.get_account(&missing), None);
+    assert_eq!(cache.get_signature(&landed), Some(true));
+    assert_eq!(cache.get_signature(&pending), None);
+}
+
+#[test]
+fn test_signature_landed_keeps_found_accounts() {
+    let cache = GuideCache::new(GuideCacheConfig {
+        positive_ttl: Duration::from_secs(60),
+        negative_ttl: Duration::from_secs(60),
+        ..Default::default()
+    });
+    let found = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let signature = Signature::new_unique();
+
+    cache.insert_account(
+        found,
+        AccountLookup::Found {
+            executable: true,
+            authority: None,
+        },
+    );
+    cache.insert_account(missing, AccountLookup::Missing);
+    cache.insert_signature(signature, false);
+    cache.signature_landed(&signature);
+
+    assert_eq!(
+        cache.get_account(&found),
+        Some(AccountLookup::Found {
+            executable: true,
+            authority: None,
+        })
+    );
+    assert_eq!(cache.get_account(&missing), None);
+    assert_eq!(cache.get_signature(&signature), None);
+}
+
+#[test]
+fn test_signature_landed_keeps_missing_accounts_if_configured() {
+    let cache = GuideCache::new(GuideCacheConfig {
+        positive_ttl: Duration::from_secs(60),
+        negative_ttl: Duration::from_secs(60),
+        forget_missing_accounts_on_landed: false,
+    });
+    let missing = Pubkey::new_unique();
+    let signature = Signature::new_unique();
+
+    cache.insert_account(missing, AccountLookup::Missing);
+    cache.insert_signature(signature, true);
+
+    assert_eq!(cache.get_account(&missing), Some(AccountLookup::Missing));
+    assert_eq!(cache.get_signature(&signature), Some(true));
+}
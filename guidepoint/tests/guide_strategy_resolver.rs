@@ -0,0 +1,459 @@
+use conjunto_core::{
+    delegation_record::{CommitFrequency, DelegationRecord},
+    GuideStrategy, RequestEndpoint,
+};
+use conjunto_guidepoint::{AccountResolutionMode, GuideStrategyResolver};
+use conjunto_lockbox::account_chain_snapshot_provider::AccountChainSnapshotProvider;
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    accounts::{
+        account_owned_by_delegation_program, account_with_data,
+        delegated_account_ids,
+    },
+    delegation_record_parser_stub::DelegationRecordParserStub,
+    signature_status_provider_stub::SignatureStatusProviderStub,
+};
+use solana_sdk::pubkey::Pubkey;
+
+struct Setup {
+    authority: Pubkey,
+    delegated: Pubkey,
+    undelegated: Pubkey,
+    ephemeral_only: Pubkey,
+    ephemeral_account_provider: AccountProviderStub,
+    chain_state_provider: AccountChainSnapshotProvider<
+        AccountProviderStub,
+        DelegationRecordParserStub,
+    >,
+}
+
+/// The delegated account was not cloned by the ephemeral validator yet while
+/// it still holds a copy of the undelegated one
+fn setup() -> Setup {
+    let (delegated, delegation_record) = delegated_account_ids();
+    let undelegated = Pubkey::new_unique();
+    let ephemeral_only = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let mut chain_account_provider = AccountProviderStub::default();
+    chain_account_provider
+        .add(delegated, account_owned_by_delegation_program());
+    chain_account_provider
+        .add(delegation_record, account_owned_by_delegation_program());
+    chain_account_provider.add(undelegated, account_with_data());
+    let chain_state_provider = AccountChainSnapshotProvider::new(
+        chain_account_provider,
+        DelegationRecordParserStub::new(Some(DelegationRecord {
+            authority,
+            owner: Pubkey::new_unique(),
+            delegation_slot: 0,
+            commit_frequency: CommitFrequency::Millis(1_000),
+        })),
+    );
+
+    let mut ephemeral_account_provider = AccountProviderStub::default();
+    ephemeral_account_provider.add(undelegated, account_with_data());
+    ephemeral_account_provider.add(ephemeral_only, account_with_data());
+
+    Setup {
+        authority,
+        delegated,
+        undelegated,
+        ephemeral_only,
+        ephemeral_account_provider,
+        chain_state_provider,
+    }
+}
+
+fn account_subscribe(pubkey: &Pubkey) -> GuideStrategy {
+    GuideStrategy::TryEphemeralForAccount(pubkey.to_string(), true)
+}
+
+fn get_account(pubkey: &Pubkey) -> GuideStrategy {
+    GuideStrategy::TryEphemeralForAccount(pubkey.to_string(), false)
+}
+
+#[tokio::test]
+async fn test_ephemeral_account_mode() {
+    let Setup {
+        delegated,
+        undelegated,
+        ephemeral_account_provider,
+        ..
+    } = setup();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&delegated))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&undelegated))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+}
+
+#[tokio::test]
+async fn test_chain_delegation_mode() {
+    let Setup {
+        delegated,
+        undelegated,
+        ephemeral_only,
+        ephemeral_account_provider,
+        chain_state_provider,
+        ..
+    } = setup();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_account_resolution_mode(
+        AccountResolutionMode::ChainDelegation,
+        chain_state_provider,
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&delegated))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&undelegated))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&ephemeral_only))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&get_account(&ephemeral_only))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+}
+
+#[tokio::test]
+async fn test_combined_mode() {
+    let Setup {
+        delegated,
+        undelegated,
+        ephemeral_only,
+        ephemeral_account_provider,
+        chain_state_provider,
+        ..
+    } = setup();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_account_resolution_mode(
+        AccountResolutionMode::Combined,
+        chain_state_provider,
+    );
+
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&delegated))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&undelegated))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+    assert_eq!(
+        resolver
+            .resolve(&get_account(&ephemeral_only))
+            .await
+            .unwrap(),
+        RequestEndpoint::Ephemeral
+    );
+    let missing = Pubkey::new_unique();
+    assert_eq!(
+        resolver
+            .resolve(&account_subscribe(&missing))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver.resolve(&get_account(&missing)).await.unwrap(),
+        RequestEndpoint::Chain
+    );
+}
+
+#[tokio::test]
+async fn test_authority_of_delegated_accounts() {
+    let Setup {
+        authority,
+        delegated,
+        undelegated,
+        ephemeral_account_provider,
+        chain_state_provider,
+        ..
+    } = setup();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_account_resolution_mode(
+        AccountResolutionMode::ChainDelegation,
+        chain_state_provider,
+    );
+
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&account_subscribe(&delegated))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Ephemeral, Some(authority))
+    );
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&account_subscribe(&undelegated))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Chain, None)
+    );
+}
+
+fn mentions(pubkeys: &[&Pubkey], is_subscription: bool) -> GuideStrategy {
+    GuideStrategy::TryEphemeralForAccounts(
+        pubkeys.iter().map(|pubkey| pubkey.to_string()).collect(),
+        is_subscription,
+    )
+}
+
+#[tokio::test]
+async fn test_ephemeral_account_mode_for_multiple_accounts() {
+    let Setup {
+        delegated,
+        undelegated,
+        ephemeral_only,
+        ephemeral_account_provider,
+        ..
+    } = setup();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    );
+
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&mentions(
+                &[&undelegated, &ephemeral_only],
+                false
+            ))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Ephemeral, None)
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&delegated, &undelegated], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&delegated], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+}
+
+#[tokio::test]
+async fn test_chain_delegation_mode_for_multiple_accounts() {
+    let Setup {
+        authority,
+        delegated,
+        undelegated,
+        ephemeral_only,
+        ephemeral_account_provider,
+        chain_state_provider,
+    } = setup();
+    let fetched_pubkeys = ephemeral_account_provider.fetched_pubkeys.clone();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_account_resolution_mode(
+        AccountResolutionMode::ChainDelegation,
+        chain_state_provider,
+    );
+
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&mentions(&[&delegated], true))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Ephemeral, Some(authority))
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&delegated, &undelegated], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&undelegated, &ephemeral_only], true))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&undelegated, &ephemeral_only], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+    // Only the chain state decides
+    assert!(fetched_pubkeys.read().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_combined_mode_for_multiple_accounts() {
+    let Setup {
+        delegated,
+        undelegated,
+        ephemeral_only,
+        ephemeral_account_provider,
+        chain_state_provider,
+        ..
+    } = setup();
+    let fetched_pubkeys = ephemeral_account_provider.fetched_pubkeys.clone();
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_account_resolution_mode(
+        AccountResolutionMode::Combined,
+        chain_state_provider,
+    );
+
+    // The account missing on chain is found in the ephemeral validator, the
+    // accounts don't share an authority
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&mentions(
+                &[&delegated, &ephemeral_only],
+                false
+            ))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Ephemeral, None)
+    );
+    assert_eq!(*fetched_pubkeys.read().unwrap(), vec![ephemeral_only]);
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&undelegated, &ephemeral_only], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    let missing = Pubkey::new_unique();
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&undelegated, &missing], true))
+            .await
+            .unwrap(),
+        RequestEndpoint::Both
+    );
+    assert_eq!(
+        resolver
+            .resolve(&mentions(&[&undelegated, &missing], false))
+            .await
+            .unwrap(),
+        RequestEndpoint::Chain
+    );
+}
+
+#[tokio::test]
+async fn test_accounts_of_registered_validators() {
+    let Setup {
+        ephemeral_only,
+        ephemeral_account_provider,
+        ..
+    } = setup();
+    let registered_only = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let registered_authority = Pubkey::new_unique();
+    let mut registered_account_provider = AccountProviderStub::default();
+    registered_account_provider.add(registered_only, account_with_data());
+    registered_account_provider.add(ephemeral_only, account_with_data());
+    let registered_fetched_pubkeys =
+        registered_account_provider.fetched_pubkeys.clone();
+    let mut failing_account_provider = AccountProviderStub::default();
+    failing_account_provider.fail(registered_only);
+    failing_account_provider.fail(missing);
+    let resolver = GuideStrategyResolver::new(
+        ephemeral_account_provider,
+        SignatureStatusProviderStub::default(),
+    )
+    .with_validator_account_provider(
+        Pubkey::new_unique(),
+        failing_account_provider,
+    )
+    .with_validator_account_provider(
+        registered_authority,
+        registered_account_provider,
+    );
+
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&account_subscribe(&registered_only))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Ephemeral, Some(registered_authority))
+    );
+    // Accounts of the default validator are not looked up anywhere else
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&account_subscribe(&ephemeral_only))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Ephemeral, None)
+    );
+    assert_eq!(
+        resolver
+            .resolve_with_authority(&mentions(
+                &[&registered_only, &missing],
+                true
+            ))
+            .await
+            .unwrap(),
+        (RequestEndpoint::Both, None)
+    );
+    assert!(!registered_fetched_pubkeys
+        .read()
+        .unwrap()
+        .contains(&ephemeral_only));
+}
@@ -66,6 +66,49 @@ impl<T: AccountProvider, U: DelegationRecordParser>
         })
     }
 
+    /// Same as [Self::try_fetch_chain_snapshot_of_pubkey] for multiple
+    /// accounts, they and their delegation records are fetched all at once
+    pub async fn try_fetch_chain_snapshots_of_pubkeys(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+    ) -> LockboxResult<Vec<AccountChainSnapshot>> {
+        let fetched_pubkeys = pubkeys
+            .iter()
+            .cloned()
+            .chain(pubkeys.iter().map(pda::delegation_record_pda_from_pubkey))
+            .collect::<Vec<_>>();
+        let (at_slot, mut fetched_accounts) = self
+            .account_provider
+            .get_multiple_accounts(&fetched_pubkeys, min_context_slot)
+            .await?;
+        if fetched_accounts.len() != fetched_pubkeys.len() {
+            return Err(LockboxError::InvalidFetch {
+                fetched_pubkeys,
+                fetched_accounts,
+            });
+        }
+        let delegation_record_accounts =
+            fetched_accounts.split_off(pubkeys.len());
+        Ok(pubkeys
+            .iter()
+            .zip(fetched_accounts)
+            .zip(delegation_record_accounts)
+            .map(|((pubkey, account), delegation_record_account)| {
+                AccountChainSnapshot {
+                    pubkey: *pubkey,
+                    at_slot,
+                    chain_state: self
+                        .try_into_chain_state_from_fetched_accounts(
+                            pubkey,
+                            account,
+                            delegation_record_account,
+                        ),
+                }
+            })
+            .collect())
+    }
+
     fn try_into_chain_state_from_fetched_accounts(
         &self,
         address: &Pubkey,
@@ -302,3 +302,58 @@ async fn test_snapshot_delegation_record_data_invalid() {
         }
     );
 }
+
+#[tokio::test]
+async fn test_snapshots_of_multiple_accounts() {
+    let (delegated, delegation_record_pubkey) = delegated_account_ids();
+    let undelegated = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let delegation_record = dummy_delegation_record();
+
+    let account_chain_snapshot_provider = setup(
+        vec![
+            (delegated, account_owned_by_delegation_program()),
+            (
+                delegation_record_pubkey,
+                account_owned_by_delegation_program(),
+            ),
+            (undelegated, account_with_data()),
+        ],
+        Some(delegation_record),
+    );
+
+    let chain_snapshots = account_chain_snapshot_provider
+        .try_fetch_chain_snapshots_of_pubkeys(
+            &[delegated, undelegated, missing],
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Same result as fetching them one by one
+    let mut expected = vec![];
+    for pubkey in [delegated, undelegated, missing] {
+        expected.push(
+            account_chain_snapshot_provider
+                .try_fetch_chain_snapshot_of_pubkey(&pubkey, None)
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(chain_snapshots, expected);
+    assert!(matches!(
+        chain_snapshots[0].chain_state,
+        AccountChainState::Delegated { .. }
+    ));
+    assert!(matches!(
+        chain_snapshots[1].chain_state,
+        AccountChainState::Undelegated { .. }
+    ));
+    assert_eq!(
+        chain_snapshots[2].chain_state,
+        AccountChainState::FeePayer {
+            lamports: 0,
+            owner: system_program::id(),
+        }
+    );
+}
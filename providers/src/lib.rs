@@ -1,5 +1,6 @@
 pub mod rpc_account_provider;
 pub mod rpc_provider_config;
 pub mod rpc_signature_status_provider;
+pub mod validator_registry;
 
 pub use conjunto_addresses::cluster::RpcCluster;
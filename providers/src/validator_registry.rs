@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::rpc_provider_config::RpcProviderConfig;
+
+/// Maps the authority of ephemeral validators, as found in the delegation
+/// records of accounts delegated to them, to their RPC/WS endpoints
+#[derive(Clone, Default)]
+pub struct ValidatorRegistry {
+    validators: HashMap<Pubkey, RpcProviderConfig>,
+}
+
+impl ValidatorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_validator(
+        mut self,
+        authority: Pubkey,
+        config: RpcProviderConfig,
+    ) -> Self {
+        self.insert(authority, config);
+        self
+    }
+
+    pub fn insert(
+        &mut self,
+        authority: Pubkey,
+        config: RpcProviderConfig,
+    ) -> Option<RpcProviderConfig> {
+        self.validators.insert(authority, config)
+    }
+
+    pub fn get(&self, authority: &Pubkey) -> Option<&RpcProviderConfig> {
+        self.validators.get(authority)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pubkey, &RpcProviderConfig)> {
+        self.validators.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conjunto_addresses::cluster::RpcCluster;
+
+    use super::*;
+
+    #[test]
+    fn test_validator_registry() {
+        let authority = Pubkey::new_unique();
+        let registry = ValidatorRegistry::new().with_validator(
+            authority,
+            RpcProviderConfig::new(
+                RpcCluster::Custom(
+                    "http://localhost:7799".to_string(),
+                    "ws://localhost:7800".to_string(),
+                ),
+                None,
+            ),
+        );
+
+        assert_eq!(registry.len(), 1);
+        assert_eq!(
+            registry.get(&authority).unwrap().url(),
+            "http://localhost:7799"
+        );
+        assert_eq!(
+            registry.get(&authority).unwrap().ws_url(),
+            "ws://localhost:7800"
+        );
+        assert!(registry.get(&Pubkey::new_unique()).is_none());
+    }
+}
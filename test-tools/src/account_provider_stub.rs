@@ -1,23 +1,43 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    task::{Context, Poll},
 };
 
 use async_trait::async_trait;
-use conjunto_core::{errors::CoreResult, AccountProvider};
+use conjunto_core::{
+    errors::{CoreError, CoreResult},
+    AccountProvider,
+};
 use solana_sdk::{account::Account, clock::Slot, pubkey::Pubkey};
 
 #[derive(Default)]
 pub struct AccountProviderStub {
     pub at_slot: Slot,
     pub accounts: Arc<RwLock<HashMap<Pubkey, Account>>>,
+    /// All pubkeys requested so far, in request order
+    pub fetched_pubkeys: Arc<RwLock<Vec<Pubkey>>>,
+    /// Requests including any of these pubkeys fail
+    pub failing_pubkeys: Arc<RwLock<HashSet<Pubkey>>>,
+    /// The most requests that were in flight at the same time
+    pub max_concurrent_requests: Arc<AtomicUsize>,
+    pub concurrent_requests: Arc<AtomicUsize>,
 }
 
 impl AccountProviderStub {
     pub fn add(&mut self, pubkey: Pubkey, account: Account) {
         self.accounts.write().unwrap().insert(pubkey, account);
     }
+    pub fn fail(&mut self, pubkey: Pubkey) {
+        self.failing_pubkeys.write().unwrap().insert(pubkey);
+    }
     fn get(&self, pubkey: &Pubkey) -> Option<Account> {
+        self.fetched_pubkeys.write().unwrap().push(*pubkey);
         self.accounts.read().unwrap().get(pubkey).cloned()
     }
 }
@@ -29,6 +49,9 @@ impl AccountProvider for AccountProviderStub {
         pubkey: &Pubkey,
         _min_context_slot: Option<Slot>,
     ) -> CoreResult<(Slot, Option<Account>)> {
+        if self.failing_pubkeys.read().unwrap().contains(pubkey) {
+            return Err(CoreError::FailedToGetAccountFromCluster);
+        }
         Ok((self.at_slot, self.get(pubkey)))
     }
 
@@ -37,9 +60,39 @@ impl AccountProvider for AccountProviderStub {
         pubkeys: &[Pubkey],
         _min_context_slot: Option<Slot>,
     ) -> CoreResult<(Slot, Vec<Option<Account>>)> {
+        let concurrent_requests =
+            self.concurrent_requests.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_concurrent_requests
+            .fetch_max(concurrent_requests, Ordering::SeqCst);
+        // Lets other requests start before this one completes
+        YieldNow(false).await;
+        self.concurrent_requests.fetch_sub(1, Ordering::SeqCst);
+
+        let failing_pubkeys = self.failing_pubkeys.read().unwrap();
+        if pubkeys
+            .iter()
+            .any(|pubkey| failing_pubkeys.contains(pubkey))
+        {
+            return Err(CoreError::FailedToGetAccountFromCluster);
+        }
         Ok((
             self.at_slot,
             pubkeys.iter().map(|pubkey| self.get(pubkey)).collect(),
         ))
     }
 }
+
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
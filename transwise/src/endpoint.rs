@@ -1,23 +1,315 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use conjunto_core::delegation_inconsistency::DelegationInconsistency;
+use dlp::consts::DELEGATION_PROGRAM_ID;
 use serde::{Deserialize, Serialize};
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{bpf_loader_upgradeable, pubkey::Pubkey};
 
-use crate::transaction_accounts_snapshot::TransactionAccountsSnapshot;
+use crate::{
+    endpoint_trace::{
+        pubkey_pairs_string, pubkey_string, pubkey_values_string,
+        pubkeys_string, EndpointKind,
+    },
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+};
 
-#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Returned to clients as error data, so pubkeys serialize as base58 strings
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum UnroutableReason {
+    #[serde(rename_all = "camelCase")]
     ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+        #[serde(with = "pubkeys_string")]
         writable_undelegated_pubkeys: Vec<Pubkey>,
+        /// Includes the ephemeral native accounts
+        #[serde(with = "pubkeys_string")]
         writable_delegated_pubkeys: Vec<Pubkey>,
     },
+    /// The payer is not among the writable accounts of the transaction
+    #[serde(rename_all = "camelCase")]
+    MissingFeePayer {
+        #[serde(with = "pubkey_string")]
+        payer: Pubkey,
+    },
+    /// Program accounts are writable without the transaction being a deployment
+    #[serde(rename_all = "camelCase")]
+    ContainsProgramAccountsAsWritable {
+        #[serde(with = "pubkeys_string")]
+        writable_program_pubkeys: Vec<Pubkey>,
+    },
+    /// Writable accounts are owned by the delegation program, but their
+    /// delegation state could not be verified
+    #[serde(rename_all = "camelCase")]
+    ContainsInconsistentDelegationsAsWritable {
+        #[serde(with = "pubkey_values_string")]
+        writable_inconsistent_pubkeys: Vec<(Pubkey, DelegationInconsistency)>,
+    },
+    /// Writable delegated accounts need to be delegated to the same validator
+    /// which also needs to hold the writable ephemeral native accounts
+    /// (pubkey, authority)
+    #[serde(rename_all = "camelCase")]
+    ContainsAccountsDelegatedToDifferentAuthorities {
+        #[serde(with = "pubkey_pairs_string")]
+        writable_delegated_authorities: Vec<(Pubkey, Pubkey)>,
+    },
+    /// The accounts require an endpoint on which some of the invoked programs
+    /// are not available according to their [ProgramPolicy]
+    #[serde(rename_all = "camelCase")]
+    InvokesProgramsUnavailableOnEndpoint {
+        endpoint: EndpointKind,
+        #[serde(with = "pubkeys_string")]
+        unavailable_program_ids: Vec<Pubkey>,
+    },
+    /// Durable nonce transaction whose accounts require the ephemeral validator
+    #[serde(rename_all = "camelCase")]
+    ContainsDurableNonceForEphemeral {
+        #[serde(with = "pubkey_string")]
+        nonce_account: Pubkey,
+    },
+    /// Chain transaction reading delegated accounts whose chain state may be
+    /// outdated, rejected according to the [ReadonlyDelegatedPolicy]
+    /// (pubkey, authority)
+    #[serde(rename_all = "camelCase")]
+    ReadsDelegatedAccountsOnChain {
+        #[serde(with = "pubkey_pairs_string")]
+        readonly_delegated_authorities: Vec<(Pubkey, Pubkey)>,
+        /// Readonly accounts whose chain state is unknown, they may be
+        /// delegated as well
+        #[serde(default, with = "pubkeys_string")]
+        readonly_unknown_pubkeys: Vec<Pubkey>,
+    },
+}
+
+impl fmt::Display for UnroutableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use UnroutableReason::*;
+        match self {
+            ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                writable_undelegated_pubkeys,
+                writable_delegated_pubkeys,
+            } => write!(
+                f,
+                "Transaction contains both undelegated ({}) and delegated ({}) accounts as writable",
+                join_pubkeys(writable_undelegated_pubkeys.iter()),
+                join_pubkeys(writable_delegated_pubkeys.iter()),
+            ),
+            MissingFeePayer { payer } => write!(
+                f,
+                "Transaction fee payer {} is not writable",
+                payer
+            ),
+            ContainsProgramAccountsAsWritable {
+                writable_program_pubkeys,
+            } => write!(
+                f,
+                "Transaction contains program accounts as writable ({})",
+                join_pubkeys(writable_program_pubkeys.iter()),
+            ),
+            ContainsInconsistentDelegationsAsWritable {
+                writable_inconsistent_pubkeys,
+            } => write!(
+                f,
+                "Transaction contains writable accounts with inconsistent delegation ({})",
+                writable_inconsistent_pubkeys
+                    .iter()
+                    .map(|(pubkey, inconsistency)| format!(
+                        "{}: {:?}",
+                        pubkey, inconsistency
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            ContainsAccountsDelegatedToDifferentAuthorities {
+                writable_delegated_authorities,
+            } => write!(
+                f,
+                "Transaction contains writable accounts delegated to different authorities ({})",
+                writable_delegated_authorities
+                    .iter()
+                    .map(|(pubkey, authority)| format!(
+                        "{}: {}",
+                        pubkey, authority
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            InvokesProgramsUnavailableOnEndpoint {
+                endpoint,
+                unavailable_program_ids,
+            } => write!(
+                f,
+                "Transaction needs to run on {:?} but invokes programs unavailable there ({})",
+                endpoint,
+                join_pubkeys(unavailable_program_ids.iter()),
+            ),
+            ContainsDurableNonceForEphemeral { nonce_account } => write!(
+                f,
+                "Transaction uses durable nonce account {} but needs to run on the ephemeral validator",
+                nonce_account
+            ),
+            ReadsDelegatedAccountsOnChain {
+                readonly_delegated_authorities,
+                readonly_unknown_pubkeys,
+            } => {
+                write!(
+                    f,
+                    "Transaction needs to run on chain but reads delegated accounts whose chain state may be outdated ({})",
+                    readonly_delegated_authorities
+                        .iter()
+                        .map(|(pubkey, authority)| format!(
+                            "{}: {}",
+                            pubkey, authority
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                )?;
+                if !readonly_unknown_pubkeys.is_empty() {
+                    write!(
+                        f,
+                        " or accounts of unknown chain state ({})",
+                        join_pubkeys(readonly_unknown_pubkeys.iter()),
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The authority all accounts are delegated to, `None` if there are no
+/// accounts or they are delegated to different validators
+fn single_authority(authorities: &[(Pubkey, Pubkey)]) -> Option<Pubkey> {
+    let (_, authority) = authorities.first()?;
+    authorities
+        .iter()
+        .all(|(_, other)| other == authority)
+        .then_some(*authority)
+}
+
+fn join_pubkeys<'a>(pubkeys: impl Iterator<Item = &'a Pubkey>) -> String {
+    pubkeys
+        .map(|pubkey| pubkey.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Where a program is deployed and thus which endpoint can run it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ProgramPolicy {
+    ChainOnly,
+    EphemeralOnly,
+    #[default]
+    Either,
+}
+
+impl ProgramPolicy {
+    pub fn is_available_on(&self, endpoint: EndpointKind) -> bool {
+        match (self, endpoint) {
+            (ProgramPolicy::ChainOnly, EndpointKind::Ephemeral) => false,
+            (ProgramPolicy::EphemeralOnly, EndpointKind::Chain) => false,
+            _ => true,
+        }
+    }
+}
+
+/// How to handle chain transactions reading delegated accounts. These see the
+/// state of the last commit instead of the latest state inside the ephemeral
+/// validator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ReadonlyDelegatedPolicy {
+    /// Route to chain and report the accounts in [Endpoint::warnings]
+    #[default]
+    Warn,
+    /// Treat the transaction as unroutable
+    Reject,
+    /// Route to the ephemeral validator the accounts are delegated to, rejects
+    /// the transaction if it writes undelegated accounts or the accounts are
+    /// delegated to different validators
+    RerouteToEphemeral,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointConfig {
+    /// Transactions invoking any of these programs always run on chain, i.e.
+    /// the delegate, undelegate and commit instructions of the delegation program.
+    /// Also matched against the account keys since programs invoked via CPI
+    /// only show up there.
+    pub chain_only_program_ids: HashSet<Pubkey>,
+    /// Programs not found here are available on either endpoint
+    pub program_policies: HashMap<Pubkey, ProgramPolicy>,
+    /// Route durable nonce transactions to the ephemeral validator, requires
+    /// it to advance nonce accounts it clones from chain
+    pub allow_durable_nonce_on_ephemeral: bool,
+    /// Applies to transactions that need to run on chain and don't invoke a
+    /// chain only program
+    pub readonly_delegated_policy: ReadonlyDelegatedPolicy,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            chain_only_program_ids: HashSet::from([DELEGATION_PROGRAM_ID]),
+            program_policies: HashMap::new(),
+            allow_durable_nonce_on_ephemeral: false,
+            readonly_delegated_policy: ReadonlyDelegatedPolicy::default(),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+impl EndpointConfig {
+    pub fn program_policy(&self, program_id: &Pubkey) -> ProgramPolicy {
+        self.program_policies
+            .get(program_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The readonly delegated accounts with their authority and the readonly
+    /// accounts of unknown chain state a chain transaction may see outdated.
+    /// Transactions invoking a chain only program are exempt since they need
+    /// to run on chain regardless, see [ReadonlyDelegatedPolicy].
+    pub(crate) fn readonly_delegated_exposure(
+        &self,
+        transaction_accounts_snapshot: &TransactionAccountsSnapshot,
+    ) -> (Vec<(Pubkey, Pubkey)>, Vec<Pubkey>) {
+        if self.invokes_chain_only_program(transaction_accounts_snapshot) {
+            return (vec![], vec![]);
+        }
+        (
+            transaction_accounts_snapshot.readonly_delegated_authorities(),
+            transaction_accounts_snapshot.readonly_unknown.clone(),
+        )
+    }
+
+    fn invokes_chain_only_program(
+        &self,
+        transaction_accounts_snapshot: &TransactionAccountsSnapshot,
+    ) -> bool {
+        let account_keys = transaction_accounts_snapshot
+            .readonly
+            .iter()
+            .chain(&transaction_accounts_snapshot.writable)
+            .map(|chain_snapshot| &chain_snapshot.pubkey)
+            .chain(&transaction_accounts_snapshot.readonly_unknown);
+        transaction_accounts_snapshot
+            .program_ids
+            .iter()
+            .chain(account_keys)
+            .any(|pubkey| self.chain_only_program_ids.contains(pubkey))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Endpoint {
     Chain {
         transaction_accounts_snapshot: TransactionAccountsSnapshot,
     },
     Ephemeral {
         transaction_accounts_snapshot: TransactionAccountsSnapshot,
+        /// The validator all writable delegated accounts are delegated to
+        authority: Pubkey,
     },
     Unroutable {
         transaction_accounts_snapshot: TransactionAccountsSnapshot,
@@ -36,6 +328,14 @@ impl Endpoint {
         matches!(self, Endpoint::Unroutable { .. })
     }
 
+    /// The validator the transaction needs to be sent to if it is ephemeral
+    pub fn authority(&self) -> Option<&Pubkey> {
+        match self {
+            Endpoint::Ephemeral { authority, .. } => Some(authority),
+            _ => None,
+        }
+    }
+
     pub fn transaction_accounts_snapshot(
         &self,
     ) -> &TransactionAccountsSnapshot {
@@ -54,16 +354,96 @@ impl Endpoint {
             } => transaction_accounts_snapshot,
         }
     }
+
+    pub fn into_transaction_accounts_snapshot(
+        self,
+    ) -> TransactionAccountsSnapshot {
+        match self {
+            Endpoint::Chain {
+                transaction_accounts_snapshot,
+                ..
+            } => transaction_accounts_snapshot,
+            Endpoint::Ephemeral {
+                transaction_accounts_snapshot,
+                ..
+            } => transaction_accounts_snapshot,
+            Endpoint::Unroutable {
+                transaction_accounts_snapshot,
+                ..
+            } => transaction_accounts_snapshot,
+        }
+    }
 }
 
 impl Endpoint {
     pub fn from(
         transaction_accounts_snapshot: TransactionAccountsSnapshot,
     ) -> Endpoint {
+        Self::from_with_config(
+            transaction_accounts_snapshot,
+            &EndpointConfig::default(),
+        )
+    }
+
+    pub fn from_with_config(
+        transaction_accounts_snapshot: TransactionAccountsSnapshot,
+        config: &EndpointConfig,
+    ) -> Endpoint {
+        let endpoint = Self::apply_readonly_delegated_policy(
+            Self::from_accounts(transaction_accounts_snapshot, config),
+            config,
+        );
+        match Self::unroutable_reason_of_endpoint(&endpoint, config) {
+            Some(reason) => Endpoint::Unroutable {
+                reason,
+                transaction_accounts_snapshot: endpoint
+                    .into_transaction_accounts_snapshot(),
+            },
+            None => endpoint,
+        }
+    }
+
+    /// Determines the endpoint from the writable accounts, only considering
+    /// the chain only programs
+    fn from_accounts(
+        transaction_accounts_snapshot: TransactionAccountsSnapshot,
+        config: &EndpointConfig,
+    ) -> Endpoint {
+        if let Some(reason) =
+            Self::unroutable_reason_of_accounts(&transaction_accounts_snapshot)
+        {
+            return Endpoint::Unroutable {
+                transaction_accounts_snapshot,
+                reason,
+            };
+        }
+
+        // Instructions of chain only programs (i.e. undelegating an account)
+        // need to run on chain even if they write to delegated accounts
+        if config.invokes_chain_only_program(&transaction_accounts_snapshot) {
+            return Endpoint::Chain {
+                transaction_accounts_snapshot,
+            };
+        }
+
+        if let Some(reason) = Self::unroutable_reason_of_delegations(
+            &transaction_accounts_snapshot,
+        ) {
+            return Endpoint::Unroutable {
+                transaction_accounts_snapshot,
+                reason,
+            };
+        }
+
         let writable_undelegated_pubkeys =
             transaction_accounts_snapshot.writable_undelegated_pubkeys();
-        let writable_delegated_pubkeys =
-            transaction_accounts_snapshot.writable_delegated_pubkeys();
+        // Ephemeral native accounts need the ephemeral validator as well
+        let writable_ephemeral_authorities =
+            transaction_accounts_snapshot.writable_ephemeral_authorities();
+        let writable_delegated_pubkeys = writable_ephemeral_authorities
+            .iter()
+            .map(|(pubkey, _)| *pubkey)
+            .collect::<Vec<_>>();
 
         let has_writable_undelegated = !writable_undelegated_pubkeys.is_empty();
         let has_writable_delegated = !writable_delegated_pubkeys.is_empty();
@@ -86,9 +466,193 @@ impl Endpoint {
                 transaction_accounts_snapshot,
             },
             // If there are only delegated accounts as writable, its for the ephemeral
-            (false, true) => Endpoint::Ephemeral {
+            // validator they are delegated to, as long as that is the same for all
+            (false, true) => {
+                match single_authority(&writable_ephemeral_authorities) {
+                    Some(authority) => Endpoint::Ephemeral {
+                        transaction_accounts_snapshot,
+                        authority,
+                    },
+                    None => Endpoint::Unroutable {
+                        transaction_accounts_snapshot,
+                        reason: UnroutableReason::ContainsAccountsDelegatedToDifferentAuthorities {
+                            writable_delegated_authorities:
+                                writable_ephemeral_authorities,
+                        },
+                    },
+                }
+            }
+        }
+    }
+
+    /// Chain transactions reading delegated accounts see the state of their
+    /// last commit, the policy decides if they are still sent to chain
+    fn apply_readonly_delegated_policy(
+        endpoint: Endpoint,
+        config: &EndpointConfig,
+    ) -> Endpoint {
+        if !endpoint.is_chain() {
+            return endpoint;
+        }
+        let transaction_accounts_snapshot =
+            endpoint.into_transaction_accounts_snapshot();
+        let (readonly_delegated_authorities, readonly_unknown_pubkeys) =
+            config.readonly_delegated_exposure(&transaction_accounts_snapshot);
+        if readonly_delegated_authorities.is_empty()
+            && readonly_unknown_pubkeys.is_empty()
+        {
+            return Endpoint::Chain {
                 transaction_accounts_snapshot,
+            };
+        }
+
+        // We cannot tell where accounts of unknown chain state live
+        let reroute_authority = if transaction_accounts_snapshot
+            .writable_undelegated_pubkeys()
+            .is_empty()
+            && readonly_unknown_pubkeys.is_empty()
+        {
+            single_authority(&readonly_delegated_authorities)
+        } else {
+            None
+        };
+        match (config.readonly_delegated_policy, reroute_authority) {
+            (ReadonlyDelegatedPolicy::Warn, _) => Endpoint::Chain {
+                transaction_accounts_snapshot,
+            },
+            (ReadonlyDelegatedPolicy::RerouteToEphemeral, Some(authority)) => {
+                Endpoint::Ephemeral {
+                    transaction_accounts_snapshot,
+                    authority,
+                }
             }
+            (
+                ReadonlyDelegatedPolicy::Reject
+                | ReadonlyDelegatedPolicy::RerouteToEphemeral,
+                _,
+            ) => Endpoint::Unroutable {
+                transaction_accounts_snapshot,
+                reason: UnroutableReason::ReadsDelegatedAccountsOnChain {
+                    readonly_delegated_authorities,
+                    readonly_unknown_pubkeys,
+                },
+            },
         }
     }
+
+    /// Checks if the transaction can run on the endpoint the accounts take us
+    fn unroutable_reason_of_endpoint(
+        endpoint: &Endpoint,
+        config: &EndpointConfig,
+    ) -> Option<UnroutableReason> {
+        let transaction_accounts_snapshot =
+            endpoint.transaction_accounts_snapshot();
+
+        // The programs need to be deployed where the accounts take us
+        let unavailable_program_ids = transaction_accounts_snapshot
+            .program_ids
+            .iter()
+            .filter(|program_id| {
+                !config
+                    .program_policy(program_id)
+                    .is_available_on(endpoint.kind())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if !unavailable_program_ids.is_empty() {
+            return Some(
+                UnroutableReason::InvokesProgramsUnavailableOnEndpoint {
+                    endpoint: endpoint.kind(),
+                    unavailable_program_ids,
+                },
+            );
+        }
+
+        // Nonce accounts live on chain, the ephemeral validator can only
+        // advance them if it supports cloning them
+        if let Some(nonce_account) =
+            &transaction_accounts_snapshot.nonce_account
+        {
+            if endpoint.is_ephemeral()
+                && !config.allow_durable_nonce_on_ephemeral
+            {
+                return Some(
+                    UnroutableReason::ContainsDurableNonceForEphemeral {
+                        nonce_account: nonce_account.pubkey,
+                    },
+                );
+            }
+        }
+
+        None
+    }
+
+    /// Checks the writable accounts for problems that prevent routing the
+    /// transaction independently of where the accounts are delegated
+    fn unroutable_reason_of_accounts(
+        transaction_accounts_snapshot: &TransactionAccountsSnapshot,
+    ) -> Option<UnroutableReason> {
+        let writable = &transaction_accounts_snapshot.writable;
+
+        let payer = transaction_accounts_snapshot.payer;
+        if !writable
+            .iter()
+            .any(|chain_snapshot| chain_snapshot.pubkey == payer)
+        {
+            return Some(UnroutableReason::MissingFeePayer { payer });
+        }
+
+        // Program deployments and upgrades legitimately write to program accounts
+        let is_deployment = transaction_accounts_snapshot
+            .program_ids
+            .contains(&bpf_loader_upgradeable::id());
+        let writable_program_pubkeys = writable
+            .iter()
+            .filter(|chain_snapshot| {
+                chain_snapshot
+                    .chain_state
+                    .account()
+                    .map(|account| account.executable)
+                    .unwrap_or(false)
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect::<Vec<_>>();
+        if !is_deployment && !writable_program_pubkeys.is_empty() {
+            return Some(UnroutableReason::ContainsProgramAccountsAsWritable {
+                writable_program_pubkeys,
+            });
+        }
+
+        None
+    }
+
+    /// Checks the delegation state of the writable accounts for problems that
+    /// prevent routing the transaction to an ephemeral validator
+    fn unroutable_reason_of_delegations(
+        transaction_accounts_snapshot: &TransactionAccountsSnapshot,
+    ) -> Option<UnroutableReason> {
+        let writable_inconsistent_pubkeys =
+            transaction_accounts_snapshot.writable_inconsistent_pubkeys();
+        if !writable_inconsistent_pubkeys.is_empty() {
+            return Some(
+                UnroutableReason::ContainsInconsistentDelegationsAsWritable {
+                    writable_inconsistent_pubkeys,
+                },
+            );
+        }
+
+        let writable_delegated_authorities =
+            transaction_accounts_snapshot.writable_ephemeral_authorities();
+        if !writable_delegated_authorities.is_empty()
+            && single_authority(&writable_delegated_authorities).is_none()
+        {
+            return Some(
+                UnroutableReason::ContainsAccountsDelegatedToDifferentAuthorities {
+                    writable_delegated_authorities,
+                },
+            );
+        }
+
+        None
+    }
 }
@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use conjunto_lockbox::{
+    account_chain_snapshot_shared::AccountChainSnapshotShared,
+    account_chain_state::AccountChainState,
+};
+use solana_sdk::{hash::Hash, pubkey::Pubkey};
+
+use crate::{
+    endpoint::Endpoint, endpoint_trace::AccountChainStateKind,
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+};
+
+/// The state of an account and the validator it is delegated to if any
+type DelegationState = (AccountChainStateKind, Option<Pubkey>);
+
+struct CachedEndpoint {
+    endpoint: Endpoint,
+    cached_at: Instant,
+}
+
+struct SeenDelegationState {
+    state: DelegationState,
+    seen_at: Instant,
+}
+
+struct Entries {
+    endpoints: HashMap<Hash, CachedEndpoint>,
+    /// Latest delegation state of the accounts of cached endpoints
+    delegation_states: HashMap<Pubkey, SeenDelegationState>,
+    last_sweep: Instant,
+}
+
+impl Entries {
+    /// Endpoints are fresh until they expire or another transaction saw any
+    /// of their accounts in a different delegation state
+    fn is_fresh(&self, cached: &CachedEndpoint, ttl: Duration) -> bool {
+        cached.cached_at.elapsed() < ttl
+            && chain_snapshots(cached.endpoint.transaction_accounts_snapshot())
+                .all(|chain_snapshot| {
+                    match self.delegation_states.get(&chain_snapshot.pubkey) {
+                        Some(seen) => {
+                            seen.state == delegation_state(chain_snapshot)
+                        }
+                        None => true,
+                    }
+                })
+    }
+
+    /// Removes expired endpoints and delegation states, a delegation state
+    /// older than the TTL is only relevant for expired endpoints
+    fn sweep(&mut self, ttl: Duration) {
+        self.endpoints
+            .retain(|_, cached| cached.cached_at.elapsed() < ttl);
+        self.delegation_states
+            .retain(|_, seen| seen.seen_at.elapsed() < ttl);
+        self.last_sweep = Instant::now();
+    }
+}
+
+/// Caches the endpoint of transactions by message hash for a short time, i.e.
+/// while clients retry sending the same transaction.
+/// Outdated endpoints are evicted when they are looked up, expired ones are
+/// swept at most once per TTL.
+pub struct EndpointCache {
+    ttl: Duration,
+    entries: RwLock<Entries>,
+}
+
+impl EndpointCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: RwLock::new(Entries {
+                endpoints: HashMap::new(),
+                delegation_states: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn get(&self, message_hash: &Hash) -> Option<Endpoint> {
+        {
+            let entries =
+                self.entries.read().expect("RwLock of entries poisoned");
+            let cached = entries.endpoints.get(message_hash)?;
+            if entries.is_fresh(cached, self.ttl) {
+                return Some(cached.endpoint.clone());
+            }
+        }
+        let mut entries =
+            self.entries.write().expect("RwLock of entries poisoned");
+        // Another thread may have cached a fresh endpoint in the meantime
+        let is_stale = match entries.endpoints.get(message_hash) {
+            Some(cached) => !entries.is_fresh(cached, self.ttl),
+            None => false,
+        };
+        if is_stale {
+            entries.endpoints.remove(message_hash);
+        }
+        None
+    }
+
+    /// Caches the endpoint and records the delegation state of its accounts,
+    /// endpoints that saw any of them in a different state are outdated
+    pub fn insert(&self, message_hash: Hash, endpoint: Endpoint) {
+        let mut entries =
+            self.entries.write().expect("RwLock of entries poisoned");
+        if entries.last_sweep.elapsed() >= self.ttl {
+            entries.sweep(self.ttl);
+        }
+        let now = Instant::now();
+        for chain_snapshot in
+            chain_snapshots(endpoint.transaction_accounts_snapshot())
+        {
+            entries.delegation_states.insert(
+                chain_snapshot.pubkey,
+                SeenDelegationState {
+                    state: delegation_state(chain_snapshot),
+                    seen_at: now,
+                },
+            );
+        }
+        entries.endpoints.insert(
+            message_hash,
+            CachedEndpoint {
+                endpoint,
+                cached_at: now,
+            },
+        );
+    }
+
+    /// Removes all endpoints involving the account, i.e. after its delegation
+    /// state changed
+    pub fn invalidate_pubkey(&self, pubkey: &Pubkey) {
+        let mut entries =
+            self.entries.write().expect("RwLock of entries poisoned");
+        entries.endpoints.retain(|_, cached| {
+            !chain_snapshots(cached.endpoint.transaction_accounts_snapshot())
+                .any(|chain_snapshot| chain_snapshot.pubkey == *pubkey)
+        });
+        entries.delegation_states.remove(pubkey);
+    }
+
+    pub fn clear(&self) {
+        let mut entries =
+            self.entries.write().expect("RwLock of entries poisoned");
+        entries.endpoints.clear();
+        entries.delegation_states.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .read()
+            .expect("RwLock of entries poisoned")
+            .endpoints
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn chain_snapshots(
+    snapshot: &TransactionAccountsSnapshot,
+) -> impl Iterator<Item = &AccountChainSnapshotShared> {
+    snapshot
+        .writable
+        .iter()
+        .chain(snapshot.readonly.iter())
+        .chain(snapshot.nonce_account.iter())
+}
+
+fn delegation_state(
+    chain_snapshot: &AccountChainSnapshotShared,
+) -> DelegationState {
+    let authority = match &chain_snapshot.chain_state {
+        AccountChainState::Delegated {
+            delegation_record, ..
+        } => Some(delegation_record.authority),
+        _ => None,
+    };
+    ((&chain_snapshot.chain_state).into(), authority)
+}
@@ -0,0 +1,361 @@
+use conjunto_core::delegation_inconsistency::DelegationInconsistency;
+use conjunto_lockbox::{
+    account_chain_snapshot_shared::AccountChainSnapshotShared,
+    account_chain_state::AccountChainState,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    endpoint::{Endpoint, EndpointConfig, UnroutableReason},
+    endpoint_warning::EndpointWarning,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EndpointKind {
+    Chain,
+    Ephemeral,
+    Unroutable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountRole {
+    Payer,
+    Writable,
+    Readonly,
+    /// Nonce account advanced by a durable nonce transaction
+    Nonce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountChainStateKind {
+    FeePayer,
+    Undelegated,
+    Delegated,
+}
+
+impl From<&AccountChainState> for AccountChainStateKind {
+    fn from(chain_state: &AccountChainState) -> Self {
+        match chain_state {
+            AccountChainState::FeePayer { .. } => Self::FeePayer,
+            AccountChainState::Undelegated { .. } => Self::Undelegated,
+            AccountChainState::Delegated { .. } => Self::Delegated,
+        }
+    }
+}
+
+/// How an account affected the routing decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountInfluence {
+    /// The account can be used on either endpoint
+    Neutral,
+    /// The account is written to and only lives on chain
+    RequiresChain,
+    /// The account is written to and is delegated to an ephemeral validator
+    /// or only exists there
+    RequiresEphemeral,
+    /// The account is one of the reasons the transaction is unroutable
+    PreventsRouting,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountTrace {
+    #[serde(with = "pubkey_string")]
+    pub pubkey: Pubkey,
+    pub role: AccountRole,
+    pub chain_state: AccountChainStateKind,
+    pub delegation_inconsistency: Option<DelegationInconsistency>,
+    pub influence: AccountInfluence,
+}
+
+/// Explains why a transaction was routed to an endpoint, one entry per account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointTrace {
+    pub endpoint: EndpointKind,
+    pub reason: Option<String>,
+    pub accounts: Vec<AccountTrace>,
+    pub warnings: Vec<EndpointWarning>,
+}
+
+impl Endpoint {
+    pub fn kind(&self) -> EndpointKind {
+        match self {
+            Endpoint::Chain { .. } => EndpointKind::Chain,
+            Endpoint::Ephemeral { .. } => EndpointKind::Ephemeral,
+            Endpoint::Unroutable { .. } => EndpointKind::Unroutable,
+        }
+    }
+
+    /// Builds the per account explanation of the routing decision
+    pub fn trace(&self) -> EndpointTrace {
+        self.trace_with_config(&EndpointConfig::default())
+    }
+
+    /// Same as [Self::trace] with the warnings of the given config, see
+    /// [Self::warnings_with_config]
+    pub fn trace_with_config(&self, config: &EndpointConfig) -> EndpointTrace {
+        let (reason, offending_pubkeys) = match self {
+            Endpoint::Unroutable { reason, .. } => {
+                (Some(reason.to_string()), reason.offending_pubkeys())
+            }
+            _ => (None, vec![]),
+        };
+
+        let snapshot = self.transaction_accounts_snapshot();
+        let writable = snapshot.writable.iter().map(|chain_snapshot| {
+            let role = if chain_snapshot.pubkey == snapshot.payer {
+                AccountRole::Payer
+            } else {
+                AccountRole::Writable
+            };
+            (chain_snapshot, role)
+        });
+        let readonly = snapshot
+            .readonly
+            .iter()
+            .map(|chain_snapshot| (chain_snapshot, AccountRole::Readonly));
+        let nonce = snapshot
+            .nonce_account
+            .iter()
+            .map(|chain_snapshot| (chain_snapshot, AccountRole::Nonce));
+
+        let accounts = writable
+            .chain(readonly)
+            .chain(nonce)
+            .map(|(chain_snapshot, role)| {
+                let is_ephemeral_native = snapshot
+                    .writable_ephemeral_native
+                    .iter()
+                    .any(|(pubkey, _)| *pubkey == chain_snapshot.pubkey);
+                let influence =
+                    if offending_pubkeys.contains(&chain_snapshot.pubkey) {
+                        AccountInfluence::PreventsRouting
+                    } else if is_ephemeral_native {
+                        AccountInfluence::RequiresEphemeral
+                    } else {
+                        influence_of_account(chain_snapshot, role)
+                    };
+                AccountTrace {
+                    pubkey: chain_snapshot.pubkey,
+                    role,
+                    chain_state: (&chain_snapshot.chain_state).into(),
+                    delegation_inconsistency: delegation_inconsistency(
+                        chain_snapshot,
+                    ),
+                    influence,
+                }
+            })
+            .collect();
+
+        EndpointTrace {
+            endpoint: self.kind(),
+            reason,
+            accounts,
+            warnings: self.warnings_with_config(config),
+        }
+    }
+}
+
+impl UnroutableReason {
+    /// The accounts that caused the transaction to be unroutable
+    pub fn offending_pubkeys(&self) -> Vec<Pubkey> {
+        use UnroutableReason::*;
+        match self {
+            ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                writable_undelegated_pubkeys,
+                writable_delegated_pubkeys,
+            } => writable_undelegated_pubkeys
+                .iter()
+                .chain(writable_delegated_pubkeys.iter())
+                .cloned()
+                .collect(),
+            MissingFeePayer { payer } => vec![*payer],
+            ContainsProgramAccountsAsWritable {
+                writable_program_pubkeys,
+            } => writable_program_pubkeys.clone(),
+            ContainsInconsistentDelegationsAsWritable {
+                writable_inconsistent_pubkeys,
+            } => writable_inconsistent_pubkeys
+                .iter()
+                .map(|(pubkey, _)| *pubkey)
+                .collect(),
+            ContainsAccountsDelegatedToDifferentAuthorities {
+                writable_delegated_authorities,
+            } => writable_delegated_authorities
+                .iter()
+                .map(|(pubkey, _)| *pubkey)
+                .collect(),
+            InvokesProgramsUnavailableOnEndpoint {
+                unavailable_program_ids,
+                ..
+            } => unavailable_program_ids.clone(),
+            ContainsDurableNonceForEphemeral { nonce_account } => {
+                vec![*nonce_account]
+            }
+            ReadsDelegatedAccountsOnChain {
+                readonly_delegated_authorities,
+                readonly_unknown_pubkeys,
+            } => readonly_delegated_authorities
+                .iter()
+                .map(|(pubkey, _)| *pubkey)
+                .chain(readonly_unknown_pubkeys.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+fn influence_of_account(
+    chain_snapshot: &AccountChainSnapshotShared,
+    role: AccountRole,
+) -> AccountInfluence {
+    match (role, &chain_snapshot.chain_state) {
+        (AccountRole::Readonly | AccountRole::Nonce, _) => {
+            AccountInfluence::Neutral
+        }
+        (_, AccountChainState::FeePayer { .. }) => AccountInfluence::Neutral,
+        (_, AccountChainState::Undelegated { .. }) => {
+            AccountInfluence::RequiresChain
+        }
+        (_, AccountChainState::Delegated { .. }) => {
+            AccountInfluence::RequiresEphemeral
+        }
+    }
+}
+
+/// Only accounts owned by the delegation program whose delegation could not
+/// be verified are inconsistent, all other undelegated accounts simply have a
+/// different owner
+fn delegation_inconsistency(
+    chain_snapshot: &AccountChainSnapshotShared,
+) -> Option<DelegationInconsistency> {
+    match &chain_snapshot.chain_state {
+        AccountChainState::Undelegated {
+            delegation_inconsistency,
+            ..
+        } if *delegation_inconsistency
+            != DelegationInconsistency::AccountInvalidOwner =>
+        {
+            Some(delegation_inconsistency.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Serializes pubkeys as base58 strings to keep the trace readable
+pub(crate) mod pubkey_string {
+    use std::str::FromStr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(
+        pubkey: &Pubkey,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(pubkey)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Pubkey, D::Error> {
+        let pubkey = String::deserialize(deserializer)?;
+        Pubkey::from_str(&pubkey).map_err(Error::custom)
+    }
+}
+
+/// Serializes lists of pubkeys as base58 strings
+pub(crate) mod pubkeys_string {
+    use std::str::FromStr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(
+        pubkeys: &[Pubkey],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(pubkeys.iter().map(|pubkey| pubkey.to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Pubkey>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pubkey| Pubkey::from_str(pubkey).map_err(Error::custom))
+            .collect()
+    }
+}
+
+/// Serializes lists of (pubkey, authority) pairs as base58 strings
+pub(crate) mod pubkey_pairs_string {
+    use std::str::FromStr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(
+        pairs: &[(Pubkey, Pubkey)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            pairs
+                .iter()
+                .map(|(pubkey, other)| (pubkey.to_string(), other.to_string())),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(Pubkey, Pubkey)>, D::Error> {
+        Vec::<(String, String)>::deserialize(deserializer)?
+            .iter()
+            .map(|(pubkey, other)| {
+                Ok((
+                    Pubkey::from_str(pubkey).map_err(Error::custom)?,
+                    Pubkey::from_str(other).map_err(Error::custom)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Serializes lists of (pubkey, value) pairs with the pubkeys as base58
+/// strings
+pub(crate) mod pubkey_values_string {
+    use std::str::FromStr;
+
+    use serde::{
+        de::{DeserializeOwned, Error},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer, T: Serialize>(
+        pairs: &[(Pubkey, T)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            pairs
+                .iter()
+                .map(|(pubkey, value)| (pubkey.to_string(), value)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: DeserializeOwned>(
+        deserializer: D,
+    ) -> Result<Vec<(Pubkey, T)>, D::Error> {
+        Vec::<(String, T)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(pubkey, value)| {
+                Ok((Pubkey::from_str(&pubkey).map_err(Error::custom)?, value))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use conjunto_core::delegation_record::CommitFrequency;
+use conjunto_lockbox::account_chain_state::AccountChainState;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+use crate::{
+    endpoint::{Endpoint, EndpointConfig},
+    endpoint_trace::pubkey_string,
+};
+
+/// Problems with a routing decision that don't prevent sending the transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EndpointWarning {
+    /// The chain transaction reads a delegated account and thus sees its
+    /// state as of the last commit of the ephemeral validator
+    #[serde(rename_all = "camelCase")]
+    ReadsDelegatedAccountOnChain {
+        #[serde(with = "pubkey_string")]
+        pubkey: Pubkey,
+        #[serde(with = "pubkey_string")]
+        authority: Pubkey,
+        commit_frequency: CommitFrequency,
+        /// Slot at which the account was delegated, the chain state is at
+        /// least as recent as that
+        delegation_slot: Slot,
+        /// Slot at which the chain state of the account was fetched
+        at_slot: Slot,
+    },
+    /// The chain transaction reads an account whose chain state could not be
+    /// fetched, it may be delegated as well
+    #[serde(rename_all = "camelCase")]
+    ReadsUnknownAccountOnChain {
+        #[serde(with = "pubkey_string")]
+        pubkey: Pubkey,
+    },
+}
+
+impl fmt::Display for EndpointWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EndpointWarning::ReadsDelegatedAccountOnChain {
+                pubkey,
+                authority,
+                commit_frequency,
+                delegation_slot,
+                at_slot,
+            } => write!(
+                f,
+                "Transaction reads account {} delegated to {} on chain, its state is as of the last commit at most {} slots ago (delegated at slot {}, commit frequency {})",
+                pubkey,
+                authority,
+                at_slot.saturating_sub(*delegation_slot),
+                delegation_slot,
+                commit_frequency,
+            ),
+            EndpointWarning::ReadsUnknownAccountOnChain { pubkey } => write!(
+                f,
+                "Transaction reads account {} on chain whose chain state is unknown, it may be delegated",
+                pubkey,
+            ),
+        }
+    }
+}
+
+impl EndpointWarning {
+    /// Upper bound of how many slots ago the read delegated account was last
+    /// committed, the exact slot of the last commit is not known
+    pub fn slots_since_delegation(&self) -> Option<Slot> {
+        match self {
+            EndpointWarning::ReadsDelegatedAccountOnChain {
+                delegation_slot,
+                at_slot,
+                ..
+            } => Some(at_slot.saturating_sub(*delegation_slot)),
+            EndpointWarning::ReadsUnknownAccountOnChain { .. } => None,
+        }
+    }
+}
+
+impl Endpoint {
+    /// Warnings about the routing decision with the default config, see
+    /// [Self::warnings_with_config]
+    pub fn warnings(&self) -> Vec<EndpointWarning> {
+        self.warnings_with_config(&EndpointConfig::default())
+    }
+
+    /// Warnings about the routing decision, so far only chain transactions
+    /// reading accounts that may be delegated result in warnings.
+    /// The same transactions are exempt as from the readonly delegated policy.
+    pub fn warnings_with_config(
+        &self,
+        config: &EndpointConfig,
+    ) -> Vec<EndpointWarning> {
+        if !self.is_chain() {
+            return vec![];
+        }
+        let snapshot = self.transaction_accounts_snapshot();
+        let (readonly_delegated_authorities, readonly_unknown_pubkeys) =
+            config.readonly_delegated_exposure(snapshot);
+        let delegated = snapshot.readonly.iter().filter_map(|chain_snapshot| {
+            match &chain_snapshot.chain_state {
+                AccountChainState::Delegated {
+                    delegation_record, ..
+                } if readonly_delegated_authorities.contains(&(
+                    chain_snapshot.pubkey,
+                    delegation_record.authority,
+                )) =>
+                {
+                    Some(EndpointWarning::ReadsDelegatedAccountOnChain {
+                        pubkey: chain_snapshot.pubkey,
+                        authority: delegation_record.authority,
+                        commit_frequency: delegation_record.commit_frequency,
+                        delegation_slot: delegation_record.delegation_slot,
+                        at_slot: chain_snapshot.at_slot,
+                    })
+                }
+                _ => None,
+            }
+        });
+        let unknown = readonly_unknown_pubkeys.into_iter().map(|pubkey| {
+            EndpointWarning::ReadsUnknownAccountOnChain { pubkey }
+        });
+        delegated.chain(unknown).collect()
+    }
+}
@@ -1,24 +1,111 @@
-use solana_sdk::pubkey::Pubkey;
+use conjunto_lockbox::errors::LockboxError;
+use solana_sdk::{packet::PACKET_DATA_SIZE, pubkey::Pubkey};
 use thiserror::Error;
 
+use crate::{endpoint_trace::EndpointKind, wire_transaction::WireEncoding};
+
 pub type TranswiseResult<T> = std::result::Result<T, TranswiseError>;
 
 #[derive(Error, Debug)]
 pub enum TranswiseError {
     #[error("LockboxError")]
-    LockboxError(#[from] conjunto_lockbox::errors::LockboxError),
+    LockboxError(#[from] LockboxError),
 
     #[error("CoreError")]
     CoreError(#[from] conjunto_core::errors::CoreError),
 
+    /// (pubkey, error) of each account whose chain state could not be fetched
+    #[error(
+        "Failed to fetch account(s): {}",
+        join_failed_pubkeys(failed_pubkeys)
+    )]
+    FailedToFetchAccounts {
+        failed_pubkeys: Vec<(Pubkey, LockboxError)>,
+    },
+
+    #[error("{encoding} encoded {type_name} too large: {encoded_len} bytes (max: encoded/raw {max_encoded_len}/{})", PACKET_DATA_SIZE)]
+    EncodedTransactionTooLarge {
+        encoding: WireEncoding,
+        type_name: &'static str,
+        encoded_len: usize,
+        max_encoded_len: usize,
+    },
+
+    #[error("invalid {encoding} encoding: {reason}")]
+    InvalidTransactionEncoding {
+        encoding: WireEncoding,
+        reason: String,
+    },
+
+    #[error(
+        "decoded {type_name} too large: {decoded_len} bytes (max: {} bytes)",
+        PACKET_DATA_SIZE
+    )]
+    DecodedTransactionTooLarge {
+        type_name: &'static str,
+        decoded_len: usize,
+    },
+
+    #[error("failed to deserialize {type_name}: {reason}")]
+    FailedToDeserializeTransaction {
+        type_name: &'static str,
+        reason: String,
+    },
+
+    #[error("SanitizeMessageError")]
+    SanitizeMessageError(#[from] solana_sdk::message::SanitizeMessageError),
+
     #[error("Transaction includes non-delegated account(s) as writables")]
     TransactionIncludeUndelegatedAccountsAsWritable {
         writable_undelegated_pubkeys: Vec<Pubkey>,
     },
 
+    #[error("Transaction includes new account(s) as writables")]
+    TransactionIncludeNewAccountsAsWritable {
+        writable_new_pubkeys: Vec<Pubkey>,
+    },
+
+    #[error("Transaction includes non-delegated account(s) as readonly")]
+    TransactionIncludeUndelegatedAccountsAsReadonly {
+        readonly_undelegated_pubkeys: Vec<Pubkey>,
+    },
+
+    #[error("Transaction includes {writable_accounts_count} writable accounts, at most {max_writable_accounts} are allowed")]
+    TransactionIncludeTooManyWritableAccounts {
+        writable_accounts_count: usize,
+        max_writable_accounts: usize,
+    },
+
+    /// (pubkey, authority) of the writable accounts delegated elsewhere
+    #[error("Transaction includes writable account(s) delegated to a validator other than {validator_authority}")]
+    TransactionIncludeAccountsDelegatedToOtherValidator {
+        validator_authority: Pubkey,
+        mismatched_authorities: Vec<(Pubkey, Pubkey)>,
+    },
+
+    #[error("Transaction payer {payer} is not escrowed")]
+    TransactionPayerIsNotEscrowed { payer: Pubkey },
+
+    #[error("Transaction invokes disallowed program(s)")]
+    TransactionInvokesDisallowedPrograms { disallowed_program_ids: Vec<Pubkey> },
+
+    /// (endpoint, authority) of each transaction of the batch
+    #[error("Transactions of the batch that need to execute in order are routed to different endpoints")]
+    TransactionBatchSpansMultipleEndpoints {
+        endpoints: Vec<(EndpointKind, Option<Pubkey>)>,
+    },
+
     #[error("Transaction is missing payer account")]
     TransactionIsMissingPayerAccount,
 
-    #[error("ValidateAccountsConfig is configured improperly")]
+    #[error("ValidateAccountsConfig is configured improperly: {0}")]
     ValidateAccountsConfigIsInvalid(String),
 }
+
+fn join_failed_pubkeys(failed_pubkeys: &[(Pubkey, LockboxError)]) -> String {
+    failed_pubkeys
+        .iter()
+        .map(|(pubkey, err)| format!("{}: {:?}", pubkey, err))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
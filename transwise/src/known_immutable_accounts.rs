@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use conjunto_core::delegation_inconsistency::DelegationInconsistency;
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    account_chain_state::AccountChainState,
+};
+use solana_sdk::{
+    account::Account, address_lookup_table, bpf_loader, bpf_loader_deprecated,
+    bpf_loader_upgradeable, clock::Slot, compute_budget, config,
+    ed25519_program, loader_v4, native_loader, pubkey, pubkey::Pubkey,
+    secp256k1_program, stake, system_program, sysvar, vote,
+};
+
+pub const TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey =
+    pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// What we know about an account without fetching it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownImmutableAccount {
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+/// Registry of accounts which can never be delegated, i.e. sysvars, native
+/// programs and well-known builtins.
+/// Their chain state is known upfront and thus doesn't need to be fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownImmutableAccounts {
+    accounts: HashMap<Pubkey, KnownImmutableAccount>,
+}
+
+impl Default for KnownImmutableAccounts {
+    fn default() -> Self {
+        let native_programs = [
+            system_program::id(),
+            compute_budget::id(),
+            vote::program::id(),
+            stake::program::id(),
+            config::program::id(),
+            address_lookup_table::program::id(),
+            ed25519_program::id(),
+            secp256k1_program::id(),
+            bpf_loader::id(),
+            bpf_loader_deprecated::id(),
+            bpf_loader_upgradeable::id(),
+            loader_v4::id(),
+        ];
+        let sysvars = sysvar::ALL_IDS.iter().cloned().chain([
+            sysvar::epoch_rewards::id(),
+            sysvar::last_restart_slot::id(),
+        ]);
+        let spl_programs = [
+            (TOKEN_PROGRAM_ID, bpf_loader::id()),
+            (TOKEN_2022_PROGRAM_ID, bpf_loader_upgradeable::id()),
+            (ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID, bpf_loader::id()),
+        ];
+
+        let mut known_accounts = Self::empty();
+        for program_id in native_programs {
+            known_accounts.insert(program_id, native_loader::id(), true);
+        }
+        for sysvar_id in sysvars {
+            known_accounts.insert(sysvar_id, sysvar::id(), false);
+        }
+        for (program_id, loader_id) in spl_programs {
+            known_accounts.insert(program_id, loader_id, true);
+        }
+        known_accounts
+    }
+}
+
+impl KnownImmutableAccounts {
+    /// Registry without any known accounts, i.e. every account is fetched
+    pub fn empty() -> Self {
+        Self {
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, pubkey: Pubkey, owner: Pubkey, executable: bool) {
+        self.accounts
+            .insert(pubkey, KnownImmutableAccount { owner, executable });
+    }
+
+    pub fn remove(&mut self, pubkey: &Pubkey) -> Option<KnownImmutableAccount> {
+        self.accounts.remove(pubkey)
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&KnownImmutableAccount> {
+        self.accounts.get(pubkey)
+    }
+
+    pub fn contains(&self, pubkey: &Pubkey) -> bool {
+        self.accounts.contains_key(pubkey)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Synthetic snapshot of a known account, the account data is not known
+    /// and thus left empty
+    pub fn chain_snapshot(
+        &self,
+        pubkey: &Pubkey,
+        at_slot: Slot,
+    ) -> Option<AccountChainSnapshot> {
+        self.get(pubkey).map(|known_account| AccountChainSnapshot {
+            pubkey: *pubkey,
+            at_slot,
+            chain_state: AccountChainState::Undelegated {
+                account: Account {
+                    owner: known_account.owner,
+                    executable: known_account.executable,
+                    ..Account::default()
+                },
+                delegation_inconsistency:
+                    DelegationInconsistency::AccountInvalidOwner,
+            },
+        })
+    }
+}
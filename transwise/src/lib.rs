@@ -1,14 +1,22 @@
 pub mod endpoint;
+pub mod endpoint_cache;
+pub mod endpoint_trace;
+pub mod endpoint_warning;
 pub mod errors;
+pub mod known_immutable_accounts;
 pub mod transaction_accounts_extractor;
 pub mod transaction_accounts_holder;
 pub mod transaction_accounts_snapshot;
 pub mod transaction_accounts_validator;
+pub mod transaction_split;
 pub mod transwise;
+pub mod wire_transaction;
 
 pub use conjunto_core::delegation_inconsistency::DelegationInconsistency;
 pub use conjunto_core::delegation_record::CommitFrequency;
 pub use conjunto_core::delegation_record::DelegationRecord;
+pub use conjunto_core::delegation_record_parser::DelegationRecordParser;
+pub use conjunto_core::AccountProvider;
 pub use conjunto_lockbox::account_chain_snapshot::AccountChainSnapshot;
 pub use conjunto_lockbox::account_chain_snapshot_provider::AccountChainSnapshotProvider;
 pub use conjunto_lockbox::account_chain_snapshot_shared::AccountChainSnapshotShared;
@@ -1,4 +1,8 @@
 use solana_sdk::{
+    message::{
+        v0::LoadedAddresses, SanitizeMessageError, SanitizedMessage,
+        SanitizedVersionedMessage, SimpleAddressLoader,
+    },
     pubkey::Pubkey,
     transaction::{SanitizedTransaction, VersionedTransaction},
 };
@@ -10,65 +14,95 @@ pub struct TransactionAccountsHolder {
     pub writable: Vec<Pubkey>,
     pub readonly: Vec<Pubkey>,
     pub payer: Pubkey,
+    /// The programs invoked by the transaction's instructions, without duplicates
+    pub program_ids: Vec<Pubkey>,
+    /// The nonce account advanced by the first instruction of a durable nonce
+    /// transaction, it is not included in the writable accounts
+    pub nonce_account: Option<Pubkey>,
 }
 
-impl TryFrom<&SanitizedTransaction> for TransactionAccountsHolder {
+impl TryFrom<&SanitizedMessage> for TransactionAccountsHolder {
     type Error = TranswiseError;
 
-    fn try_from(tx: &SanitizedTransaction) -> TranswiseResult<Self> {
-        let loaded = tx.get_account_locks_unchecked();
-        let writable = loaded.writable.iter().map(|x| **x).collect();
-        let readonly = loaded.readonly.iter().map(|x| **x).collect();
-        let payer = tx
-            .message()
-            .account_keys()
+    /// Uses the same writability rules as the runtime, i.e. invoked program ids
+    /// as well as builtin programs and sysvars are demoted to readonly even if
+    /// the message marks them as writable.
+    fn try_from(message: &SanitizedMessage) -> TranswiseResult<Self> {
+        let account_keys = message.account_keys();
+        let payer = account_keys
             .get(0)
             .ok_or(TranswiseError::TransactionIsMissingPayerAccount)?;
-        Ok(Self {
-            writable,
-            readonly,
-            payer: *payer,
-        })
-    }
-}
-
-impl TryFrom<&VersionedTransaction> for TransactionAccountsHolder {
-    type Error = TranswiseError;
-    fn try_from(tx: &VersionedTransaction) -> TranswiseResult<Self> {
-        let static_accounts = tx.message.static_account_keys();
+        let nonce_account = message.get_durable_nonce().cloned();
         let mut writable = Vec::new();
         let mut readonly = Vec::new();
-        let payer = static_accounts
-            .first()
-            .ok_or(TranswiseError::TransactionIsMissingPayerAccount)?;
-
-        for (idx, pubkey) in static_accounts.iter().enumerate() {
-            if tx.message.is_maybe_writable(idx) {
+        for (idx, pubkey) in account_keys.iter().enumerate() {
+            if Some(*pubkey) == nonce_account {
+                continue;
+            }
+            if message.is_writable(idx) {
                 writable.push(*pubkey);
             } else {
                 readonly.push(*pubkey);
             }
         }
-
-        let lookups = tx.message.address_table_lookups().unwrap_or_default();
-        for lookup in lookups {
-            let _writable_idxs = &lookup.writable_indexes;
-            let _readonly_idxs = &lookup.readonly_indexes;
-            // TODO(thlorenz): to properly support lookup tables we'd now have to do the following:
-            //
-            // 1. Fetch data of the lookup table
-            // 2. resolve the indexes to actual account keys
-            //
-            // However to do that there are two issues with this:
-            // 1. This method would have to be async and fetching that data results in more latency
-            // 2. Where do we fetch the table from, ephemeral or chain? Or first ephemeral and then chain?
-            //    The latter would result in even more latency.
+        let mut program_ids = Vec::new();
+        for (program_id, _) in message.program_instructions_iter() {
+            if !program_ids.contains(program_id) {
+                program_ids.push(*program_id);
+            }
         }
-
         Ok(Self {
             writable,
             readonly,
             payer: *payer,
+            program_ids,
+            nonce_account,
         })
     }
 }
+
+impl TryFrom<&SanitizedTransaction> for TransactionAccountsHolder {
+    type Error = TranswiseError;
+
+    fn try_from(tx: &SanitizedTransaction) -> TranswiseResult<Self> {
+        TransactionAccountsHolder::try_from(tx.message())
+    }
+}
+
+impl TryFrom<&VersionedTransaction> for TransactionAccountsHolder {
+    type Error = TranswiseError;
+    fn try_from(tx: &VersionedTransaction) -> TranswiseResult<Self> {
+        if tx.message.static_account_keys().is_empty() {
+            return Err(TranswiseError::TransactionIsMissingPayerAccount);
+        }
+        let message = sanitized_message_from_versioned_transaction(tx)?;
+        TransactionAccountsHolder::try_from(&message)
+    }
+}
+
+/// Sanitizes the message of the transaction without loading any addresses
+/// from lookup tables
+pub(crate) fn sanitized_message_from_versioned_transaction(
+    tx: &VersionedTransaction,
+) -> TranswiseResult<SanitizedMessage> {
+    let sanitized_versioned_message =
+        SanitizedVersionedMessage::try_new(tx.message.clone())
+            .map_err(SanitizeMessageError::from)?;
+
+    // TODO(thlorenz): to properly support lookup tables we'd now have to do the following:
+    //
+    // 1. Fetch data of the lookup table
+    // 2. resolve the indexes to actual account keys
+    //
+    // However to do that there are two issues with this:
+    // 1. This method would have to be async and fetching that data results in more latency
+    // 2. Where do we fetch the table from, ephemeral or chain? Or first ephemeral and then chain?
+    //    The latter would result in even more latency.
+    //
+    // Until then we load no addresses from lookup tables which means that those
+    // accounts are not included in the holder.
+    Ok(SanitizedMessage::try_new(
+        sanitized_versioned_message,
+        SimpleAddressLoader::Enabled(LoadedAddresses::default()),
+    )?)
+}
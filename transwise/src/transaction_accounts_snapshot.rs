@@ -1,24 +1,72 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    slice,
+};
+
 use conjunto_core::{
+    delegation_inconsistency::DelegationInconsistency,
     delegation_record_parser::DelegationRecordParser, AccountProvider,
 };
 use conjunto_lockbox::{
     account_chain_snapshot_provider::AccountChainSnapshotProvider,
     account_chain_snapshot_shared::AccountChainSnapshotShared,
+    account_chain_state::AccountChainState, errors::LockboxResult,
 };
-use futures_util::future::{try_join, try_join_all, TryFutureExt};
+use futures_util::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use solana_sdk::{clock::Slot, pubkey::Pubkey};
 
 use crate::{
-    errors::TranswiseResult,
+    errors::{TranswiseError, TranswiseResult},
+    known_immutable_accounts::KnownImmutableAccounts,
     transaction_accounts_holder::TransactionAccountsHolder,
 };
 
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct TransactionAccountsSnapshot {
     pub readonly: Vec<AccountChainSnapshotShared>,
     pub writable: Vec<AccountChainSnapshotShared>,
     pub payer: Pubkey,
+    pub program_ids: Vec<Pubkey>,
+    /// The nonce account of a durable nonce transaction, it always lives on
+    /// chain and is not included in the writable accounts
+    #[serde(default)]
+    pub nonce_account: Option<AccountChainSnapshotShared>,
+    /// Writable accounts that don't exist on chain, but inside an ephemeral
+    /// validator (pubkey, authority of that validator)
+    #[serde(default)]
+    pub writable_ephemeral_native: Vec<(Pubkey, Pubkey)>,
+    /// Readonly accounts whose chain state could not be fetched, see
+    /// [TransactionAccountsSnapshotConfig::treat_failed_readonly_as_unknown]
+    #[serde(default)]
+    pub readonly_unknown: Vec<Pubkey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionAccountsSnapshotConfig {
+    /// Readonly accounts found in this registry are not fetched, instead a
+    /// synthetic chain state is used for them
+    pub known_immutable_accounts: KnownImmutableAccounts,
+    /// Maximum amount of requests fetching accounts at the same time
+    pub max_concurrent_fetches: usize,
+    /// Maximum amount of accounts fetched with a single request, each one is
+    /// fetched along with its delegation record
+    pub max_accounts_per_fetch: usize,
+    /// Continue without the chain state of readonly accounts that failed to
+    /// be fetched instead of failing the whole snapshot
+    pub treat_failed_readonly_as_unknown: bool,
+}
+
+impl Default for TransactionAccountsSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            known_immutable_accounts: KnownImmutableAccounts::default(),
+            max_concurrent_fetches: 16,
+            // getMultipleAccounts returns at most 100 accounts
+            max_accounts_per_fetch: 50,
+            treat_failed_readonly_as_unknown: false,
+        }
+    }
 }
 
 impl TransactionAccountsSnapshot {
@@ -30,31 +78,258 @@ impl TransactionAccountsSnapshot {
         account_chain_snapshot_provider: &AccountChainSnapshotProvider<T, V>,
         min_context_slot: Option<Slot>,
     ) -> TranswiseResult<Self> {
-        // Fully parallelize snapshot fetching using join(s)
-        let (readonly, writable) = try_join(
-            try_join_all(holder.readonly.iter().map(|pubkey| {
-                account_chain_snapshot_provider
-                    .try_fetch_chain_snapshot_of_pubkey(
-                        pubkey,
+        Self::from_accounts_holder_with_config(
+            holder,
+            account_chain_snapshot_provider,
+            min_context_slot,
+            &TransactionAccountsSnapshotConfig::default(),
+        )
+        .await
+    }
+
+    pub async fn from_accounts_holder_with_config<
+        T: AccountProvider,
+        V: DelegationRecordParser,
+    >(
+        holder: &TransactionAccountsHolder,
+        account_chain_snapshot_provider: &AccountChainSnapshotProvider<T, V>,
+        min_context_slot: Option<Slot>,
+        config: &TransactionAccountsSnapshotConfig,
+    ) -> TranswiseResult<Self> {
+        let mut snapshots = Self::from_accounts_holders_with_config(
+            slice::from_ref(holder),
+            account_chain_snapshot_provider,
+            min_context_slot,
+            config,
+        )
+        .await?;
+        Ok(snapshots.remove(0))
+    }
+
+    /// Snapshots the accounts of multiple transactions at once, accounts
+    /// shared between transactions are only fetched once
+    pub async fn from_accounts_holders_with_config<
+        T: AccountProvider,
+        V: DelegationRecordParser,
+    >(
+        holders: &[TransactionAccountsHolder],
+        account_chain_snapshot_provider: &AccountChainSnapshotProvider<T, V>,
+        min_context_slot: Option<Slot>,
+        config: &TransactionAccountsSnapshotConfig,
+    ) -> TranswiseResult<Vec<Self>> {
+        let mut pubkeys = vec![];
+        let mut is_writable_by_pubkey = HashMap::new();
+        for holder in holders {
+            let readonly = holder.readonly.iter().map(|pubkey| (pubkey, false));
+            let writable = holder
+                .writable
+                .iter()
+                .chain(&holder.nonce_account)
+                .map(|pubkey| (pubkey, true));
+            for (pubkey, is_writable) in readonly.chain(writable) {
+                match is_writable_by_pubkey.entry(*pubkey) {
+                    Entry::Vacant(entry) => {
+                        pubkeys.push(*pubkey);
+                        entry.insert(is_writable);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        *entry.get_mut() |= is_writable;
+                    }
+                }
+            }
+        }
+
+        // Known accounts can never be delegated, so we don't need to fetch them.
+        // Writable accounts are always fetched since a known account is only
+        // writable while it is modified on chain, i.e. during a program upgrade.
+        let mut fetch_results = HashMap::new();
+        let mut fetched_pubkeys = vec![];
+        for pubkey in &pubkeys {
+            let known_chain_snapshot = if is_writable_by_pubkey[pubkey] {
+                None
+            } else {
+                config.known_immutable_accounts.chain_snapshot(
+                    pubkey,
+                    min_context_slot.unwrap_or_default(),
+                )
+            };
+            match known_chain_snapshot {
+                Some(chain_snapshot) => {
+                    fetch_results.insert(
+                        *pubkey,
+                        Ok(AccountChainSnapshotShared::from(chain_snapshot)),
+                    );
+                }
+                None => fetched_pubkeys.push(*pubkey),
+            }
+        }
+        let chunk_results = stream::iter(
+            fetched_pubkeys
+                .chunks(config.max_accounts_per_fetch.max(1))
+                .map(|chunk| {
+                    Self::fetch_chain_snapshots_of_chunk(
+                        chunk,
+                        account_chain_snapshot_provider,
                         min_context_slot,
                     )
-                    .map_ok(AccountChainSnapshotShared::from)
-            })),
-            try_join_all(holder.writable.iter().map(|pubkey| {
-                account_chain_snapshot_provider
-                    .try_fetch_chain_snapshot_of_pubkey(
-                        pubkey,
-                        min_context_slot,
+                }),
+        )
+        .buffered(config.max_concurrent_fetches.max(1))
+        .collect::<Vec<_>>()
+        .await;
+        fetch_results.extend(chunk_results.into_iter().flatten());
+
+        let mut chain_snapshots = HashMap::new();
+        let mut unknown_pubkeys = HashSet::new();
+        let mut failed_pubkeys = vec![];
+        for pubkey in pubkeys {
+            match fetch_results.remove(&pubkey).expect("pubkey was fetched") {
+                Ok(chain_snapshot) => {
+                    chain_snapshots.insert(pubkey, chain_snapshot);
+                }
+                // Readonly accounts don't decide the endpoint, so we may
+                // continue without knowing their chain state
+                Err(_)
+                    if config.treat_failed_readonly_as_unknown
+                        && !is_writable_by_pubkey[&pubkey] =>
+                {
+                    unknown_pubkeys.insert(pubkey);
+                }
+                Err(err) => failed_pubkeys.push((pubkey, err)),
+            }
+        }
+        if !failed_pubkeys.is_empty() {
+            return Err(TranswiseError::FailedToFetchAccounts {
+                failed_pubkeys,
+            });
+        }
+
+        let chain_snapshots_of = |pubkeys: &[Pubkey]| {
+            pubkeys
+                .iter()
+                .filter_map(|pubkey| chain_snapshots.get(pubkey).cloned())
+                .collect::<Vec<_>>()
+        };
+        Ok(holders
+            .iter()
+            .map(|holder| Self {
+                readonly: chain_snapshots_of(&holder.readonly),
+                writable: chain_snapshots_of(&holder.writable),
+                payer: holder.payer,
+                program_ids: holder.program_ids.clone(),
+                nonce_account: holder
+                    .nonce_account
+                    .map(|pubkey| chain_snapshots[&pubkey].clone()),
+                writable_ephemeral_native: vec![],
+                readonly_unknown: holder
+                    .readonly
+                    .iter()
+                    .filter(|pubkey| unknown_pubkeys.contains(pubkey))
+                    .cloned()
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Fetches the accounts of the chunk with a single request, if that fails
+    /// each account is fetched on its own so only the accounts causing the
+    /// failure are reported as failed
+    async fn fetch_chain_snapshots_of_chunk<
+        T: AccountProvider,
+        V: DelegationRecordParser,
+    >(
+        pubkeys: &[Pubkey],
+        account_chain_snapshot_provider: &AccountChainSnapshotProvider<T, V>,
+        min_context_slot: Option<Slot>,
+    ) -> Vec<(Pubkey, LockboxResult<AccountChainSnapshotShared>)> {
+        match account_chain_snapshot_provider
+            .try_fetch_chain_snapshots_of_pubkeys(pubkeys, min_context_slot)
+            .await
+        {
+            Ok(chain_snapshots) => chain_snapshots
+                .into_iter()
+                .map(|chain_snapshot| {
+                    (
+                        chain_snapshot.pubkey,
+                        Ok(AccountChainSnapshotShared::from(chain_snapshot)),
                     )
-                    .map_ok(AccountChainSnapshotShared::from)
-            })),
+                })
+                .collect(),
+            Err(err) if pubkeys.len() == 1 => vec![(pubkeys[0], Err(err))],
+            Err(_) => {
+                let mut results = Vec::with_capacity(pubkeys.len());
+                for pubkey in pubkeys {
+                    let result = account_chain_snapshot_provider
+                        .try_fetch_chain_snapshot_of_pubkey(
+                            pubkey,
+                            min_context_slot,
+                        )
+                        .await
+                        .map(AccountChainSnapshotShared::from);
+                    results.push((*pubkey, result));
+                }
+                results
+            }
+        }
+    }
+
+    /// Looks up the writable accounts missing on chain in the ephemeral
+    /// validator with the given authority, the ones found there are
+    /// ephemeral native
+    pub async fn fetch_writable_ephemeral_native<
+        T: AccountProvider + ?Sized,
+    >(
+        &mut self,
+        ephemeral_account_provider: &T,
+        ephemeral_authority: Pubkey,
+    ) -> TranswiseResult<()> {
+        Self::fetch_writable_ephemeral_native_of_snapshots(
+            slice::from_mut(self),
+            ephemeral_account_provider,
+            ephemeral_authority,
         )
-        .await?;
-        Ok(Self {
-            readonly,
-            writable,
-            payer: holder.payer,
-        })
+        .await
+    }
+
+    /// Same as [Self::fetch_writable_ephemeral_native] for multiple snapshots
+    /// with a single request, accounts shared between them are fetched once
+    pub async fn fetch_writable_ephemeral_native_of_snapshots<
+        T: AccountProvider + ?Sized,
+    >(
+        snapshots: &mut [Self],
+        ephemeral_account_provider: &T,
+        ephemeral_authority: Pubkey,
+    ) -> TranswiseResult<()> {
+        let mut writable_new_pubkeys = vec![];
+        let mut seen_pubkeys = HashSet::new();
+        for snapshot in snapshots.iter() {
+            for pubkey in snapshot.writable_new_pubkeys() {
+                if seen_pubkeys.insert(pubkey) {
+                    writable_new_pubkeys.push(pubkey);
+                }
+            }
+        }
+        if writable_new_pubkeys.is_empty() {
+            return Ok(());
+        }
+        let (_, accounts) = ephemeral_account_provider
+            .get_multiple_accounts(&writable_new_pubkeys, None)
+            .await?;
+        let ephemeral_native_pubkeys = writable_new_pubkeys
+            .into_iter()
+            .zip(accounts)
+            .filter(|(_, account)| account.is_some())
+            .map(|(pubkey, _)| pubkey)
+            .collect::<HashSet<_>>();
+        for snapshot in snapshots.iter_mut() {
+            snapshot.writable_ephemeral_native = snapshot
+                .writable_new_pubkeys()
+                .into_iter()
+                .filter(|pubkey| ephemeral_native_pubkeys.contains(pubkey))
+                .map(|pubkey| (pubkey, ephemeral_authority))
+                .collect();
+        }
+        Ok(())
     }
 
     pub fn writable_undelegated_pubkeys(&self) -> Vec<Pubkey> {
@@ -74,4 +349,120 @@ impl TransactionAccountsSnapshot {
             .map(|chain_snapshot| chain_snapshot.pubkey)
             .collect()
     }
+
+    /// Writable undelegated accounts that look delegated, but whose delegation
+    /// could not be verified
+    pub fn writable_inconsistent_pubkeys(
+        &self,
+    ) -> Vec<(Pubkey, DelegationInconsistency)> {
+        self.writable
+            .iter()
+            .filter_map(|chain_snapshot| match &chain_snapshot.chain_state {
+                AccountChainState::Undelegated {
+                    delegation_inconsistency,
+                    ..
+                } if *delegation_inconsistency
+                    != DelegationInconsistency::AccountInvalidOwner =>
+                {
+                    Some((
+                        chain_snapshot.pubkey,
+                        delegation_inconsistency.clone(),
+                    ))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Writable delegated accounts with the authority they are delegated to
+    pub fn writable_delegated_authorities(&self) -> Vec<(Pubkey, Pubkey)> {
+        self.writable
+            .iter()
+            .filter_map(|chain_snapshot| match &chain_snapshot.chain_state {
+                AccountChainState::Delegated {
+                    delegation_record, ..
+                } => Some((chain_snapshot.pubkey, delegation_record.authority)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Writable delegated and ephemeral native accounts with the authority of
+    /// the ephemeral validator they live in
+    pub fn writable_ephemeral_authorities(&self) -> Vec<(Pubkey, Pubkey)> {
+        self.writable_delegated_authorities()
+            .into_iter()
+            .chain(self.writable_ephemeral_native.iter().cloned())
+            .collect()
+    }
+
+    /// Writable accounts that don't exist on chain yet
+    pub fn writable_new_pubkeys(&self) -> Vec<Pubkey> {
+        self.writable
+            .iter()
+            .filter(|chain_snapshot| {
+                matches!(
+                    chain_snapshot.chain_state,
+                    AccountChainState::FeePayer { lamports: 0, .. }
+                )
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect()
+    }
+
+    /// Readonly delegated accounts with the authority they are delegated to
+    pub fn readonly_delegated_authorities(&self) -> Vec<(Pubkey, Pubkey)> {
+        self.readonly
+            .iter()
+            .filter_map(|chain_snapshot| match &chain_snapshot.chain_state {
+                AccountChainState::Delegated {
+                    delegation_record, ..
+                } => Some((chain_snapshot.pubkey, delegation_record.authority)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn readonly_undelegated_pubkeys(&self) -> Vec<Pubkey> {
+        self.readonly
+            .iter()
+            .filter(|chain_snapshot| {
+                chain_snapshot.chain_state.is_undelegated()
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect()
+    }
+
+    /// Readonly undelegated or unknown accounts which may hold mutable data,
+    /// the invoked programs, executables and known immutable accounts are
+    /// excluded since they can't be delegated anyway
+    pub fn readonly_undelegated_data_pubkeys(
+        &self,
+        known_immutable_accounts: &KnownImmutableAccounts,
+    ) -> Vec<Pubkey> {
+        self.readonly
+            .iter()
+            .filter(|chain_snapshot| {
+                chain_snapshot.chain_state.is_undelegated()
+                    && !chain_snapshot
+                        .chain_state
+                        .account()
+                        .map(|account| account.executable)
+                        .unwrap_or(false)
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .chain(self.readonly_unknown.iter().cloned())
+            .filter(|pubkey| {
+                !self.program_ids.contains(pubkey)
+                    && !known_immutable_accounts.contains(pubkey)
+            })
+            .collect()
+    }
+
+    pub fn payer_chain_snapshot(&self) -> Option<&AccountChainSnapshotShared> {
+        self.writable
+            .iter()
+            .chain(self.readonly.iter())
+            .find(|chain_snapshot| chain_snapshot.pubkey == self.payer)
+    }
 }
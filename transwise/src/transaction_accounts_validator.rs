@@ -1,8 +1,81 @@
+use std::collections::HashSet;
+
+use solana_sdk::pubkey::Pubkey;
+
 use crate::{
     errors::{TranswiseError, TranswiseResult},
+    known_immutable_accounts::KnownImmutableAccounts,
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
 };
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidateAccountsConfig {
+    /// Allow writable accounts that don't exist on chain yet
+    pub allow_new_accounts: bool,
+    /// Require the fee payer to be delegated (escrowed) to the ephemeral validator
+    pub require_escrowed_payer: bool,
+    /// Allow undelegated accounts to be used as readonly, invoked programs,
+    /// executables and known immutable accounts are always allowed
+    pub allow_readonly_undelegated: bool,
+    /// Accounts that are never delegated and thus always allowed as readonly
+    pub known_immutable_accounts: KnownImmutableAccounts,
+    /// Maximum amount of writable accounts, no limit if `None`
+    pub max_writable_accounts: Option<usize>,
+    /// If provided, only those programs may be invoked
+    pub allowed_program_ids: Option<HashSet<Pubkey>>,
+    /// Programs that may never be invoked
+    pub denied_program_ids: HashSet<Pubkey>,
+    /// Authority of the local validator, if provided all writable delegated
+    /// accounts need to be delegated to it
+    pub validator_authority: Option<Pubkey>,
+}
+
+impl Default for ValidateAccountsConfig {
+    fn default() -> Self {
+        Self {
+            allow_new_accounts: true,
+            require_escrowed_payer: false,
+            allow_readonly_undelegated: true,
+            known_immutable_accounts: KnownImmutableAccounts::default(),
+            max_writable_accounts: None,
+            allowed_program_ids: None,
+            denied_program_ids: HashSet::new(),
+            validator_authority: None,
+        }
+    }
+}
+
+impl ValidateAccountsConfig {
+    pub fn validate(&self) -> TranswiseResult<()> {
+        if self.max_writable_accounts == Some(0) {
+            return Err(TranswiseError::ValidateAccountsConfigIsInvalid(
+                "max_writable_accounts needs to be at least 1 since the payer is always writable".to_string(),
+            ));
+        }
+        if let Some(allowed_program_ids) = &self.allowed_program_ids {
+            if allowed_program_ids.is_empty() {
+                return Err(TranswiseError::ValidateAccountsConfigIsInvalid(
+                    "allowed_program_ids is empty, no transaction could ever be accepted".to_string(),
+                ));
+            }
+            let mut conflicting_program_ids = allowed_program_ids
+                .intersection(&self.denied_program_ids)
+                .map(|program_id| program_id.to_string())
+                .collect::<Vec<_>>();
+            if !conflicting_program_ids.is_empty() {
+                conflicting_program_ids.sort();
+                return Err(TranswiseError::ValidateAccountsConfigIsInvalid(
+                    format!(
+                        "program ids are both allowed and denied: {}",
+                        conflicting_program_ids.join(", ")
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 pub trait TransactionAccountsValidator {
     /// Read information on the provided accounts,
     /// validates that we will accept this transaction in an ephemeral validator
@@ -13,26 +86,134 @@ pub trait TransactionAccountsValidator {
     ) -> TranswiseResult<()>;
 }
 
-pub struct TransactionAccountsValidatorImpl;
+#[derive(Debug, Default)]
+pub struct TransactionAccountsValidatorImpl {
+    config: ValidateAccountsConfig,
+}
+
+impl TransactionAccountsValidatorImpl {
+    pub fn try_new(config: ValidateAccountsConfig) -> TranswiseResult<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    pub fn config(&self) -> &ValidateAccountsConfig {
+        &self.config
+    }
+}
 
 impl TransactionAccountsValidator for TransactionAccountsValidatorImpl {
     fn validate_ephemeral_transaction_accounts(
         &self,
         transaction_accounts: &TransactionAccountsSnapshot,
     ) -> TranswiseResult<()> {
-        // We need make sure that none of the writables are data accounts
-        let writable_undelegated_pubkeys =
+        let config = &self.config;
+
+        // Only accept the programs we were configured for
+        let disallowed_program_ids = transaction_accounts
+            .program_ids
+            .iter()
+            .filter(|program_id| {
+                config.denied_program_ids.contains(program_id)
+                    || config
+                        .allowed_program_ids
+                        .as_ref()
+                        .map(|allowed| !allowed.contains(program_id))
+                        .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if !disallowed_program_ids.is_empty() {
+            return Err(TranswiseError::TransactionInvokesDisallowedPrograms {
+                disallowed_program_ids,
+            });
+        }
+
+        // The payer needs to be able to pay fees inside the ephemeral validator
+        if config.require_escrowed_payer {
+            let payer_chain_snapshot = transaction_accounts
+                .payer_chain_snapshot()
+                .ok_or(TranswiseError::TransactionIsMissingPayerAccount)?;
+            if !payer_chain_snapshot.chain_state.is_delegated() {
+                return Err(TranswiseError::TransactionPayerIsNotEscrowed {
+                    payer: transaction_accounts.payer,
+                });
+            }
+        }
+
+        if let Some(max_writable_accounts) = config.max_writable_accounts {
+            let writable_accounts_count = transaction_accounts.writable.len();
+            if writable_accounts_count > max_writable_accounts {
+                return Err(
+                    TranswiseError::TransactionIncludeTooManyWritableAccounts {
+                        writable_accounts_count,
+                        max_writable_accounts,
+                    },
+                );
+            }
+        }
+
+        // We need make sure that none of the writables are data accounts,
+        // this includes the nonce account advanced by the transaction
+        let mut writable_undelegated_pubkeys =
             transaction_accounts.writable_undelegated_pubkeys();
-        let has_writable_undelegated = !writable_undelegated_pubkeys.is_empty();
-        if has_writable_undelegated {
-            let writable_undelegated_pubkeys =
-                transaction_accounts.writable_undelegated_pubkeys();
+        if let Some(nonce_account) = &transaction_accounts.nonce_account {
+            if nonce_account.chain_state.is_undelegated() {
+                writable_undelegated_pubkeys.push(nonce_account.pubkey);
+            }
+        }
+        if !writable_undelegated_pubkeys.is_empty() {
             return Err(
                 TranswiseError::TransactionIncludeUndelegatedAccountsAsWritable {
                     writable_undelegated_pubkeys,
                 },
             );
         }
+
+        // We can only write to accounts that are delegated to us
+        if let Some(validator_authority) = config.validator_authority {
+            let mismatched_authorities = transaction_accounts
+                .writable_delegated_authorities()
+                .into_iter()
+                .filter(|(_, authority)| *authority != validator_authority)
+                .collect::<Vec<_>>();
+            if !mismatched_authorities.is_empty() {
+                return Err(
+                    TranswiseError::TransactionIncludeAccountsDelegatedToOtherValidator {
+                        validator_authority,
+                        mismatched_authorities,
+                    },
+                );
+            }
+        }
+
+        if !config.allow_new_accounts {
+            let writable_new_pubkeys =
+                transaction_accounts.writable_new_pubkeys();
+            if !writable_new_pubkeys.is_empty() {
+                return Err(
+                    TranswiseError::TransactionIncludeNewAccountsAsWritable {
+                        writable_new_pubkeys,
+                    },
+                );
+            }
+        }
+
+        if !config.allow_readonly_undelegated {
+            // Accounts of unknown chain state may be undelegated as well
+            let readonly_undelegated_pubkeys = transaction_accounts
+                .readonly_undelegated_data_pubkeys(
+                    &config.known_immutable_accounts,
+                );
+            if !readonly_undelegated_pubkeys.is_empty() {
+                return Err(
+                    TranswiseError::TransactionIncludeUndelegatedAccountsAsReadonly {
+                        readonly_undelegated_pubkeys,
+                    },
+                );
+            }
+        }
+
         // Transaction should work fine in other cases
         Ok(())
     }
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    compute_budget, message::SanitizedMessage, pubkey::Pubkey,
+    transaction::VersionedTransaction,
+};
+
+use crate::{
+    endpoint::{Endpoint, UnroutableReason},
+    endpoint_trace::{pubkey_string, pubkeys_string},
+    errors::TranswiseResult,
+    transaction_accounts_holder::sanitized_message_from_versioned_transaction,
+};
+
+/// Instructions of the original transaction that can be sent to one endpoint
+/// together with the accounts they require
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstructionSet {
+    /// Indexes of the instructions inside the original transaction
+    pub instruction_indexes: Vec<usize>,
+    #[serde(with = "pubkeys_string")]
+    pub writable: Vec<Pubkey>,
+    #[serde(with = "pubkeys_string")]
+    pub readonly: Vec<Pubkey>,
+}
+
+/// Proposal to split an unroutable transaction into one transaction for chain
+/// and one for the ephemeral validator.
+/// Compute budget instructions are included in both sets, all other
+/// instructions that don't write to chain or delegated accounts go to chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionSplit {
+    pub chain: InstructionSet,
+    pub ephemeral: InstructionSet,
+    /// The validator the ephemeral instructions need to be sent to
+    #[serde(with = "pubkey_string")]
+    pub authority: Pubkey,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InstructionTarget {
+    Chain,
+    Ephemeral,
+    Both,
+}
+
+impl TransactionSplit {
+    pub fn try_from_versioned_transaction(
+        tx: &VersionedTransaction,
+        endpoint: &Endpoint,
+    ) -> TranswiseResult<Option<Self>> {
+        let message = sanitized_message_from_versioned_transaction(tx)?;
+        Ok(Self::from_sanitized_message(&message, endpoint))
+    }
+
+    /// Proposes a split if the transaction is unroutable only because it
+    /// writes both undelegated and delegated accounts and none of its
+    /// instructions writes to both kinds
+    pub fn from_sanitized_message(
+        message: &SanitizedMessage,
+        endpoint: &Endpoint,
+    ) -> Option<Self> {
+        let (writable_undelegated_pubkeys, writable_delegated_pubkeys) =
+            match endpoint {
+                Endpoint::Unroutable {
+                    reason:
+                        UnroutableReason::ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                            writable_undelegated_pubkeys,
+                            writable_delegated_pubkeys,
+                        },
+                    ..
+                } => (writable_undelegated_pubkeys, writable_delegated_pubkeys),
+                _ => return None,
+            };
+        let transaction_accounts_snapshot =
+            endpoint.transaction_accounts_snapshot();
+        // The nonce needs to be advanced by the first instruction, splitting
+        // would result in two transactions using the same nonce
+        if transaction_accounts_snapshot.nonce_account.is_some() {
+            return None;
+        }
+        let authority = transaction_accounts_snapshot
+            .writable_ephemeral_authorities()
+            .first()?
+            .1;
+
+        let payer = *message.account_keys().get(0)?;
+        let mut chain = InstructionSet::with_payer(payer);
+        let mut ephemeral = InstructionSet::with_payer(payer);
+        // The account metas of each instruction carry the writability the
+        // message header assigns to its accounts
+        for (idx, ix) in message.decompile_instructions().iter().enumerate() {
+            let mut writes_undelegated = false;
+            let mut writes_delegated = false;
+            for meta in ix.accounts.iter().filter(|meta| meta.is_writable) {
+                writes_undelegated |=
+                    writable_undelegated_pubkeys.contains(meta.pubkey);
+                writes_delegated |=
+                    writable_delegated_pubkeys.contains(meta.pubkey);
+            }
+            let target = match (writes_undelegated, writes_delegated) {
+                (true, true) => return None,
+                (false, true) => InstructionTarget::Ephemeral,
+                (true, false) => InstructionTarget::Chain,
+                (false, false) if *ix.program_id == compute_budget::id() => {
+                    InstructionTarget::Both
+                }
+                (false, false) => InstructionTarget::Chain,
+            };
+
+            let accounts = ix
+                .accounts
+                .iter()
+                .map(|meta| (*meta.pubkey, meta.is_writable))
+                .chain([(*ix.program_id, false)]);
+            for (set, set_target) in [
+                (&mut chain, InstructionTarget::Chain),
+                (&mut ephemeral, InstructionTarget::Ephemeral),
+            ] {
+                if target == set_target || target == InstructionTarget::Both {
+                    set.add_instruction(idx, accounts.clone());
+                }
+            }
+        }
+
+        Some(Self {
+            chain,
+            ephemeral,
+            authority,
+        })
+    }
+}
+
+impl InstructionSet {
+    fn with_payer(payer: Pubkey) -> Self {
+        Self {
+            instruction_indexes: vec![],
+            writable: vec![payer],
+            readonly: vec![],
+        }
+    }
+
+    fn add_instruction(
+        &mut self,
+        instruction_index: usize,
+        accounts: impl Iterator<Item = (Pubkey, bool)>,
+    ) {
+        self.instruction_indexes.push(instruction_index);
+        for (pubkey, is_writable) in accounts {
+            let accounts = if is_writable {
+                &mut self.writable
+            } else {
+                &mut self.readonly
+            };
+            if !accounts.contains(&pubkey) {
+                accounts.push(pubkey);
+            }
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use std::{slice, time::Duration};
+
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
 use conjunto_lockbox::{
     account_chain_snapshot_provider::AccountChainSnapshotProvider,
     delegation_record_parser_impl::DelegationRecordParserImpl,
@@ -6,45 +11,151 @@ use conjunto_providers::{
     rpc_account_provider::RpcAccountProvider,
     rpc_provider_config::RpcProviderConfig,
 };
-use solana_sdk::transaction::{SanitizedTransaction, VersionedTransaction};
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    transaction::{SanitizedTransaction, Transaction, VersionedTransaction},
+};
 
 use crate::{
-    endpoint::Endpoint, errors::TranswiseResult,
+    endpoint::{Endpoint, EndpointConfig},
+    endpoint_cache::EndpointCache,
+    errors::{TranswiseError, TranswiseResult},
     transaction_accounts_holder::TransactionAccountsHolder,
-    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+    transaction_accounts_snapshot::{
+        TransactionAccountsSnapshot, TransactionAccountsSnapshotConfig,
+    },
+    wire_transaction::{deserialize_wire_transaction, WireEncoding},
 };
 
+/// Provides accounts that only exist inside an ephemeral validator
+struct EphemeralAccountProvider<V: AccountProvider> {
+    account_provider: V,
+    authority: Pubkey,
+}
+
 /// The API that allows us to guide a transaction given a cluster
 /// Guiding decisions are made by consulting the state of accounts on chain
 /// See [../examples/guiding_transactions.rs] for more info.
-pub struct Transwise {
-    account_chain_snapshot_provider: AccountChainSnapshotProvider<
-        RpcAccountProvider,
-        DelegationRecordParserImpl,
-    >,
+/// The optional ephemeral account provider is of type `V`, see
+/// [Self::with_ephemeral_account_provider].
+pub struct Transwise<
+    T: AccountProvider,
+    U: DelegationRecordParser,
+    V: AccountProvider = T,
+> {
+    account_chain_snapshot_provider: AccountChainSnapshotProvider<T, U>,
+    snapshot_config: TransactionAccountsSnapshotConfig,
+    endpoint_config: EndpointConfig,
+    endpoint_cache: Option<EndpointCache>,
+    ephemeral_account_provider: Option<EphemeralAccountProvider<V>>,
 }
 
-impl Transwise {
+impl Transwise<RpcAccountProvider, DelegationRecordParserImpl> {
+    /// Convenience constructor which fetches accounts from the RPC of the
+    /// provided config
     pub fn new(config: RpcProviderConfig) -> Self {
-        let account_chain_snapshot_provider = AccountChainSnapshotProvider::new(
+        Self::with_providers(
             RpcAccountProvider::new(config),
             DelegationRecordParserImpl,
+        )
+    }
+}
+
+impl<T: AccountProvider, U: DelegationRecordParser> Transwise<T, U> {
+    pub fn with_providers(
+        account_provider: T,
+        delegation_record_parser: U,
+    ) -> Self {
+        let account_chain_snapshot_provider = AccountChainSnapshotProvider::new(
+            account_provider,
+            delegation_record_parser,
         );
         Self {
             account_chain_snapshot_provider,
+            snapshot_config: TransactionAccountsSnapshotConfig::default(),
+            endpoint_config: EndpointConfig::default(),
+            endpoint_cache: None,
+            ephemeral_account_provider: None,
+        }
+    }
+}
+
+impl<T: AccountProvider, U: DelegationRecordParser, V: AccountProvider>
+    Transwise<T, U, V>
+{
+    pub fn with_snapshot_config(
+        mut self,
+        snapshot_config: TransactionAccountsSnapshotConfig,
+    ) -> Self {
+        self.snapshot_config = snapshot_config;
+        self
+    }
+
+    pub fn with_endpoint_config(
+        mut self,
+        endpoint_config: EndpointConfig,
+    ) -> Self {
+        self.endpoint_config = endpoint_config;
+        self
+    }
+
+    /// Caches the endpoint of each transaction message for the given duration,
+    /// i.e. to avoid fetching accounts again when clients retry sending it
+    pub fn with_endpoint_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.endpoint_cache = Some(EndpointCache::new(ttl));
+        self
+    }
+
+    /// Looks up writable accounts missing on chain in the ephemeral validator
+    /// with the given authority. Transactions writing accounts found there are
+    /// routed to that validator.
+    pub fn with_ephemeral_account_provider<W: AccountProvider>(
+        self,
+        account_provider: W,
+        authority: Pubkey,
+    ) -> Transwise<T, U, W> {
+        Transwise {
+            account_chain_snapshot_provider: self
+                .account_chain_snapshot_provider,
+            snapshot_config: self.snapshot_config,
+            endpoint_config: self.endpoint_config,
+            endpoint_cache: self.endpoint_cache,
+            ephemeral_account_provider: Some(EphemeralAccountProvider {
+                account_provider,
+                authority,
+            }),
         }
     }
 
+    /// The config endpoints are determined with, also decides which
+    /// warnings apply to them, see [Endpoint::warnings_with_config]
+    pub fn endpoint_config(&self) -> &EndpointConfig {
+        &self.endpoint_config
+    }
+
+    /// Allows clearing cached endpoints
+    pub fn endpoint_cache(&self) -> Option<&EndpointCache> {
+        self.endpoint_cache.as_ref()
+    }
+
     /// Extracts information of all accounts involved in the transaction,
     /// checks their lock state on chain and based on that returns an endpoint.
     pub async fn guide_versioned_transaction(
         &self,
         tx: &VersionedTransaction,
     ) -> TranswiseResult<Endpoint> {
-        Ok(Endpoint::from(
+        let message_hash = tx.message.hash();
+        if let Some(endpoint) = self.cached_endpoint(&message_hash) {
+            return Ok(endpoint);
+        }
+        let endpoint = Endpoint::from_with_config(
             self.transaction_accounts_snapshot_from_versioned_transaction(tx)
                 .await?,
-        ))
+            &self.endpoint_config,
+        );
+        self.cache_endpoint(message_hash, &endpoint);
+        Ok(endpoint)
     }
 
     /// Extracts information of all accounts involved in the transaction,
@@ -53,10 +164,108 @@ impl Transwise {
         &self,
         tx: &SanitizedTransaction,
     ) -> TranswiseResult<Endpoint> {
-        Ok(Endpoint::from(
+        let message_hash = *tx.message_hash();
+        if let Some(endpoint) = self.cached_endpoint(&message_hash) {
+            return Ok(endpoint);
+        }
+        let endpoint = Endpoint::from_with_config(
             self.transaction_accounts_snapshot_from_sanitized_transaction(tx)
                 .await?,
+            &self.endpoint_config,
+        );
+        self.cache_endpoint(message_hash, &endpoint);
+        Ok(endpoint)
+    }
+
+    /// Guides a legacy transaction
+    pub async fn guide_transaction(
+        &self,
+        tx: &Transaction,
+    ) -> TranswiseResult<Endpoint> {
+        self.guide_versioned_transaction(&VersionedTransaction::from(
+            tx.clone(),
         ))
+        .await
+    }
+
+    /// Deserializes a transaction as received over the wire and guides it.
+    /// The data needs to fit into a single packet, see
+    /// [crate::wire_transaction::decode_and_deserialize].
+    pub async fn guide_wire_transaction(
+        &self,
+        data: &[u8],
+        encoding: WireEncoding,
+    ) -> TranswiseResult<Endpoint> {
+        let tx = deserialize_wire_transaction(data, encoding)?;
+        self.guide_versioned_transaction(&tx).await
+    }
+
+    /// Guides multiple transactions at once, returning one endpoint per
+    /// transaction. Accounts shared between the transactions are fetched once,
+    /// only for the transactions whose endpoint is not cached.
+    pub async fn guide_versioned_transactions(
+        &self,
+        txs: &[VersionedTransaction],
+    ) -> TranswiseResult<Vec<Endpoint>> {
+        let message_hashes =
+            txs.iter().map(|tx| tx.message.hash()).collect::<Vec<_>>();
+        let mut endpoints = message_hashes
+            .iter()
+            .map(|message_hash| self.cached_endpoint(message_hash))
+            .collect::<Vec<_>>();
+        let uncached_idxs = endpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, endpoint)| endpoint.is_none())
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        let holders = uncached_idxs
+            .iter()
+            .map(|idx| TransactionAccountsHolder::try_from(&txs[*idx]))
+            .collect::<TranswiseResult<Vec<_>>>()?;
+        let mut snapshots =
+            TransactionAccountsSnapshot::from_accounts_holders_with_config(
+                &holders,
+                &self.account_chain_snapshot_provider,
+                None,
+                &self.snapshot_config,
+            )
+            .await?;
+        self.fetch_writable_ephemeral_native(&mut snapshots).await?;
+        for (idx, snapshot) in uncached_idxs.into_iter().zip(snapshots) {
+            let endpoint =
+                Endpoint::from_with_config(snapshot, &self.endpoint_config);
+            self.cache_endpoint(message_hashes[idx], &endpoint);
+            endpoints[idx] = Some(endpoint);
+        }
+        Ok(endpoints.into_iter().flatten().collect())
+    }
+
+    /// Same as [Self::guide_versioned_transactions] for transactions that
+    /// need to execute in order and thus have to be sent to the same endpoint.
+    /// Fails if the transactions are routed to different endpoints.
+    pub async fn guide_versioned_transactions_in_order(
+        &self,
+        txs: &[VersionedTransaction],
+    ) -> TranswiseResult<Vec<Endpoint>> {
+        let endpoints = self.guide_versioned_transactions(txs).await?;
+        let spans_multiple_endpoints = endpoints.windows(2).any(|pair| {
+            pair[0].kind() != pair[1].kind()
+                || pair[0].authority() != pair[1].authority()
+        });
+        if spans_multiple_endpoints {
+            return Err(
+                TranswiseError::TransactionBatchSpansMultipleEndpoints {
+                    endpoints: endpoints
+                        .iter()
+                        .map(|endpoint| {
+                            (endpoint.kind(), endpoint.authority().cloned())
+                        })
+                        .collect(),
+                },
+            );
+        }
+        Ok(endpoints)
     }
 
     /// Extracts information of all accounts involved in the transaction and
@@ -68,12 +277,17 @@ impl Transwise {
         &self,
         tx: &VersionedTransaction,
     ) -> TranswiseResult<TransactionAccountsSnapshot> {
-        TransactionAccountsSnapshot::from_accounts_holder(
-            &TransactionAccountsHolder::try_from(tx)?,
-            &self.account_chain_snapshot_provider,
-            None,
-        )
-        .await
+        let mut snapshot =
+            TransactionAccountsSnapshot::from_accounts_holder_with_config(
+                &TransactionAccountsHolder::try_from(tx)?,
+                &self.account_chain_snapshot_provider,
+                None,
+                &self.snapshot_config,
+            )
+            .await?;
+        self.fetch_writable_ephemeral_native(slice::from_mut(&mut snapshot))
+            .await?;
+        Ok(snapshot)
     }
 
     /// Extracts information of all accounts involved in the transaction and
@@ -85,11 +299,47 @@ impl Transwise {
         &self,
         tx: &SanitizedTransaction,
     ) -> TranswiseResult<TransactionAccountsSnapshot> {
-        TransactionAccountsSnapshot::from_accounts_holder(
-            &TransactionAccountsHolder::try_from(tx)?,
-            &self.account_chain_snapshot_provider,
-            None,
-        )
-        .await
+        let mut snapshot =
+            TransactionAccountsSnapshot::from_accounts_holder_with_config(
+                &TransactionAccountsHolder::try_from(tx)?,
+                &self.account_chain_snapshot_provider,
+                None,
+                &self.snapshot_config,
+            )
+            .await?;
+        self.fetch_writable_ephemeral_native(slice::from_mut(&mut snapshot))
+            .await?;
+        Ok(snapshot)
+    }
+
+    /// Looks up the writable accounts of all snapshots that are missing on
+    /// chain in the ephemeral validator with a single request
+    async fn fetch_writable_ephemeral_native(
+        &self,
+        snapshots: &mut [TransactionAccountsSnapshot],
+    ) -> TranswiseResult<()> {
+        match &self.ephemeral_account_provider {
+            Some(ephemeral_account_provider) => {
+                TransactionAccountsSnapshot::fetch_writable_ephemeral_native_of_snapshots(
+                    snapshots,
+                    &ephemeral_account_provider.account_provider,
+                    ephemeral_account_provider.authority,
+                )
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn cached_endpoint(&self, message_hash: &Hash) -> Option<Endpoint> {
+        self.endpoint_cache
+            .as_ref()
+            .and_then(|endpoint_cache| endpoint_cache.get(message_hash))
+    }
+
+    fn cache_endpoint(&self, message_hash: Hash, endpoint: &Endpoint) {
+        if let Some(endpoint_cache) = &self.endpoint_cache {
+            endpoint_cache.insert(message_hash, endpoint.clone());
+        }
     }
 }
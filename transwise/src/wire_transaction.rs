@@ -0,0 +1,101 @@
+// NOTE: from sleipnir rpc via solana rpc
+
+use core::any::type_name;
+use std::fmt;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use solana_sdk::{packet::PACKET_DATA_SIZE, transaction::VersionedTransaction};
+
+use crate::errors::{TranswiseError, TranswiseResult};
+
+const MAX_BASE58_SIZE: usize = 1683; // Depends on PACKET_DATA_SIZE
+const MAX_BASE64_SIZE: usize = 1644; // Depends on PACKET_DATA_SIZE
+
+/// How a transaction received over the wire is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WireEncoding {
+    Base58,
+    Base64,
+    /// The bincode serialized bytes without any further encoding
+    Raw,
+}
+
+impl fmt::Display for WireEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireEncoding::Base58 => write!(f, "base58"),
+            WireEncoding::Base64 => write!(f, "base64"),
+            WireEncoding::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+/// Decodes and deserializes a (legacy or versioned) transaction, see
+/// [decode_and_deserialize]
+pub fn deserialize_wire_transaction(
+    data: &[u8],
+    encoding: WireEncoding,
+) -> TranswiseResult<VersionedTransaction> {
+    decode_and_deserialize(data, encoding).map(|(_, tx)| tx)
+}
+
+/// Decodes the data and deserializes it, rejecting anything that doesn't fit
+/// into a single packet.
+/// Returns the decoded bytes together with the deserialized value.
+pub fn decode_and_deserialize<T>(
+    data: &[u8],
+    encoding: WireEncoding,
+) -> TranswiseResult<(Vec<u8>, T)>
+where
+    T: DeserializeOwned,
+{
+    // Raw data is only limited by the decoded size check below
+    let max_encoded_len = match encoding {
+        WireEncoding::Base58 => Some(MAX_BASE58_SIZE),
+        WireEncoding::Base64 => Some(MAX_BASE64_SIZE),
+        WireEncoding::Raw => None,
+    };
+    if let Some(max_encoded_len) =
+        max_encoded_len.filter(|max_encoded_len| data.len() > *max_encoded_len)
+    {
+        return Err(TranswiseError::EncodedTransactionTooLarge {
+            encoding,
+            type_name: type_name::<T>(),
+            encoded_len: data.len(),
+            max_encoded_len,
+        });
+    }
+    let wire_output = match encoding {
+        WireEncoding::Base58 => bs58::decode(data).into_vec().map_err(|e| {
+            TranswiseError::InvalidTransactionEncoding {
+                encoding,
+                reason: format!("{e:?}"),
+            }
+        })?,
+        WireEncoding::Base64 => BASE64_STANDARD.decode(data).map_err(|e| {
+            TranswiseError::InvalidTransactionEncoding {
+                encoding,
+                reason: format!("{e:?}"),
+            }
+        })?,
+        WireEncoding::Raw => data.to_vec(),
+    };
+    if wire_output.len() > PACKET_DATA_SIZE {
+        return Err(TranswiseError::DecodedTransactionTooLarge {
+            type_name: type_name::<T>(),
+            decoded_len: wire_output.len(),
+        });
+    }
+    bincode::options()
+        .with_limit(PACKET_DATA_SIZE as u64)
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .deserialize_from(&wire_output[..])
+        .map_err(|err| TranswiseError::FailedToDeserializeTransaction {
+            type_name: type_name::<T>(),
+            reason: err.to_string(),
+        })
+        .map(|output| (wire_output, output))
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    account_chain_state::AccountChainState,
+};
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    accounts::{account_owned_by_delegation_program, account_with_data},
+    delegation_record_parser_stub::DelegationRecordParserStub,
+};
+use conjunto_transwise::{
+    endpoint::Endpoint, endpoint_cache::EndpointCache,
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+    transwise::Transwise, AccountChainSnapshotShared, CommitFrequency,
+    DelegationInconsistency, DelegationRecord,
+};
+use solana_sdk::{
+    hash::Hash,
+    message::Message,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    transaction::{Transaction, VersionedTransaction},
+};
+
+fn transfer_to(to: &Pubkey) -> VersionedTransaction {
+    let payer = Pubkey::new_unique();
+    Transaction::new_unsigned(Message::new(
+        &[system_instruction::transfer(&payer, to, 42)],
+        Some(&payer),
+    ))
+    .into()
+}
+
+fn endpoint_writing(
+    payer: Pubkey,
+    chain_snapshot: AccountChainSnapshotShared,
+) -> Endpoint {
+    Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            AccountChainSnapshot {
+                pubkey: payer,
+                at_slot: 0,
+                chain_state: AccountChainState::FeePayer {
+                    lamports: 42,
+                    owner: system_program::id(),
+                },
+            }
+            .into(),
+            chain_snapshot,
+        ],
+        payer,
+        ..Default::default()
+    })
+}
+
+fn undelegated_chain_snapshot(pubkey: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 0,
+        chain_state: AccountChainState::Undelegated {
+            account: account_with_data(),
+            delegation_inconsistency:
+                DelegationInconsistency::AccountInvalidOwner,
+        },
+    }
+    .into()
+}
+
+fn delegated_chain_snapshot(pubkey: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 0,
+        chain_state: AccountChainState::Delegated {
+            account: account_owned_by_delegation_program(),
+            delegation_record: DelegationRecord {
+                authority: Pubkey::new_unique(),
+                owner: Pubkey::new_unique(),
+                delegation_slot: 0,
+                commit_frequency: CommitFrequency::Millis(1_000),
+            },
+        },
+    }
+    .into()
+}
+
+#[tokio::test]
+async fn test_retried_transaction_uses_cached_endpoint() {
+    let to = Pubkey::new_unique();
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(to, account_with_data());
+    let fetched_pubkeys = account_provider.fetched_pubkeys.clone();
+    let transwise = Transwise::with_providers(
+        account_provider,
+        DelegationRecordParserStub::default(),
+    )
+    .with_endpoint_cache_ttl(Duration::from_secs(60));
+    let tx = transfer_to(&to);
+
+    let endpoint = transwise.guide_versioned_transaction(&tx).await.unwrap();
+    let fetch_count = fetched_pubkeys.read().unwrap().len();
+    assert!(endpoint.is_chain());
+    assert!(fetch_count > 0);
+
+    let cached_endpoint =
+        transwise.guide_versioned_transaction(&tx).await.unwrap();
+    assert_eq!(cached_endpoint, endpoint);
+    assert_eq!(fetched_pubkeys.read().unwrap().len(), fetch_count);
+
+    // Once the cache is cleared we need to fetch again
+    transwise.endpoint_cache().unwrap().clear();
+    transwise.guide_versioned_transaction(&tx).await.unwrap();
+    assert_eq!(fetched_pubkeys.read().unwrap().len(), 2 * fetch_count);
+}
+
+#[test]
+fn test_expired_endpoints_are_not_returned() {
+    let endpoint_cache = EndpointCache::new(Duration::ZERO);
+    let message_hash = Hash::new_unique();
+    let endpoint = endpoint_writing(
+        Pubkey::new_unique(),
+        undelegated_chain_snapshot(Pubkey::new_unique()),
+    );
+
+    endpoint_cache.insert(message_hash, endpoint);
+
+    assert_eq!(endpoint_cache.get(&message_hash), None);
+}
+
+#[test]
+fn test_endpoints_with_changed_delegation_are_invalidated() {
+    let endpoint_cache = EndpointCache::new(Duration::from_secs(60));
+    let account = Pubkey::new_unique();
+    let undelegated_hash = Hash::new_unique();
+    let unrelated_hash = Hash::new_unique();
+    let delegated_hash = Hash::new_unique();
+
+    endpoint_cache.insert(
+        undelegated_hash,
+        endpoint_writing(
+            Pubkey::new_unique(),
+            undelegated_chain_snapshot(account),
+        ),
+    );
+    endpoint_cache.insert(
+        unrelated_hash,
+        endpoint_writing(
+            Pubkey::new_unique(),
+            undelegated_chain_snapshot(Pubkey::new_unique()),
+        ),
+    );
+    assert!(endpoint_cache.get(&undelegated_hash).unwrap().is_chain());
+
+    // Another transaction saw the account delegated in the meantime
+    endpoint_cache.insert(
+        delegated_hash,
+        endpoint_writing(
+            Pubkey::new_unique(),
+            delegated_chain_snapshot(account),
+        ),
+    );
+
+    assert_eq!(endpoint_cache.get(&undelegated_hash), None);
+    assert!(endpoint_cache.get(&unrelated_hash).is_some());
+    assert!(endpoint_cache.get(&delegated_hash).unwrap().is_ephemeral());
+    assert_eq!(endpoint_cache.len(), 2);
+}
+
+#[test]
+fn test_invalidate_pubkey_removes_endpoints_involving_it() {
+    let endpoint_cache = EndpointCache::new(Duration::from_secs(60));
+    let account = Pubkey::new_unique();
+    let involved_hash = Hash::new_unique();
+    let unrelated_hash = Hash::new_unique();
+
+    endpoint_cache.insert(
+        involved_hash,
+        endpoint_writing(
+            Pubkey::new_unique(),
+            undelegated_chain_snapshot(account),
+        ),
+    );
+    endpoint_cache.insert(
+        unrelated_hash,
+        endpoint_writing(
+            Pubkey::new_unique(),
+            undelegated_chain_snapshot(Pubkey::new_unique()),
+        ),
+    );
+
+    endpoint_cache.invalidate_pubkey(&account);
+
+    assert_eq!(endpoint_cache.get(&involved_hash), None);
+    assert!(endpoint_cache.get(&unrelated_hash).is_some());
+    assert_eq!(endpoint_cache.len(), 1);
+}
+
+#[tokio::test]
+async fn test_batch_only_fetches_uncached_transactions() {
+    let cached_to = Pubkey::new_unique();
+    let uncached_to = Pubkey::new_unique();
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(cached_to, account_with_data());
+    account_provider.add(uncached_to, account_with_data());
+    let fetched_pubkeys = account_provider.fetched_pubkeys.clone();
+    let transwise = Transwise::with_providers(
+        account_provider,
+        DelegationRecordParserStub::default(),
+    )
+    .with_endpoint_cache_ttl(Duration::from_secs(60));
+    let cached_tx = transfer_to(&cached_to);
+    let uncached_tx = transfer_to(&uncached_to);
+
+    let cached_endpoint = transwise
+        .guide_versioned_transaction(&cached_tx)
+        .await
+        .unwrap();
+    fetched_pubkeys.write().unwrap().clear();
+
+    let endpoints = transwise
+        .guide_versioned_transactions(&[uncached_tx.clone(), cached_tx])
+        .await
+        .unwrap();
+    assert_eq!(endpoints.len(), 2);
+    assert_eq!(endpoints[1], cached_endpoint);
+    assert!(fetched_pubkeys.read().unwrap().contains(&uncached_to));
+    assert!(!fetched_pubkeys.read().unwrap().contains(&cached_to));
+
+    // The endpoints of the batch are cached as well
+    fetched_pubkeys.write().unwrap().clear();
+    assert_eq!(
+        transwise
+            .guide_versioned_transaction(&uncached_tx)
+            .await
+            .unwrap(),
+        endpoints[0]
+    );
+    assert!(fetched_pubkeys.read().unwrap().is_empty());
+}
@@ -0,0 +1,255 @@
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    account_chain_state::AccountChainState,
+};
+use conjunto_test_tools::accounts::{
+    account_owned_by_delegation_program, account_with_data,
+};
+use conjunto_transwise::{
+    endpoint::{Endpoint, UnroutableReason},
+    endpoint_trace::{
+        AccountChainStateKind, AccountInfluence, AccountRole, AccountTrace,
+        EndpointKind, EndpointTrace,
+    },
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+    AccountChainSnapshotShared, CommitFrequency, DelegationInconsistency,
+    DelegationRecord,
+};
+use solana_sdk::{pubkey::Pubkey, system_program};
+
+fn chain_snapshot(
+    pubkey: Pubkey,
+    chain_state: AccountChainState,
+) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 42,
+        chain_state,
+    }
+    .into()
+}
+
+fn feepayer() -> AccountChainState {
+    AccountChainState::FeePayer {
+        lamports: 42,
+        owner: system_program::ID,
+    }
+}
+
+fn undelegated(
+    delegation_inconsistency: DelegationInconsistency,
+) -> AccountChainState {
+    AccountChainState::Undelegated {
+        account: account_with_data(),
+        delegation_inconsistency,
+    }
+}
+
+fn delegated() -> AccountChainState {
+    AccountChainState::Delegated {
+        account: account_owned_by_delegation_program(),
+        delegation_record: DelegationRecord {
+            authority: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            delegation_slot: 0,
+            commit_frequency: CommitFrequency::Millis(1_000),
+        },
+    }
+}
+
+#[test]
+fn test_trace_ephemeral() {
+    let payer = Pubkey::new_unique();
+    let writable_delegated = Pubkey::new_unique();
+    let readonly_undelegated = Pubkey::new_unique();
+
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![chain_snapshot(
+            readonly_undelegated,
+            undelegated(DelegationInconsistency::AccountInvalidOwner),
+        )],
+        writable: vec![
+            chain_snapshot(payer, feepayer()),
+            chain_snapshot(writable_delegated, delegated()),
+        ],
+        payer,
+        ..Default::default()
+    });
+
+    assert_eq!(
+        endpoint.trace(),
+        EndpointTrace {
+            endpoint: EndpointKind::Ephemeral,
+            reason: None,
+            accounts: vec![
+                AccountTrace {
+                    pubkey: payer,
+                    role: AccountRole::Payer,
+                    chain_state: AccountChainStateKind::FeePayer,
+                    delegation_inconsistency: None,
+                    influence: AccountInfluence::Neutral,
+                },
+                AccountTrace {
+                    pubkey: writable_delegated,
+                    role: AccountRole::Writable,
+                    chain_state: AccountChainStateKind::Delegated,
+                    delegation_inconsistency: None,
+                    influence: AccountInfluence::RequiresEphemeral,
+                },
+                AccountTrace {
+                    pubkey: readonly_undelegated,
+                    role: AccountRole::Readonly,
+                    chain_state: AccountChainStateKind::Undelegated,
+                    delegation_inconsistency: None,
+                    influence: AccountInfluence::Neutral,
+                },
+            ],
+            warnings: vec![],
+        }
+    );
+}
+
+#[test]
+fn test_trace_unroutable_inconsistent_delegation() {
+    let payer = Pubkey::new_unique();
+    let writable_undelegated = Pubkey::new_unique();
+    let writable_inconsistent = Pubkey::new_unique();
+
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            chain_snapshot(payer, feepayer()),
+            chain_snapshot(
+                writable_undelegated,
+                undelegated(DelegationInconsistency::AccountInvalidOwner),
+            ),
+            chain_snapshot(
+                writable_inconsistent,
+                undelegated(DelegationInconsistency::DelegationRecordNotFound),
+            ),
+        ],
+        payer,
+        ..Default::default()
+    });
+
+    let trace = endpoint.trace();
+    assert_eq!(trace.endpoint, EndpointKind::Unroutable);
+    assert!(trace.reason.is_some());
+
+    let influences = trace
+        .accounts
+        .iter()
+        .map(|account| (account.pubkey, account.influence))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        influences,
+        vec![
+            (payer, AccountInfluence::Neutral),
+            (writable_undelegated, AccountInfluence::RequiresChain),
+            (writable_inconsistent, AccountInfluence::PreventsRouting),
+        ]
+    );
+    assert_eq!(trace.accounts[1].delegation_inconsistency, None);
+    assert_eq!(
+        trace.accounts[2].delegation_inconsistency,
+        Some(DelegationInconsistency::DelegationRecordNotFound)
+    );
+}
+
+#[test]
+fn test_trace_serializes_pubkeys_as_strings() {
+    let payer = Pubkey::new_unique();
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![chain_snapshot(payer, feepayer())],
+        payer,
+        ..Default::default()
+    });
+    let trace = endpoint.trace();
+
+    let json = serde_json::to_value(&trace).unwrap();
+    assert_eq!(json["endpoint"], "chain");
+    assert_eq!(json["accounts"][0]["pubkey"], payer.to_string());
+    assert_eq!(json["accounts"][0]["role"], "payer");
+    assert_eq!(json["accounts"][0]["chainState"], "feePayer");
+
+    let deserialized: EndpointTrace = serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized, trace);
+}
+
+#[test]
+fn test_unroutable_reason_serializes_pubkeys_as_strings() {
+    let pubkey = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let reasons = [
+        UnroutableReason::ContainsInconsistentDelegationsAsWritable {
+            writable_inconsistent_pubkeys: vec![(
+                pubkey,
+                DelegationInconsistency::DelegationRecordNotFound,
+            )],
+        },
+        UnroutableReason::ContainsAccountsDelegatedToDifferentAuthorities {
+            writable_delegated_authorities: vec![(pubkey, authority)],
+        },
+        UnroutableReason::MissingFeePayer { payer: pubkey },
+    ];
+
+    let json = serde_json::to_value(&reasons).unwrap();
+    assert_eq!(
+        json[0]["ContainsInconsistentDelegationsAsWritable"]
+            ["writableInconsistentPubkeys"][0][0],
+        pubkey.to_string()
+    );
+    assert_eq!(
+        json[1]["ContainsAccountsDelegatedToDifferentAuthorities"]
+            ["writableDelegatedAuthorities"][0],
+        serde_json::json!([pubkey.to_string(), authority.to_string()])
+    );
+    assert_eq!(json[2]["MissingFeePayer"]["payer"], pubkey.to_string());
+
+    let deserialized: Vec<UnroutableReason> =
+        serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized, reasons);
+}
+
+#[test]
+fn test_unroutable_reason_serializes_fields_in_camel_case() {
+    let pubkey = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let reasons = [
+        UnroutableReason::ReadsDelegatedAccountsOnChain {
+            readonly_delegated_authorities: vec![(pubkey, authority)],
+            readonly_unknown_pubkeys: vec![pubkey],
+        },
+        UnroutableReason::InvokesProgramsUnavailableOnEndpoint {
+            endpoint: EndpointKind::Ephemeral,
+            unavailable_program_ids: vec![pubkey],
+        },
+    ];
+
+    let json = serde_json::to_value(&reasons).unwrap();
+    assert_eq!(
+        json[0],
+        serde_json::json!({
+            "ReadsDelegatedAccountsOnChain": {
+                "readonlyDelegatedAuthorities": [
+                    [pubkey.to_string(), authority.to_string()]
+                ],
+                "readonlyUnknownPubkeys": [pubkey.to_string()],
+            }
+        })
+    );
+    assert_eq!(
+        json[1],
+        serde_json::json!({
+            "InvokesProgramsUnavailableOnEndpoint": {
+                "endpoint": "ephemeral",
+                "unavailableProgramIds": [pubkey.to_string()],
+            }
+        })
+    );
+
+    let deserialized: Vec<UnroutableReason> =
+        serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized, reasons);
+}
@@ -0,0 +1,169 @@
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    account_chain_state::AccountChainState,
+};
+use conjunto_test_tools::accounts::{
+    account_owned_by_delegation_program, DELEGATION_PROGRAM_ID,
+};
+use conjunto_transwise::{
+    endpoint::{Endpoint, EndpointConfig},
+    endpoint_warning::EndpointWarning,
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+    AccountChainSnapshotShared, CommitFrequency, DelegationRecord,
+};
+use solana_sdk::{clock::Slot, pubkey::Pubkey, system_program};
+
+fn feepayer_chain_snapshot(pubkey: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 0,
+        chain_state: AccountChainState::FeePayer {
+            lamports: 42,
+            owner: system_program::id(),
+        },
+    }
+    .into()
+}
+
+fn delegated_chain_snapshot(
+    pubkey: Pubkey,
+    authority: Pubkey,
+    at_slot: Slot,
+    delegation_slot: Slot,
+) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot,
+        chain_state: AccountChainState::Delegated {
+            account: account_owned_by_delegation_program(),
+            delegation_record: DelegationRecord {
+                authority,
+                owner: Pubkey::new_unique(),
+                delegation_slot,
+                commit_frequency: CommitFrequency::Millis(1_000),
+            },
+        },
+    }
+    .into()
+}
+
+#[test]
+fn test_chain_transaction_reading_delegated_account() {
+    let payer = Pubkey::new_unique();
+    let readonly_delegated = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![delegated_chain_snapshot(
+            readonly_delegated,
+            authority,
+            111,
+            100,
+        )],
+        writable: vec![feepayer_chain_snapshot(payer)],
+        payer,
+        ..Default::default()
+    });
+
+    assert!(endpoint.is_chain());
+    assert_eq!(
+        endpoint.warnings(),
+        vec![EndpointWarning::ReadsDelegatedAccountOnChain {
+            pubkey: readonly_delegated,
+            authority,
+            commit_frequency: CommitFrequency::Millis(1_000),
+            delegation_slot: 100,
+            at_slot: 111,
+        }]
+    );
+    assert_eq!(endpoint.warnings()[0].slots_since_delegation(), Some(11));
+    assert!(endpoint.warnings()[0]
+        .to_string()
+        .contains("at most 11 slots ago"));
+    assert_eq!(endpoint.trace().warnings, endpoint.warnings());
+}
+
+#[test]
+fn test_no_warnings_for_ephemeral_transaction() {
+    let payer = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![delegated_chain_snapshot(
+            Pubkey::new_unique(),
+            authority,
+            111,
+            100,
+        )],
+        writable: vec![
+            feepayer_chain_snapshot(payer),
+            delegated_chain_snapshot(Pubkey::new_unique(), authority, 111, 100),
+        ],
+        payer,
+        ..Default::default()
+    });
+
+    assert!(endpoint.is_ephemeral());
+    assert_eq!(endpoint.warnings(), vec![]);
+}
+
+#[test]
+fn test_chain_transaction_reading_unknown_account() {
+    let payer = Pubkey::new_unique();
+    let readonly_unknown = Pubkey::new_unique();
+
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![feepayer_chain_snapshot(payer)],
+        payer,
+        readonly_unknown: vec![readonly_unknown],
+        ..Default::default()
+    });
+
+    assert!(endpoint.is_chain());
+    assert_eq!(
+        endpoint.warnings(),
+        vec![EndpointWarning::ReadsUnknownAccountOnChain {
+            pubkey: readonly_unknown
+        }]
+    );
+}
+
+#[test]
+fn test_no_warnings_for_chain_only_program() {
+    let payer = Pubkey::new_unique();
+    let chain_only_program = Pubkey::new_unique();
+
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![delegated_chain_snapshot(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            111,
+            100,
+        )],
+        writable: vec![feepayer_chain_snapshot(payer)],
+        payer,
+        program_ids: vec![DELEGATION_PROGRAM_ID],
+        readonly_unknown: vec![Pubkey::new_unique()],
+        ..Default::default()
+    });
+
+    assert!(endpoint.is_chain());
+    assert_eq!(endpoint.warnings(), vec![]);
+
+    // Same for programs configured as chain only
+    let config = EndpointConfig {
+        chain_only_program_ids: [chain_only_program].into(),
+        ..Default::default()
+    };
+    let snapshot = TransactionAccountsSnapshot {
+        program_ids: vec![chain_only_program],
+        ..endpoint.transaction_accounts_snapshot().clone()
+    };
+    let endpoint = Endpoint::from_with_config(snapshot, &config);
+    assert!(endpoint.is_chain());
+    assert_eq!(endpoint.warnings_with_config(&config), vec![]);
+    assert_eq!(endpoint.trace_with_config(&config).warnings, vec![]);
+    // The default config doesn't know that program
+    assert_eq!(endpoint.warnings().len(), 2);
+}
@@ -0,0 +1,236 @@
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    accounts::{
+        account_owned_by_delegation_program, account_with_data,
+        delegated_account_ids,
+    },
+    delegation_record_parser_stub::DelegationRecordParserStub,
+};
+use conjunto_transwise::{
+    endpoint_trace::EndpointKind, errors::TranswiseError, transwise::Transwise,
+    CommitFrequency, DelegationRecord,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    transaction::{Transaction, VersionedTransaction},
+};
+
+struct Setup {
+    transwise: Transwise<AccountProviderStub, DelegationRecordParserStub>,
+    undelegated: Pubkey,
+    delegated: Pubkey,
+    authority: Pubkey,
+}
+
+fn setup() -> Setup {
+    let undelegated = Pubkey::new_unique();
+    let (delegated, delegation_record) = delegated_account_ids();
+    let authority = Pubkey::new_unique();
+
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(undelegated, account_with_data());
+    account_provider.add(delegated, account_owned_by_delegation_program());
+    account_provider
+        .add(delegation_record, account_owned_by_delegation_program());
+    let transwise = Transwise::with_providers(
+        account_provider,
+        DelegationRecordParserStub::new(Some(DelegationRecord {
+            authority,
+            owner: Pubkey::new_unique(),
+            delegation_slot: 0,
+            commit_frequency: CommitFrequency::Millis(1_000),
+        })),
+    );
+    Setup {
+        transwise,
+        undelegated,
+        delegated,
+        authority,
+    }
+}
+
+fn transfer_to(to: &Pubkey) -> VersionedTransaction {
+    let payer = Pubkey::new_unique();
+    Transaction::new_unsigned(Message::new(
+        &[system_instruction::transfer(&payer, to, 42)],
+        Some(&payer),
+    ))
+    .into()
+}
+
+fn write_delegated(delegated: &Pubkey) -> VersionedTransaction {
+    let payer = Pubkey::new_unique();
+    Transaction::new_unsigned(Message::new(
+        &[Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![AccountMeta::new(*delegated, false)],
+        )],
+        Some(&payer),
+    ))
+    .into()
+}
+
+#[tokio::test]
+async fn test_guide_transactions_fetches_shared_accounts_once() {
+    let account_provider = AccountProviderStub::default();
+    let fetched_pubkeys = account_provider.fetched_pubkeys.clone();
+    let undelegated = Pubkey::new_unique();
+    account_provider
+        .accounts
+        .write()
+        .unwrap()
+        .insert(undelegated, account_with_data());
+    let transwise = Transwise::with_providers(
+        account_provider,
+        DelegationRecordParserStub::default(),
+    );
+
+    let txs = vec![
+        transfer_to(&undelegated),
+        transfer_to(&undelegated),
+        transfer_to(&undelegated),
+    ];
+    let endpoints = transwise.guide_versioned_transactions(&txs).await.unwrap();
+
+    assert_eq!(endpoints.len(), 3);
+    assert!(endpoints.iter().all(|endpoint| endpoint.is_chain()));
+    let fetched_pubkeys = fetched_pubkeys.read().unwrap();
+    let fetch_count = |pubkey: &Pubkey| {
+        fetched_pubkeys
+            .iter()
+            .filter(|fetched| *fetched == pubkey)
+            .count()
+    };
+    assert_eq!(fetch_count(&undelegated), 1);
+    // The system program is known and thus never fetched
+    assert_eq!(fetch_count(&system_program::id()), 0);
+}
+
+#[tokio::test]
+async fn test_guide_transactions_returns_endpoint_per_transaction() {
+    let Setup {
+        transwise,
+        undelegated,
+        delegated,
+        authority,
+    } = setup();
+
+    let txs = vec![
+        transfer_to(&undelegated),
+        write_delegated(&delegated),
+        transfer_to(&undelegated),
+    ];
+    let endpoints = transwise.guide_versioned_transactions(&txs).await.unwrap();
+
+    assert_eq!(
+        endpoints
+            .iter()
+            .map(|endpoint| (endpoint.kind(), endpoint.authority().cloned()))
+            .collect::<Vec<_>>(),
+        vec![
+            (EndpointKind::Chain, None),
+            (EndpointKind::Ephemeral, Some(authority)),
+            (EndpointKind::Chain, None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_guide_transactions_in_order() {
+    let Setup {
+        transwise,
+        undelegated,
+        delegated,
+        authority,
+    } = setup();
+
+    let same_endpoint =
+        vec![write_delegated(&delegated), write_delegated(&delegated)];
+    let endpoints = transwise
+        .guide_versioned_transactions_in_order(&same_endpoint)
+        .await
+        .unwrap();
+    assert!(endpoints
+        .iter()
+        .all(|endpoint| endpoint.authority() == Some(&authority)));
+
+    let spanning_endpoints =
+        vec![write_delegated(&delegated), transfer_to(&undelegated)];
+    let result = transwise
+        .guide_versioned_transactions_in_order(&spanning_endpoints)
+        .await;
+    match result {
+        Err(TranswiseError::TransactionBatchSpansMultipleEndpoints {
+            endpoints,
+        }) => assert_eq!(
+            endpoints,
+            vec![
+                (EndpointKind::Ephemeral, Some(authority)),
+                (EndpointKind::Chain, None),
+            ]
+        ),
+        _ => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn test_guide_transaction_writing_ephemeral_native_account() {
+    let Setup { transwise, .. } = setup();
+    let ephemeral_native = Pubkey::new_unique();
+    let ephemeral_authority = Pubkey::new_unique();
+    let mut ephemeral_account_provider = AccountProviderStub::default();
+    ephemeral_account_provider.add(ephemeral_native, account_with_data());
+
+    let tx = write_delegated(&ephemeral_native);
+    assert!(transwise
+        .guide_versioned_transaction(&tx)
+        .await
+        .unwrap()
+        .is_chain());
+
+    let transwise = transwise.with_ephemeral_account_provider(
+        ephemeral_account_provider,
+        ephemeral_authority,
+    );
+    let endpoint = transwise.guide_versioned_transaction(&tx).await.unwrap();
+    assert_eq!(endpoint.authority(), Some(&ephemeral_authority));
+}
+
+#[tokio::test]
+async fn test_guide_transactions_looks_up_ephemeral_native_accounts_once() {
+    let Setup { transwise, .. } = setup();
+    let ephemeral_native = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let ephemeral_authority = Pubkey::new_unique();
+    let mut ephemeral_account_provider = AccountProviderStub::default();
+    ephemeral_account_provider.add(ephemeral_native, account_with_data());
+    let fetched_pubkeys = ephemeral_account_provider.fetched_pubkeys.clone();
+
+    let transwise = transwise.with_ephemeral_account_provider(
+        ephemeral_account_provider,
+        ephemeral_authority,
+    );
+    let txs = vec![
+        write_delegated(&ephemeral_native),
+        write_delegated(&missing),
+        write_delegated(&ephemeral_native),
+    ];
+    let endpoints = transwise.guide_versioned_transactions(&txs).await.unwrap();
+
+    assert_eq!(endpoints[0].authority(), Some(&ephemeral_authority));
+    assert!(endpoints[1].is_chain());
+    assert_eq!(endpoints[2].authority(), Some(&ephemeral_authority));
+    let fetched_pubkeys = fetched_pubkeys.read().unwrap();
+    let fetch_count = |pubkey: &Pubkey| {
+        fetched_pubkeys
+            .iter()
+            .filter(|fetched| *fetched == pubkey)
+            .count()
+    };
+    assert_eq!(fetch_count(&ephemeral_native), 1);
+    assert_eq!(fetch_count(&missing), 1);
+}
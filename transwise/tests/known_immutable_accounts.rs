@@ -0,0 +1,190 @@
+use conjunto_lockbox::account_chain_snapshot_provider::AccountChainSnapshotProvider;
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub, accounts::account_with_data,
+    delegation_record_parser_stub::DelegationRecordParserStub,
+};
+use conjunto_transwise::{
+    known_immutable_accounts::{
+        KnownImmutableAccounts, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID,
+    },
+    transaction_accounts_holder::TransactionAccountsHolder,
+    transaction_accounts_snapshot::{
+        TransactionAccountsSnapshot, TransactionAccountsSnapshotConfig,
+    },
+    AccountChainState, DelegationInconsistency,
+};
+use solana_sdk::{
+    clock::Slot, compute_budget, native_loader, pubkey::Pubkey, system_program,
+    sysvar,
+};
+
+const EXPECTED_SLOT: Slot = 42;
+
+fn setup_chain_snapshot_provider(
+    readonly_data: Pubkey,
+) -> AccountChainSnapshotProvider<AccountProviderStub, DelegationRecordParserStub>
+{
+    let mut account_provider = AccountProviderStub {
+        at_slot: EXPECTED_SLOT,
+        ..Default::default()
+    };
+    account_provider.add(readonly_data, account_with_data());
+    AccountChainSnapshotProvider::new(
+        account_provider,
+        DelegationRecordParserStub::default(),
+    )
+}
+
+#[test]
+fn test_default_known_immutable_accounts() {
+    let known_accounts = KnownImmutableAccounts::default();
+
+    for pubkey in [
+        system_program::id(),
+        compute_budget::id(),
+        sysvar::clock::id(),
+        sysvar::instructions::id(),
+        TOKEN_PROGRAM_ID,
+        TOKEN_2022_PROGRAM_ID,
+    ] {
+        assert!(known_accounts.contains(&pubkey), "{} is known", pubkey);
+    }
+    assert!(!known_accounts.contains(&Pubkey::new_unique()));
+
+    let system_program = known_accounts.get(&system_program::id()).unwrap();
+    assert_eq!(system_program.owner, native_loader::id());
+    assert!(system_program.executable);
+
+    let clock = known_accounts.get(&sysvar::clock::id()).unwrap();
+    assert_eq!(clock.owner, sysvar::id());
+    assert!(!clock.executable);
+}
+
+#[tokio::test]
+async fn test_known_readonly_accounts_are_not_fetched() {
+    let readonly_data = Pubkey::new_unique();
+    let writable_feepayer = Pubkey::new_unique();
+    let chain_snapshot_provider = setup_chain_snapshot_provider(readonly_data);
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![
+            system_program::id(),
+            sysvar::clock::id(),
+            readonly_data,
+        ],
+        writable: vec![writable_feepayer],
+        payer: writable_feepayer,
+        program_ids: vec![system_program::id()],
+        nonce_account: None,
+    };
+
+    let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
+        &acc_holder,
+        &chain_snapshot_provider,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // The stub doesn't know the system program and clock, so they would be
+    // classified as (empty) feepayers if they had been fetched
+    let system_program = &acc_snapshot.readonly[0];
+    assert_eq!(system_program.pubkey, system_program::id());
+    assert_eq!(system_program.at_slot, 0);
+    match &system_program.chain_state {
+        AccountChainState::Undelegated {
+            account,
+            delegation_inconsistency,
+        } => {
+            assert_eq!(account.owner, native_loader::id());
+            assert!(account.executable);
+            assert_eq!(
+                delegation_inconsistency,
+                &DelegationInconsistency::AccountInvalidOwner
+            );
+        }
+        _ => panic!("unexpected chain state {:?}", system_program),
+    }
+
+    let clock = &acc_snapshot.readonly[1];
+    assert!(clock.chain_state.is_undelegated());
+    assert_eq!(clock.chain_state.account().unwrap().owner, sysvar::id());
+
+    // Unknown accounts are still fetched
+    let readonly_data_snapshot = &acc_snapshot.readonly[2];
+    assert_eq!(readonly_data_snapshot.at_slot, EXPECTED_SLOT);
+    assert!(readonly_data_snapshot.chain_state.is_undelegated());
+
+    assert_eq!(acc_snapshot.writable[0].at_slot, EXPECTED_SLOT);
+    assert!(acc_snapshot.writable[0].chain_state.is_feepayer());
+}
+
+#[tokio::test]
+async fn test_known_accounts_use_min_context_slot() {
+    let chain_snapshot_provider =
+        setup_chain_snapshot_provider(Pubkey::new_unique());
+    let writable_feepayer = Pubkey::new_unique();
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![system_program::id()],
+        writable: vec![writable_feepayer],
+        payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
+    };
+
+    let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
+        &acc_holder,
+        &chain_snapshot_provider,
+        Some(7),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(acc_snapshot.readonly[0].at_slot, 7);
+}
+
+#[tokio::test]
+async fn test_empty_known_immutable_accounts_fetches_everything() {
+    let chain_snapshot_provider =
+        setup_chain_snapshot_provider(Pubkey::new_unique());
+    let writable_feepayer = Pubkey::new_unique();
+    let custom_immutable = Pubkey::new_unique();
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![system_program::id(), custom_immutable],
+        writable: vec![writable_feepayer],
+        payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
+    };
+
+    let mut known_immutable_accounts = KnownImmutableAccounts::empty();
+    known_immutable_accounts.insert(
+        custom_immutable,
+        Pubkey::new_unique(),
+        false,
+    );
+    let config = TransactionAccountsSnapshotConfig {
+        known_immutable_accounts,
+        ..Default::default()
+    };
+
+    let acc_snapshot =
+        TransactionAccountsSnapshot::from_accounts_holder_with_config(
+            &acc_holder,
+            &chain_snapshot_provider,
+            None,
+            &config,
+        )
+        .await
+        .unwrap();
+
+    // Not registered anymore and thus fetched
+    assert_eq!(acc_snapshot.readonly[0].at_slot, EXPECTED_SLOT);
+    assert!(acc_snapshot.readonly[0].chain_state.is_feepayer());
+
+    // Registered by the user and thus not fetched
+    assert_eq!(acc_snapshot.readonly[1].at_slot, 0);
+    assert!(acc_snapshot.readonly[1].chain_state.is_undelegated());
+}
@@ -0,0 +1,255 @@
+use conjunto_transwise::transaction_accounts_holder::TransactionAccountsHolder;
+use solana_sdk::{
+    bpf_loader_upgradeable,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::{
+        v0::{self, LoadedAddresses},
+        Message, SimpleAddressLoader, VersionedMessage,
+    },
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+    system_instruction, system_program, system_transaction, sysvar,
+    transaction::{SanitizedTransaction, Transaction, VersionedTransaction},
+};
+
+fn holders_from_legacy_transaction(
+    tx: Transaction,
+) -> (TransactionAccountsHolder, TransactionAccountsHolder) {
+    let sanitized_tx =
+        SanitizedTransaction::from_transaction_for_tests(tx.clone());
+    let versioned_tx = VersionedTransaction::from(tx);
+    (
+        TransactionAccountsHolder::try_from(&sanitized_tx).unwrap(),
+        TransactionAccountsHolder::try_from(&versioned_tx).unwrap(),
+    )
+}
+
+fn holder_from_legacy_transaction(
+    tx: Transaction,
+) -> TransactionAccountsHolder {
+    let (sanitized_holder, versioned_holder) =
+        holders_from_legacy_transaction(tx);
+    assert_eq!(sanitized_holder, versioned_holder);
+    versioned_holder
+}
+
+fn unsigned_transaction(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+) -> Transaction {
+    Transaction::new_unsigned(Message::new(instructions, Some(payer)))
+}
+
+#[test]
+fn test_transfer() {
+    let from = Keypair::new();
+    let to = Pubkey::new_unique();
+    let tx = system_transaction::transfer(&from, &to, 42, Hash::default());
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.payer, from.pubkey());
+    assert_eq!(holder.writable, vec![from.pubkey(), to]);
+    assert_eq!(holder.readonly, vec![system_program::id()]);
+}
+
+#[test]
+fn test_multiple_transfers_to_same_account() {
+    let payer = Pubkey::new_unique();
+    let to = Pubkey::new_unique();
+    let tx = unsigned_transaction(
+        &payer,
+        &[
+            system_instruction::transfer(&payer, &to, 1),
+            system_instruction::transfer(&payer, &to, 2),
+        ],
+    );
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.payer, payer);
+    assert_eq!(holder.writable, vec![payer, to]);
+    assert_eq!(holder.readonly, vec![system_program::id()]);
+    assert_eq!(holder.program_ids, vec![system_program::id()]);
+}
+
+#[test]
+fn test_invoked_program_passed_as_writable_is_demoted() {
+    let payer = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let writable = Pubkey::new_unique();
+    let ix = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(writable, false),
+            AccountMeta::new(program_id, false),
+        ],
+    );
+    let tx = unsigned_transaction(&payer, &[ix]);
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.writable, vec![payer, writable]);
+    assert_eq!(holder.readonly, vec![program_id]);
+}
+
+#[test]
+fn test_invoked_program_stays_writable_with_upgradeable_loader_present() {
+    let payer = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let ix = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(program_id, false),
+            AccountMeta::new_readonly(bpf_loader_upgradeable::id(), false),
+        ],
+    );
+    let tx = unsigned_transaction(&payer, &[ix]);
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert!(holder.writable.contains(&program_id));
+    assert!(holder.readonly.contains(&bpf_loader_upgradeable::id()));
+}
+
+#[test]
+fn test_sysvars_and_builtins_passed_as_writable_are_demoted() {
+    let payer = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let writable = Pubkey::new_unique();
+    let ix = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(writable, false),
+            AccountMeta::new(sysvar::clock::id(), false),
+            AccountMeta::new(sysvar::rent::id(), false),
+            AccountMeta::new(system_program::id(), false),
+        ],
+    );
+    let tx = unsigned_transaction(&payer, &[ix]);
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.writable, vec![payer, writable]);
+    assert!(holder.readonly.contains(&sysvar::clock::id()));
+    assert!(holder.readonly.contains(&sysvar::rent::id()));
+    assert!(holder.readonly.contains(&system_program::id()));
+    assert!(holder.readonly.contains(&program_id));
+}
+
+#[test]
+fn test_readonly_signer_and_readonly_accounts() {
+    let payer = Pubkey::new_unique();
+    let readonly_signer = Pubkey::new_unique();
+    let readonly = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let ix = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new_readonly(readonly_signer, true),
+            AccountMeta::new_readonly(readonly, false),
+        ],
+    );
+    let tx = unsigned_transaction(&payer, &[ix]);
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.payer, payer);
+    assert_eq!(holder.writable, vec![payer]);
+    assert_eq!(holder.readonly, vec![readonly_signer, readonly, program_id]);
+}
+
+#[test]
+fn test_v0_message_without_lookups() {
+    let payer = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let writable = Pubkey::new_unique();
+    let instructions = [Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(writable, false),
+            AccountMeta::new(program_id, false),
+            AccountMeta::new(sysvar::clock::id(), false),
+        ],
+    )];
+    let message = VersionedMessage::V0(
+        v0::Message::try_compile(&payer, &instructions, &[], Hash::default())
+            .unwrap(),
+    );
+    let versioned_tx = VersionedTransaction {
+        signatures: vec![Default::default()],
+        message,
+    };
+    let sanitized_tx = SanitizedTransaction::try_create(
+        versioned_tx.clone(),
+        Hash::default(),
+        Some(false),
+        SimpleAddressLoader::Enabled(LoadedAddresses::default()),
+    )
+    .unwrap();
+
+    let sanitized_holder =
+        TransactionAccountsHolder::try_from(&sanitized_tx).unwrap();
+    let versioned_holder =
+        TransactionAccountsHolder::try_from(&versioned_tx).unwrap();
+
+    assert_eq!(sanitized_holder, versioned_holder);
+    assert_eq!(versioned_holder.writable, vec![payer, writable]);
+    assert!(versioned_holder.readonly.contains(&program_id));
+    assert!(versioned_holder.readonly.contains(&sysvar::clock::id()));
+}
+
+#[test]
+fn test_versioned_transaction_without_accounts() {
+    let versioned_tx = VersionedTransaction {
+        signatures: vec![],
+        message: VersionedMessage::Legacy(Message::default()),
+    };
+    assert!(TransactionAccountsHolder::try_from(&versioned_tx).is_err());
+}
+
+#[test]
+fn test_durable_nonce_transaction() {
+    let payer = Pubkey::new_unique();
+    let nonce_account = Pubkey::new_unique();
+    let to = Pubkey::new_unique();
+    let tx = unsigned_transaction(
+        &payer,
+        &[
+            system_instruction::advance_nonce_account(&nonce_account, &payer),
+            system_instruction::transfer(&payer, &to, 42),
+        ],
+    );
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.nonce_account, Some(nonce_account));
+    assert_eq!(holder.writable, vec![payer, to]);
+    assert!(!holder.readonly.contains(&nonce_account));
+}
+
+#[test]
+fn test_advance_nonce_not_first_instruction() {
+    let payer = Pubkey::new_unique();
+    let nonce_account = Pubkey::new_unique();
+    let to = Pubkey::new_unique();
+    let tx = unsigned_transaction(
+        &payer,
+        &[
+            system_instruction::transfer(&payer, &to, 42),
+            system_instruction::advance_nonce_account(&nonce_account, &payer),
+        ],
+    );
+
+    let holder = holder_from_legacy_transaction(tx);
+
+    assert_eq!(holder.nonce_account, None);
+    assert!(holder.writable.contains(&nonce_account));
+}
@@ -0,0 +1,171 @@
+use std::sync::atomic::Ordering;
+
+use conjunto_lockbox::account_chain_snapshot_provider::AccountChainSnapshotProvider;
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub, accounts::account_with_data,
+    delegation_record_parser_stub::DelegationRecordParserStub,
+};
+use conjunto_transwise::{
+    errors::TranswiseError,
+    transaction_accounts_holder::TransactionAccountsHolder,
+    transaction_accounts_snapshot::{
+        TransactionAccountsSnapshot, TransactionAccountsSnapshotConfig,
+    },
+};
+use solana_sdk::pubkey::Pubkey;
+
+fn chain_snapshot_provider(
+    account_provider: AccountProviderStub,
+) -> AccountChainSnapshotProvider<AccountProviderStub, DelegationRecordParserStub>
+{
+    AccountChainSnapshotProvider::new(
+        account_provider,
+        DelegationRecordParserStub::default(),
+    )
+}
+
+fn holder_with(
+    readonly: Vec<Pubkey>,
+    writable: Vec<Pubkey>,
+) -> TransactionAccountsHolder {
+    TransactionAccountsHolder {
+        payer: writable[0],
+        readonly,
+        writable,
+        program_ids: vec![],
+        nonce_account: None,
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_fetches_are_limited() {
+    let account_provider = AccountProviderStub::default();
+    let max_concurrent_requests =
+        account_provider.max_concurrent_requests.clone();
+    let holder = holder_with(
+        (0..20).map(|_| Pubkey::new_unique()).collect(),
+        (0..20).map(|_| Pubkey::new_unique()).collect(),
+    );
+    let config = TransactionAccountsSnapshotConfig {
+        max_concurrent_fetches: 4,
+        max_accounts_per_fetch: 5,
+        ..Default::default()
+    };
+
+    let acc_snapshot =
+        TransactionAccountsSnapshot::from_accounts_holder_with_config(
+            &holder,
+            &chain_snapshot_provider(account_provider),
+            None,
+            &config,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(acc_snapshot.readonly.len(), 20);
+    assert_eq!(acc_snapshot.writable.len(), 20);
+    assert_eq!(max_concurrent_requests.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_accounts_are_fetched_in_chunks() {
+    let account_provider = AccountProviderStub::default();
+    let max_concurrent_requests =
+        account_provider.max_concurrent_requests.clone();
+    let fetched_pubkeys = account_provider.fetched_pubkeys.clone();
+    let holder = holder_with(
+        (0..20).map(|_| Pubkey::new_unique()).collect(),
+        (0..20).map(|_| Pubkey::new_unique()).collect(),
+    );
+
+    let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
+        &holder,
+        &chain_snapshot_provider(account_provider),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(acc_snapshot.readonly.len(), 20);
+    assert_eq!(acc_snapshot.writable.len(), 20);
+    // All accounts and their delegation records are fetched at once
+    assert_eq!(fetched_pubkeys.read().unwrap().len(), 80);
+    assert_eq!(max_concurrent_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_failed_accounts_are_reported() {
+    let payer = Pubkey::new_unique();
+    let failing_readonly = Pubkey::new_unique();
+    let failing_writable = Pubkey::new_unique();
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(failing_writable, account_with_data());
+    account_provider.fail(failing_readonly);
+    account_provider.fail(failing_writable);
+    let holder = holder_with(
+        vec![failing_readonly, Pubkey::new_unique()],
+        vec![payer, failing_writable],
+    );
+
+    let result = TransactionAccountsSnapshot::from_accounts_holder(
+        &holder,
+        &chain_snapshot_provider(account_provider),
+        None,
+    )
+    .await;
+
+    match result {
+        Err(TranswiseError::FailedToFetchAccounts { failed_pubkeys }) => {
+            assert_eq!(
+                failed_pubkeys
+                    .iter()
+                    .map(|(pubkey, _)| *pubkey)
+                    .collect::<Vec<_>>(),
+                vec![failing_readonly, failing_writable]
+            );
+        }
+        _ => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn test_failed_readonly_accounts_as_unknown() {
+    let payer = Pubkey::new_unique();
+    let failing_readonly = Pubkey::new_unique();
+    let readonly = Pubkey::new_unique();
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(readonly, account_with_data());
+    account_provider.fail(failing_readonly);
+    let config = TransactionAccountsSnapshotConfig {
+        treat_failed_readonly_as_unknown: true,
+        ..Default::default()
+    };
+    let chain_snapshot_provider = chain_snapshot_provider(account_provider);
+
+    let acc_snapshot =
+        TransactionAccountsSnapshot::from_accounts_holder_with_config(
+            &holder_with(vec![failing_readonly, readonly], vec![payer]),
+            &chain_snapshot_provider,
+            None,
+            &config,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(acc_snapshot.readonly.len(), 1);
+    assert_eq!(acc_snapshot.readonly[0].pubkey, readonly);
+    assert_eq!(acc_snapshot.readonly_unknown, vec![failing_readonly]);
+
+    // Writable accounts always need to be known
+    let result = TransactionAccountsSnapshot::from_accounts_holder_with_config(
+        &holder_with(vec![], vec![payer, failing_readonly]),
+        &chain_snapshot_provider,
+        None,
+        &config,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(TranswiseError::FailedToFetchAccounts { .. })
+    ));
+}
@@ -1,21 +1,36 @@
-use std::vec;
+use std::{
+    collections::{HashMap, HashSet},
+    vec,
+};
 
-use conjunto_lockbox::account_chain_snapshot_provider::AccountChainSnapshotProvider;
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    account_chain_snapshot_provider::AccountChainSnapshotProvider,
+    account_chain_state::AccountChainState,
+};
 use conjunto_test_tools::{
     account_provider_stub::AccountProviderStub,
     accounts::{
         account_owned_by_delegation_program, account_with_data,
-        delegated_account_ids,
+        delegated_account_ids, program_account, DELEGATION_PROGRAM_ID,
     },
     delegation_record_parser_stub::DelegationRecordParserStub,
 };
 use conjunto_transwise::{
-    endpoint::{Endpoint, UnroutableReason},
+    endpoint::{
+        Endpoint, EndpointConfig, ProgramPolicy, ReadonlyDelegatedPolicy,
+        UnroutableReason,
+    },
+    endpoint_trace::EndpointKind,
     transaction_accounts_holder::TransactionAccountsHolder,
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
-    CommitFrequency, DelegationRecord,
+    AccountChainSnapshotShared, CommitFrequency, DelegationInconsistency,
+    DelegationRecord,
+};
+use solana_sdk::{
+    account::Account, bpf_loader_upgradeable, clock::Slot, pubkey::Pubkey,
+    system_program,
 };
-use solana_sdk::{account::Account, clock::Slot, pubkey::Pubkey};
 
 const EXPECTED_SLOT: Slot = 42;
 
@@ -52,19 +67,24 @@ async fn test_one_undelegated_readonly_and_one_delegated_writable_and_payer() {
     let (writable_delegated, delegation_record) = delegated_account_ids();
     let writable_feepayer = Pubkey::new_unique();
 
+    let delegation_record_data =
+        dummy_delegation_record_with_owner(Pubkey::new_unique());
+    let authority = delegation_record_data.authority;
     let chain_snapshot_provider = setup_chain_snapshot_provider(
         vec![
             (readonly_data, account_with_data()),
             (writable_delegated, account_owned_by_delegation_program()),
             (delegation_record, account_owned_by_delegation_program()),
         ],
-        Some(dummy_delegation_record_with_owner(Pubkey::new_unique())),
+        Some(delegation_record_data),
     );
 
     let acc_holder = TransactionAccountsHolder {
         readonly: vec![readonly_data],
         writable: vec![writable_delegated, writable_feepayer],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -94,6 +114,7 @@ async fn test_one_undelegated_readonly_and_one_delegated_writable_and_payer() {
         endpoint,
         Endpoint::Ephemeral {
             transaction_accounts_snapshot: acc_snapshot,
+            authority,
         }
     );
 }
@@ -121,6 +142,8 @@ async fn test_one_writable_delegated_and_one_writable_undelegated() {
             writable_feepayer,
         ],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -176,6 +199,8 @@ async fn test_one_writable_inconsistent_with_missing_delegation_account() {
         readonly: vec![],
         writable: vec![writable_undelegated, writable_feepayer],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -201,8 +226,15 @@ async fn test_one_writable_inconsistent_with_missing_delegation_account() {
 
     assert_eq!(
         endpoint,
-        Endpoint::Chain {
+        Endpoint::Unroutable {
             transaction_accounts_snapshot: acc_snapshot,
+            reason:
+                UnroutableReason::ContainsInconsistentDelegationsAsWritable {
+                    writable_inconsistent_pubkeys: vec![(
+                        writable_undelegated,
+                        DelegationInconsistency::DelegationRecordNotFound
+                    )],
+                }
         }
     );
 }
@@ -224,6 +256,8 @@ async fn test_one_writable_inconsistent_with_invalid_delegation_record() {
         readonly: vec![],
         writable: vec![writable_undelegated, writable_feepayer],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -245,14 +279,28 @@ async fn test_one_writable_inconsistent_with_invalid_delegation_record() {
 
     assert_eq!(acc_snapshot.payer, writable_feepayer);
 
-    let endpoint = Endpoint::from(acc_snapshot.clone());
+    let endpoint = Endpoint::from(acc_snapshot);
 
-    assert_eq!(
-        endpoint,
-        Endpoint::Chain {
-            transaction_accounts_snapshot: acc_snapshot,
+    match &endpoint {
+        Endpoint::Unroutable {
+            reason:
+                UnroutableReason::ContainsInconsistentDelegationsAsWritable {
+                    writable_inconsistent_pubkeys,
+                },
+            ..
+        } => {
+            assert_eq!(writable_inconsistent_pubkeys.len(), 1);
+            assert_eq!(
+                writable_inconsistent_pubkeys[0].0,
+                writable_undelegated
+            );
+            assert!(matches!(
+                writable_inconsistent_pubkeys[0].1,
+                DelegationInconsistency::DelegationRecordDataInvalid(_)
+            ));
         }
-    );
+        _ => panic!("expected unroutable endpoint, got {:?}", endpoint),
+    }
 }
 
 #[tokio::test]
@@ -269,6 +317,8 @@ async fn test_one_writable_undelegated_with_writable_feepayer() {
         readonly: vec![],
         writable: vec![writable_undelegated, writable_feepayer],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -317,6 +367,8 @@ async fn test_one_writable_undelegated_as_payer() {
         readonly: vec![],
         writable: vec![writable_undelegated],
         payer: writable_undelegated,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -363,6 +415,8 @@ async fn test_one_writable_undelegated_as_payer_and_one_writable_delegated() {
         readonly: vec![],
         writable: vec![writable_delegated, writable_undelegated],
         payer: writable_undelegated,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -417,6 +471,8 @@ async fn test_two_readonly_datas_and_payer() {
         readonly: vec![readonly1_data, readonly2_data],
         writable: vec![writable_feepayer],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -470,6 +526,8 @@ async fn test_two_readonly_undelegated_and_one_writable_undelegated() {
         readonly: vec![readonly1_data, readonly2_data],
         writable: vec![writable_undelegated, writable_feepayer],
         payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
     };
 
     let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
@@ -504,3 +562,784 @@ async fn test_two_readonly_undelegated_and_one_writable_undelegated() {
         }
     );
 }
+
+fn feepayer_chain_snapshot(pubkey: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: EXPECTED_SLOT,
+        chain_state: AccountChainState::FeePayer {
+            lamports: 42,
+            owner: system_program::ID,
+        },
+    }
+    .into()
+}
+
+fn delegated_chain_snapshot(
+    pubkey: Pubkey,
+    authority: Pubkey,
+) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: EXPECTED_SLOT,
+        chain_state: AccountChainState::Delegated {
+            account: account_owned_by_delegation_program(),
+            delegation_record: DelegationRecord {
+                authority,
+                ..dummy_delegation_record_with_owner(Pubkey::new_unique())
+            },
+        },
+    }
+    .into()
+}
+
+#[test]
+fn test_payer_not_writable() {
+    let readonly_feepayer = Pubkey::new_unique();
+    let writable_delegated = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![feepayer_chain_snapshot(readonly_feepayer)],
+        writable: vec![delegated_chain_snapshot(
+            writable_delegated,
+            Pubkey::new_unique(),
+        )],
+        payer: readonly_feepayer,
+        ..Default::default()
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::MissingFeePayer {
+                payer: readonly_feepayer
+            },
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_one_writable_program_account() {
+    let writable_program = Pubkey::new_unique();
+    let writable_feepayer = Pubkey::new_unique();
+
+    let chain_snapshot_provider = setup_chain_snapshot_provider(
+        vec![(writable_program, program_account())],
+        None,
+    );
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![],
+        writable: vec![writable_program, writable_feepayer],
+        payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
+    };
+
+    let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
+        &acc_holder,
+        &chain_snapshot_provider,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+            reason: UnroutableReason::ContainsProgramAccountsAsWritable {
+                writable_program_pubkeys: vec![writable_program],
+            },
+        }
+    );
+
+    // Deploying or upgrading a program writes to it on chain
+    let deployment_snapshot = TransactionAccountsSnapshot {
+        program_ids: vec![bpf_loader_upgradeable::id()],
+        nonce_account: None,
+        writable_ephemeral_native: vec![],
+        readonly_unknown: vec![],
+        ..acc_snapshot
+    };
+    assert_eq!(
+        Endpoint::from(deployment_snapshot.clone()),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: deployment_snapshot,
+        }
+    );
+}
+
+#[test]
+fn test_two_writable_delegated_to_different_authorities() {
+    let writable_feepayer = Pubkey::new_unique();
+    let writable_delegated1 = Pubkey::new_unique();
+    let writable_delegated2 = Pubkey::new_unique();
+    let authority1 = Pubkey::new_unique();
+    let authority2 = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_chain_snapshot(writable_delegated1, authority1),
+            delegated_chain_snapshot(writable_delegated2, authority2),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        ..Default::default()
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason:
+                UnroutableReason::ContainsAccountsDelegatedToDifferentAuthorities {
+                    writable_delegated_authorities: vec![
+                        (writable_delegated1, authority1),
+                        (writable_delegated2, authority2),
+                    ],
+                },
+        }
+    );
+}
+
+#[test]
+fn test_two_writable_delegated_to_same_authority() {
+    let writable_feepayer = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_chain_snapshot(Pubkey::new_unique(), authority),
+            delegated_chain_snapshot(Pubkey::new_unique(), authority),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        ..Default::default()
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot,
+            authority,
+        }
+    );
+}
+
+#[test]
+fn test_undelegating_writable_delegated_goes_to_chain() {
+    let writable_feepayer = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_chain_snapshot(Pubkey::new_unique(), authority),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        program_ids: vec![DELEGATION_PROGRAM_ID],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+        }
+    );
+
+    // Without treating the delegation program as chain only we route by accounts
+    let config = EndpointConfig {
+        chain_only_program_ids: HashSet::new(),
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot,
+            authority,
+        }
+    );
+}
+
+#[test]
+fn test_invoking_configured_chain_only_program() {
+    let writable_feepayer = Pubkey::new_unique();
+    let writable_undelegated = Pubkey::new_unique();
+    let chain_only_program = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_chain_snapshot(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            ),
+            AccountChainSnapshot {
+                pubkey: writable_undelegated,
+                at_slot: EXPECTED_SLOT,
+                chain_state: AccountChainState::Undelegated {
+                    account: account_with_data(),
+                    delegation_inconsistency:
+                        DelegationInconsistency::AccountInvalidOwner,
+                },
+            }
+            .into(),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        program_ids: vec![system_program::id(), chain_only_program],
+        ..Default::default()
+    };
+
+    assert!(Endpoint::from(acc_snapshot.clone()).is_unroutable());
+
+    let config = EndpointConfig {
+        chain_only_program_ids: HashSet::from([chain_only_program]),
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot,
+        }
+    );
+}
+
+#[test]
+fn test_invoking_chain_only_program_via_cpi_goes_to_chain() {
+    let writable_feepayer = Pubkey::new_unique();
+    let caller_program = Pubkey::new_unique();
+
+    // The delegation program is only passed as readonly account to the program
+    // that invokes it
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![AccountChainSnapshot {
+            pubkey: DELEGATION_PROGRAM_ID,
+            at_slot: EXPECTED_SLOT,
+            chain_state: AccountChainState::Undelegated {
+                account: account_with_data(),
+                delegation_inconsistency:
+                    DelegationInconsistency::AccountInvalidOwner,
+            },
+        }
+        .into()],
+        writable: vec![
+            delegated_chain_snapshot(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            ),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        program_ids: vec![caller_program],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+        }
+    );
+
+    // Same if its chain state is unknown
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        readonly_unknown: vec![DELEGATION_PROGRAM_ID],
+        ..acc_snapshot
+    };
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot,
+        }
+    );
+}
+
+#[test]
+fn test_invoking_chain_only_program_without_writable_payer() {
+    let readonly_feepayer = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![feepayer_chain_snapshot(readonly_feepayer)],
+        writable: vec![delegated_chain_snapshot(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        )],
+        payer: readonly_feepayer,
+        program_ids: vec![DELEGATION_PROGRAM_ID],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::MissingFeePayer {
+                payer: readonly_feepayer
+            },
+        }
+    );
+}
+
+#[test]
+fn test_program_policies() {
+    let writable_feepayer = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let chain_program = Pubkey::new_unique();
+    let ephemeral_program = Pubkey::new_unique();
+    let either_program = Pubkey::new_unique();
+
+    let config = EndpointConfig {
+        program_policies: HashMap::from([
+            (chain_program, ProgramPolicy::ChainOnly),
+            (ephemeral_program, ProgramPolicy::EphemeralOnly),
+            (either_program, ProgramPolicy::Either),
+        ]),
+        ..Default::default()
+    };
+    let ephemeral_snapshot =
+        |program_ids: Vec<Pubkey>| TransactionAccountsSnapshot {
+            readonly: vec![],
+            writable: vec![
+                delegated_chain_snapshot(Pubkey::new_unique(), authority),
+                feepayer_chain_snapshot(writable_feepayer),
+            ],
+            payer: writable_feepayer,
+            program_ids,
+            ..Default::default()
+        };
+    let chain_snapshot =
+        |program_ids: Vec<Pubkey>| TransactionAccountsSnapshot {
+            readonly: vec![],
+            writable: vec![feepayer_chain_snapshot(writable_feepayer)],
+            payer: writable_feepayer,
+            program_ids,
+            ..Default::default()
+        };
+
+    // Programs available where the accounts take us
+    let acc_snapshot =
+        ephemeral_snapshot(vec![ephemeral_program, either_program]);
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot,
+            authority,
+        }
+    );
+    let acc_snapshot = chain_snapshot(vec![chain_program, either_program]);
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot,
+        }
+    );
+
+    // Programs unavailable where the accounts take us
+    let acc_snapshot = ephemeral_snapshot(vec![chain_program, either_program]);
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::InvokesProgramsUnavailableOnEndpoint {
+                endpoint: EndpointKind::Ephemeral,
+                unavailable_program_ids: vec![chain_program],
+            },
+        }
+    );
+    let acc_snapshot = chain_snapshot(vec![ephemeral_program, chain_program]);
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::InvokesProgramsUnavailableOnEndpoint {
+                endpoint: EndpointKind::Chain,
+                unavailable_program_ids: vec![ephemeral_program],
+            },
+        }
+    );
+
+    // Programs without a policy are available everywhere
+    let acc_snapshot = ephemeral_snapshot(vec![Pubkey::new_unique()]);
+    assert!(Endpoint::from_with_config(acc_snapshot, &config).is_ephemeral());
+}
+
+#[test]
+fn test_durable_nonce_transaction() {
+    let writable_feepayer = Pubkey::new_unique();
+    let nonce_account = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let nonce_chain_snapshot: AccountChainSnapshotShared =
+        AccountChainSnapshot {
+            pubkey: nonce_account,
+            at_slot: EXPECTED_SLOT,
+            chain_state: AccountChainState::Undelegated {
+                account: account_with_data(),
+                delegation_inconsistency:
+                    DelegationInconsistency::AccountInvalidOwner,
+            },
+        }
+        .into();
+
+    // The nonce account does not force the transaction to chain
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_chain_snapshot(Pubkey::new_unique(), authority),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        program_ids: vec![system_program::id()],
+        nonce_account: Some(nonce_chain_snapshot.clone()),
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+            reason: UnroutableReason::ContainsDurableNonceForEphemeral {
+                nonce_account
+            },
+        }
+    );
+
+    let config = EndpointConfig {
+        allow_durable_nonce_on_ephemeral: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot,
+            authority,
+        }
+    );
+
+    // Chain bound nonce transactions are routed as usual
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![feepayer_chain_snapshot(writable_feepayer)],
+        payer: writable_feepayer,
+        program_ids: vec![system_program::id()],
+        nonce_account: Some(nonce_chain_snapshot),
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_writable_ephemeral_native_account() {
+    let writable_feepayer = Pubkey::new_unique();
+    let ephemeral_native = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let chain_snapshot_provider = setup_chain_snapshot_provider(vec![], None);
+    let mut ephemeral_account_provider = AccountProviderStub::default();
+    ephemeral_account_provider.add(ephemeral_native, account_with_data());
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![],
+        writable: vec![writable_feepayer, ephemeral_native],
+        payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
+    };
+
+    let mut acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
+        &acc_holder,
+        &chain_snapshot_provider,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // Without asking the ephemeral validator the account is just new
+    assert!(Endpoint::from(acc_snapshot.clone()).is_chain());
+
+    acc_snapshot
+        .fetch_writable_ephemeral_native(&ephemeral_account_provider, authority)
+        .await
+        .unwrap();
+    assert_eq!(
+        acc_snapshot.writable_ephemeral_native,
+        vec![(ephemeral_native, authority)]
+    );
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot,
+            authority,
+        }
+    );
+}
+
+#[test]
+fn test_writable_ephemeral_native_and_writable_delegated() {
+    let writable_feepayer = Pubkey::new_unique();
+    let writable_delegated = Pubkey::new_unique();
+    let ephemeral_native = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let other_authority = Pubkey::new_unique();
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_chain_snapshot(writable_delegated, authority),
+            feepayer_chain_snapshot(writable_feepayer),
+        ],
+        payer: writable_feepayer,
+        writable_ephemeral_native: vec![(ephemeral_native, authority)],
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+            authority,
+        }
+    );
+
+    // The ephemeral native account lives in another validator
+    let acc_snapshot = TransactionAccountsSnapshot {
+        writable_ephemeral_native: vec![(ephemeral_native, other_authority)],
+        ..acc_snapshot
+    };
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason:
+                UnroutableReason::ContainsAccountsDelegatedToDifferentAuthorities {
+                    writable_delegated_authorities: vec![
+                        (writable_delegated, authority),
+                        (ephemeral_native, other_authority),
+                    ],
+                },
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_writable_ephemeral_native_and_writable_undelegated() {
+    let writable_feepayer = Pubkey::new_unique();
+    let writable_undelegated = Pubkey::new_unique();
+    let ephemeral_native = Pubkey::new_unique();
+
+    let chain_snapshot_provider = setup_chain_snapshot_provider(
+        vec![(writable_undelegated, account_with_data())],
+        None,
+    );
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![],
+        writable: vec![
+            writable_feepayer,
+            writable_undelegated,
+            ephemeral_native,
+        ],
+        payer: writable_feepayer,
+        program_ids: vec![],
+        nonce_account: None,
+    };
+    let acc_snapshot = TransactionAccountsSnapshot {
+        writable_ephemeral_native: vec![(
+            ephemeral_native,
+            Pubkey::new_unique(),
+        )],
+        ..TransactionAccountsSnapshot::from_accounts_holder(
+            &acc_holder,
+            &chain_snapshot_provider,
+            None,
+        )
+        .await
+        .unwrap()
+    };
+
+    assert_eq!(
+        Endpoint::from(acc_snapshot.clone()),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                writable_undelegated_pubkeys: vec![writable_undelegated],
+                writable_delegated_pubkeys: vec![ephemeral_native],
+            },
+        }
+    );
+}
+
+#[test]
+fn test_readonly_delegated_policies() {
+    let writable_feepayer = Pubkey::new_unique();
+    let readonly_delegated = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let config_with_policy = |readonly_delegated_policy| EndpointConfig {
+        readonly_delegated_policy,
+        ..Default::default()
+    };
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![delegated_chain_snapshot(readonly_delegated, authority)],
+        writable: vec![feepayer_chain_snapshot(writable_feepayer)],
+        payer: writable_feepayer,
+        ..Default::default()
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+    assert!(endpoint.is_chain());
+    assert_eq!(endpoint.warnings().len(), 1);
+
+    assert_eq!(
+        Endpoint::from_with_config(
+            acc_snapshot.clone(),
+            &config_with_policy(ReadonlyDelegatedPolicy::Reject)
+        ),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+            reason: UnroutableReason::ReadsDelegatedAccountsOnChain {
+                readonly_delegated_authorities: vec![(
+                    readonly_delegated,
+                    authority
+                )],
+                readonly_unknown_pubkeys: vec![],
+            },
+        }
+    );
+
+    assert_eq!(
+        Endpoint::from_with_config(
+            acc_snapshot.clone(),
+            &config_with_policy(ReadonlyDelegatedPolicy::RerouteToEphemeral)
+        ),
+        Endpoint::Ephemeral {
+            transaction_accounts_snapshot: acc_snapshot,
+            authority,
+        }
+    );
+}
+
+#[test]
+fn test_readonly_delegated_cannot_be_rerouted() {
+    let writable_feepayer = Pubkey::new_unique();
+    let readonly_delegated1 = Pubkey::new_unique();
+    let readonly_delegated2 = Pubkey::new_unique();
+    let authority1 = Pubkey::new_unique();
+    let authority2 = Pubkey::new_unique();
+    let config = EndpointConfig {
+        readonly_delegated_policy: ReadonlyDelegatedPolicy::RerouteToEphemeral,
+        ..Default::default()
+    };
+
+    // The accounts are delegated to different validators
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![
+            delegated_chain_snapshot(readonly_delegated1, authority1),
+            delegated_chain_snapshot(readonly_delegated2, authority2),
+        ],
+        writable: vec![feepayer_chain_snapshot(writable_feepayer)],
+        payer: writable_feepayer,
+        ..Default::default()
+    };
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot.clone(),
+            reason: UnroutableReason::ReadsDelegatedAccountsOnChain {
+                readonly_delegated_authorities: vec![
+                    (readonly_delegated1, authority1),
+                    (readonly_delegated2, authority2),
+                ],
+                readonly_unknown_pubkeys: vec![],
+            },
+        }
+    );
+
+    // Chain only programs run on chain regardless of the policy
+    let acc_snapshot = TransactionAccountsSnapshot {
+        program_ids: vec![DELEGATION_PROGRAM_ID],
+        ..acc_snapshot
+    };
+    assert_eq!(
+        Endpoint::from_with_config(acc_snapshot.clone(), &config),
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot,
+        }
+    );
+}
+
+#[test]
+fn test_readonly_unknown_policies() {
+    let writable_feepayer = Pubkey::new_unique();
+    let readonly_unknown = Pubkey::new_unique();
+    let config_with_policy = |readonly_delegated_policy| EndpointConfig {
+        readonly_delegated_policy,
+        ..Default::default()
+    };
+
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![feepayer_chain_snapshot(writable_feepayer)],
+        payer: writable_feepayer,
+        readonly_unknown: vec![readonly_unknown],
+        ..Default::default()
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+    assert!(endpoint.is_chain());
+    assert_eq!(endpoint.warnings().len(), 1);
+
+    // The account may be delegated, but we cannot tell where to
+    for policy in [
+        ReadonlyDelegatedPolicy::Reject,
+        ReadonlyDelegatedPolicy::RerouteToEphemeral,
+    ] {
+        assert_eq!(
+            Endpoint::from_with_config(
+                acc_snapshot.clone(),
+                &config_with_policy(policy)
+            ),
+            Endpoint::Unroutable {
+                transaction_accounts_snapshot: acc_snapshot.clone(),
+                reason: UnroutableReason::ReadsDelegatedAccountsOnChain {
+                    readonly_delegated_authorities: vec![],
+                    readonly_unknown_pubkeys: vec![readonly_unknown],
+                },
+            }
+        );
+    }
+
+    // Chain only programs run on chain regardless of the policy
+    let acc_snapshot = TransactionAccountsSnapshot {
+        program_ids: vec![DELEGATION_PROGRAM_ID],
+        ..acc_snapshot
+    };
+    let endpoint = Endpoint::from_with_config(
+        acc_snapshot.clone(),
+        &config_with_policy(ReadonlyDelegatedPolicy::Reject),
+    );
+    assert_eq!(
+        endpoint,
+        Endpoint::Chain {
+            transaction_accounts_snapshot: acc_snapshot,
+        }
+    );
+    assert_eq!(endpoint.warnings(), vec![]);
+}
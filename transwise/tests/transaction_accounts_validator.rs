@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use conjunto_core::delegation_inconsistency::DelegationInconsistency;
 use conjunto_lockbox::{
     account_chain_snapshot::AccountChainSnapshot,
@@ -7,16 +9,22 @@ use conjunto_test_tools::accounts::{
     account_owned_by_delegation_program, account_with_data,
 };
 use conjunto_transwise::{
+    errors::{TranswiseError, TranswiseResult},
+    known_immutable_accounts::KnownImmutableAccounts,
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
     transaction_accounts_validator::{
         TransactionAccountsValidator, TransactionAccountsValidatorImpl,
+        ValidateAccountsConfig,
     },
     AccountChainSnapshotShared, CommitFrequency, DelegationRecord,
 };
-use solana_sdk::{pubkey::Pubkey, system_program};
+use solana_sdk::{
+    account::Account, bpf_loader_upgradeable, pubkey::Pubkey, system_program,
+    sysvar,
+};
 
 fn transaction_accounts_validator() -> TransactionAccountsValidatorImpl {
-    TransactionAccountsValidatorImpl {}
+    TransactionAccountsValidatorImpl::default()
 }
 
 fn chain_snapshot_feepayer() -> AccountChainSnapshotShared {
@@ -30,6 +38,17 @@ fn chain_snapshot_feepayer() -> AccountChainSnapshotShared {
     }
     .into()
 }
+fn chain_snapshot_new_account() -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey: Pubkey::new_unique(),
+        at_slot: 42,
+        chain_state: AccountChainState::FeePayer {
+            lamports: 0,
+            owner: system_program::ID,
+        },
+    }
+    .into()
+}
 fn chain_snapshot_undelegated() -> AccountChainSnapshotShared {
     AccountChainSnapshot {
         pubkey: Pubkey::new_unique(),
@@ -42,14 +61,41 @@ fn chain_snapshot_undelegated() -> AccountChainSnapshotShared {
     }
     .into()
 }
+fn chain_snapshot_program(program_id: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey: program_id,
+        at_slot: 42,
+        chain_state: AccountChainState::Undelegated {
+            account: Account {
+                owner: bpf_loader_upgradeable::id(),
+                executable: true,
+                ..Account::default()
+            },
+            delegation_inconsistency:
+                DelegationInconsistency::AccountInvalidOwner,
+        },
+    }
+    .into()
+}
+fn chain_snapshot_sysvar(sysvar_id: Pubkey) -> AccountChainSnapshotShared {
+    KnownImmutableAccounts::default()
+        .chain_snapshot(&sysvar_id, 42)
+        .unwrap()
+        .into()
+}
 fn chain_snapshot_delegated() -> AccountChainSnapshotShared {
+    chain_snapshot_delegated_to(Pubkey::new_unique())
+}
+fn chain_snapshot_delegated_to(
+    authority: Pubkey,
+) -> AccountChainSnapshotShared {
     AccountChainSnapshot {
         pubkey: Pubkey::new_unique(),
         at_slot: 42,
         chain_state: AccountChainState::Delegated {
             account: account_owned_by_delegation_program(),
             delegation_record: DelegationRecord {
-                authority: Pubkey::new_unique(),
+                authority,
                 owner: Pubkey::new_unique(),
                 delegation_slot: 0,
                 commit_frequency: CommitFrequency::Millis(1_000),
@@ -82,6 +128,7 @@ fn test_two_readonly_undelegated_and_two_writable_delegated_and_feepayers() {
                     writable_delegated2,
                     writable_feepayer,
                 ],
+                ..Default::default()
             },
         );
 
@@ -97,6 +144,7 @@ fn test_empty_transaction_accounts() {
                 payer: Pubkey::new_unique(),
                 readonly: vec![],
                 writable: vec![],
+                ..Default::default()
             },
         );
 
@@ -114,6 +162,7 @@ fn test_only_one_readonly_undelegated() {
                 payer: Pubkey::new_unique(),
                 readonly: vec![readonly_undelegated],
                 writable: vec![],
+                ..Default::default()
             },
         );
 
@@ -131,6 +180,7 @@ fn test_only_one_writable_delegated() {
                 payer: Pubkey::new_unique(),
                 readonly: vec![],
                 writable: vec![writable_delegated],
+                ..Default::default()
             },
         );
 
@@ -148,6 +198,7 @@ fn test_only_one_writable_feepayer() {
                 payer: Pubkey::new_unique(),
                 readonly: vec![],
                 writable: vec![writable_feepayer],
+                ..Default::default()
             },
         );
 
@@ -165,6 +216,7 @@ fn test_only_one_readable_undelegated_as_payer() {
                 payer: readable_undelegated.pubkey,
                 readonly: vec![readable_undelegated],
                 writable: vec![],
+                ..Default::default()
             },
         );
 
@@ -182,6 +234,7 @@ fn test_only_one_writable_undelegated_as_payer_fail() {
                 payer: writable_undelegated.pubkey,
                 readonly: vec![],
                 writable: vec![writable_undelegated],
+                ..Default::default()
             },
         );
 
@@ -199,6 +252,7 @@ fn test_only_one_writable_delegated_as_payer() {
                 payer: writable_delegated.pubkey,
                 readonly: vec![],
                 writable: vec![writable_delegated],
+                ..Default::default()
             },
         );
 
@@ -216,6 +270,7 @@ fn test_only_one_writable_feepayer_as_payer() {
                 payer: writable_feepayer.pubkey,
                 readonly: vec![],
                 writable: vec![writable_feepayer],
+                ..Default::default()
             },
         );
 
@@ -234,6 +289,7 @@ fn test_one_readonly_undelegated_and_writable_feepayer_as_payer() {
                 payer: writable_feepayer.pubkey,
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_feepayer],
+                ..Default::default()
             },
         );
 
@@ -253,6 +309,7 @@ fn test_one_readonly_undelegated_and_one_writable_delegated_and_feepayer() {
                 payer: Pubkey::new_unique(),
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_delegated, writable_feepayer],
+                ..Default::default()
             },
         );
 
@@ -272,6 +329,7 @@ fn test_one_readonly_undelegated_and_one_writable_undelegated_and_payer_fail() {
                 payer: Pubkey::new_unique(),
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_undelegated, writable_feepayer],
+                ..Default::default()
             },
         );
 
@@ -290,6 +348,7 @@ fn test_one_readonly_undelegated_and_one_writable_undelegated_as_payer_fail() {
                 payer: writable_undelegated.pubkey,
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_undelegated],
+                ..Default::default()
             },
         );
 
@@ -308,6 +367,7 @@ fn test_one_writable_undelegated_and_writable_feepayer_as_payer_fail() {
                 payer: writable_feepayer.pubkey,
                 readonly: vec![],
                 writable: vec![writable_undelegated, writable_feepayer],
+                ..Default::default()
             },
         );
 
@@ -334,9 +394,432 @@ fn test_one_of_each_valid_type() {
                     readonly_feepayer,
                 ],
                 writable: vec![writable_delegated, writable_feepayer],
+                ..Default::default()
             },
         );
 
     // This should work just right in strict mode
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_validate_accounts_config() {
+    let program_id = Pubkey::new_unique();
+    let cases = vec![
+        ("default", ValidateAccountsConfig::default(), true),
+        (
+            "max writable accounts of zero",
+            ValidateAccountsConfig {
+                max_writable_accounts: Some(0),
+                ..Default::default()
+            },
+            false,
+        ),
+        (
+            "max writable accounts of one",
+            ValidateAccountsConfig {
+                max_writable_accounts: Some(1),
+                ..Default::default()
+            },
+            true,
+        ),
+        (
+            "empty allowed program ids",
+            ValidateAccountsConfig {
+                allowed_program_ids: Some(HashSet::new()),
+                ..Default::default()
+            },
+            false,
+        ),
+        (
+            "program both allowed and denied",
+            ValidateAccountsConfig {
+                allowed_program_ids: Some(HashSet::from([program_id])),
+                denied_program_ids: HashSet::from([program_id]),
+                ..Default::default()
+            },
+            false,
+        ),
+        (
+            "distinct allowed and denied programs",
+            ValidateAccountsConfig {
+                allowed_program_ids: Some(HashSet::from([program_id])),
+                denied_program_ids: HashSet::from([Pubkey::new_unique()]),
+                ..Default::default()
+            },
+            true,
+        ),
+    ];
+    for (name, config, is_valid) in cases {
+        match TransactionAccountsValidatorImpl::try_new(config) {
+            Ok(_) => assert!(is_valid, "{}: expected invalid config", name),
+            Err(TranswiseError::ValidateAccountsConfigIsInvalid(_)) => {
+                assert!(!is_valid, "{}: expected valid config", name)
+            }
+            Err(err) => panic!("{}: unexpected error {:?}", name, err),
+        }
+    }
+}
+
+type ResultCheck = fn(&TranswiseResult<()>) -> bool;
+
+#[test]
+fn test_validate_with_config() {
+    let allowed_program = Pubkey::new_unique();
+    let denied_program = Pubkey::new_unique();
+    let validator_authority = Pubkey::new_unique();
+
+    let feepayer = chain_snapshot_feepayer();
+    let escrowed_payer = chain_snapshot_delegated();
+    let writable_delegated = chain_snapshot_delegated();
+    let writable_new = chain_snapshot_new_account();
+    let readonly_undelegated = chain_snapshot_undelegated();
+    let readonly_program = chain_snapshot_program(Pubkey::new_unique());
+    let writable_delegated_to_us =
+        chain_snapshot_delegated_to(validator_authority);
+
+    let snapshot = |payer: &AccountChainSnapshotShared,
+                    writable: Vec<AccountChainSnapshotShared>,
+                    readonly: Vec<AccountChainSnapshotShared>,
+                    program_ids: Vec<Pubkey>| {
+        let mut all_writable = vec![payer.clone()];
+        all_writable.extend(writable);
+        TransactionAccountsSnapshot {
+            payer: payer.pubkey,
+            program_ids,
+            readonly,
+            writable: all_writable,
+            ..Default::default()
+        }
+    };
+
+    let cases: Vec<(
+        &str,
+        ValidateAccountsConfig,
+        TransactionAccountsSnapshot,
+        ResultCheck,
+    )> = vec![
+        (
+            "new writable account allowed by default",
+            ValidateAccountsConfig::default(),
+            snapshot(&feepayer, vec![writable_new.clone()], vec![], vec![]),
+            |result| result.is_ok(),
+        ),
+        (
+            "new writable account rejected",
+            ValidateAccountsConfig {
+                allow_new_accounts: false,
+                ..Default::default()
+            },
+            snapshot(&feepayer, vec![writable_new.clone()], vec![], vec![]),
+            |result| {
+                matches!(
+                    result,
+                    Err(TranswiseError::TransactionIncludeNewAccountsAsWritable { .. })
+                )
+            },
+        ),
+        (
+            "funded feepayer is not a new account",
+            ValidateAccountsConfig {
+                allow_new_accounts: false,
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![writable_delegated.clone()],
+                vec![],
+                vec![],
+            ),
+            |result| result.is_ok(),
+        ),
+        (
+            "payer not escrowed",
+            ValidateAccountsConfig {
+                require_escrowed_payer: true,
+                ..Default::default()
+            },
+            snapshot(&feepayer, vec![], vec![], vec![]),
+            |result| {
+                matches!(
+                    result,
+                    Err(TranswiseError::TransactionPayerIsNotEscrowed { .. })
+                )
+            },
+        ),
+        (
+            "payer escrowed",
+            ValidateAccountsConfig {
+                require_escrowed_payer: true,
+                ..Default::default()
+            },
+            snapshot(&escrowed_payer, vec![], vec![], vec![]),
+            |result| result.is_ok(),
+        ),
+        (
+            "readonly undelegated rejected",
+            ValidateAccountsConfig {
+                allow_readonly_undelegated: false,
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![],
+                vec![readonly_undelegated.clone()],
+                vec![],
+            ),
+            |result| {
+                matches!(
+                    result,
+                    Err(TranswiseError::TransactionIncludeUndelegatedAccountsAsReadonly { .. })
+                )
+            },
+        ),
+        (
+            "readonly programs and sysvars allowed",
+            ValidateAccountsConfig {
+                allow_readonly_undelegated: false,
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![],
+                vec![
+                    readonly_program.clone(),
+                    chain_snapshot_sysvar(sysvar::clock::id()),
+                ],
+                vec![readonly_program.pubkey],
+            ),
+            |result| result.is_ok(),
+        ),
+        (
+            "readonly unknown program and sysvar allowed",
+            ValidateAccountsConfig {
+                allow_readonly_undelegated: false,
+                ..Default::default()
+            },
+            TransactionAccountsSnapshot {
+                payer: feepayer.pubkey,
+                writable: vec![feepayer.clone()],
+                program_ids: vec![readonly_program.pubkey],
+                readonly_unknown: vec![
+                    readonly_program.pubkey,
+                    sysvar::rent::id(),
+                ],
+                ..Default::default()
+            },
+            |result| result.is_ok(),
+        ),
+        (
+            "readonly undelegated rejected next to program and sysvar",
+            ValidateAccountsConfig {
+                allow_readonly_undelegated: false,
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![],
+                vec![
+                    readonly_program.clone(),
+                    chain_snapshot_sysvar(sysvar::clock::id()),
+                    readonly_undelegated.clone(),
+                ],
+                vec![readonly_program.pubkey],
+            ),
+            |result| {
+                match result {
+                Err(TranswiseError::TransactionIncludeUndelegatedAccountsAsReadonly {
+                    readonly_undelegated_pubkeys,
+                }) => readonly_undelegated_pubkeys.len() == 1,
+                _ => false,
+            }
+            },
+        ),
+        (
+            "too many writable accounts",
+            ValidateAccountsConfig {
+                max_writable_accounts: Some(1),
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![writable_delegated.clone()],
+                vec![],
+                vec![],
+            ),
+            |result| {
+                matches!(
+                    result,
+                    Err(TranswiseError::TransactionIncludeTooManyWritableAccounts {
+                        writable_accounts_count: 2,
+                        max_writable_accounts: 1,
+                    })
+                )
+            },
+        ),
+        (
+            "writable accounts at the limit",
+            ValidateAccountsConfig {
+                max_writable_accounts: Some(2),
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![writable_delegated.clone()],
+                vec![],
+                vec![],
+            ),
+            |result| result.is_ok(),
+        ),
+        (
+            "denied program",
+            ValidateAccountsConfig {
+                denied_program_ids: HashSet::from([denied_program]),
+                ..Default::default()
+            },
+            snapshot(&feepayer, vec![], vec![], vec![denied_program]),
+            |result| {
+                matches!(
+                    result,
+                    Err(
+                        TranswiseError::TransactionInvokesDisallowedPrograms { .. }
+                    )
+                )
+            },
+        ),
+        (
+            "program not in allowed list",
+            ValidateAccountsConfig {
+                allowed_program_ids: Some(HashSet::from([allowed_program])),
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![],
+                vec![],
+                vec![allowed_program, Pubkey::new_unique()],
+            ),
+            |result| {
+                matches!(
+                    result,
+                    Err(
+                        TranswiseError::TransactionInvokesDisallowedPrograms { .. }
+                    )
+                )
+            },
+        ),
+        (
+            "program in allowed list",
+            ValidateAccountsConfig {
+                allowed_program_ids: Some(HashSet::from([allowed_program])),
+                ..Default::default()
+            },
+            snapshot(&feepayer, vec![], vec![], vec![allowed_program]),
+            |result| result.is_ok(),
+        ),
+        (
+            "writable delegated to other validator",
+            ValidateAccountsConfig {
+                validator_authority: Some(validator_authority),
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![
+                    writable_delegated_to_us.clone(),
+                    writable_delegated.clone(),
+                ],
+                vec![],
+                vec![],
+            ),
+            |result| {
+                matches!(
+                    result,
+                    Err(TranswiseError::TransactionIncludeAccountsDelegatedToOtherValidator { .. })
+                )
+            },
+        ),
+        (
+            "writable delegated to this validator",
+            ValidateAccountsConfig {
+                validator_authority: Some(validator_authority),
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![writable_delegated_to_us.clone()],
+                vec![],
+                vec![],
+            ),
+            |result| result.is_ok(),
+        ),
+        (
+            "readonly delegated to other validator",
+            ValidateAccountsConfig {
+                validator_authority: Some(validator_authority),
+                ..Default::default()
+            },
+            snapshot(
+                &feepayer,
+                vec![writable_delegated_to_us.clone()],
+                vec![writable_delegated.clone()],
+                vec![],
+            ),
+            |result| result.is_ok(),
+        ),
+    ];
+
+    for (name, config, snapshot, check) in cases {
+        let validator =
+            TransactionAccountsValidatorImpl::try_new(config).unwrap();
+        let result =
+            validator.validate_ephemeral_transaction_accounts(&snapshot);
+        assert!(check(&result), "{}: unexpected result {:?}", name, result);
+    }
+}
+
+#[test]
+fn test_validator_authority_mismatch_lists_accounts() {
+    let validator_authority = Pubkey::new_unique();
+    let other_authority = Pubkey::new_unique();
+    let writable_feepayer = chain_snapshot_feepayer();
+    let writable_delegated_to_us =
+        chain_snapshot_delegated_to(validator_authority);
+    let writable_delegated_to_other =
+        chain_snapshot_delegated_to(other_authority);
+
+    let validator =
+        TransactionAccountsValidatorImpl::try_new(ValidateAccountsConfig {
+            validator_authority: Some(validator_authority),
+            ..Default::default()
+        })
+        .unwrap();
+    let result = validator.validate_ephemeral_transaction_accounts(
+        &TransactionAccountsSnapshot {
+            payer: writable_feepayer.pubkey,
+            readonly: vec![],
+            writable: vec![
+                writable_delegated_to_us,
+                writable_delegated_to_other.clone(),
+                writable_feepayer,
+            ],
+            ..Default::default()
+        },
+    );
+
+    match result {
+        Err(
+            TranswiseError::TransactionIncludeAccountsDelegatedToOtherValidator {
+                validator_authority: actual_validator_authority,
+                mismatched_authorities,
+            },
+        ) => {
+            assert_eq!(actual_validator_authority, validator_authority);
+            assert_eq!(
+                mismatched_authorities,
+                vec![(writable_delegated_to_other.pubkey, other_authority)]
+            );
+        }
+        _ => panic!("unexpected result {:?}", result),
+    }
+}
@@ -0,0 +1,189 @@
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    account_chain_state::AccountChainState,
+};
+use conjunto_test_tools::accounts::{
+    account_owned_by_delegation_program, account_with_data,
+};
+use conjunto_transwise::{
+    endpoint::Endpoint,
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+    transaction_split::TransactionSplit, AccountChainSnapshotShared,
+    CommitFrequency, DelegationInconsistency, DelegationRecord,
+};
+use solana_sdk::{
+    compute_budget::{self, ComputeBudgetInstruction},
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    pubkey::Pubkey,
+    system_program,
+    transaction::{Transaction, VersionedTransaction},
+};
+
+fn feepayer_chain_snapshot(pubkey: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 0,
+        chain_state: AccountChainState::FeePayer {
+            lamports: 42,
+            owner: system_program::id(),
+        },
+    }
+    .into()
+}
+
+fn undelegated_chain_snapshot(pubkey: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 0,
+        chain_state: AccountChainState::Undelegated {
+            account: account_with_data(),
+            delegation_inconsistency:
+                DelegationInconsistency::AccountInvalidOwner,
+        },
+    }
+    .into()
+}
+
+fn delegated_chain_snapshot(
+    pubkey: Pubkey,
+    authority: Pubkey,
+) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey,
+        at_slot: 0,
+        chain_state: AccountChainState::Delegated {
+            account: account_owned_by_delegation_program(),
+            delegation_record: DelegationRecord {
+                authority,
+                owner: Pubkey::new_unique(),
+                delegation_slot: 0,
+                commit_frequency: CommitFrequency::Millis(1_000),
+            },
+        },
+    }
+    .into()
+}
+
+fn write_ix(program_id: Pubkey, writable: &[Pubkey]) -> Instruction {
+    Instruction::new_with_bytes(
+        program_id,
+        &[],
+        writable
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false))
+            .collect(),
+    )
+}
+
+struct Setup {
+    payer: Pubkey,
+    undelegated: Pubkey,
+    delegated: Pubkey,
+    authority: Pubkey,
+}
+
+impl Setup {
+    fn new() -> Self {
+        Self {
+            payer: Pubkey::new_unique(),
+            undelegated: Pubkey::new_unique(),
+            delegated: Pubkey::new_unique(),
+            authority: Pubkey::new_unique(),
+        }
+    }
+
+    fn endpoint(&self) -> Endpoint {
+        Endpoint::from(TransactionAccountsSnapshot {
+            readonly: vec![],
+            writable: vec![
+                feepayer_chain_snapshot(self.payer),
+                undelegated_chain_snapshot(self.undelegated),
+                delegated_chain_snapshot(self.delegated, self.authority),
+            ],
+            payer: self.payer,
+            ..Default::default()
+        })
+    }
+
+    fn transaction(
+        &self,
+        instructions: &[Instruction],
+    ) -> VersionedTransaction {
+        Transaction::new_unsigned(Message::new(instructions, Some(&self.payer)))
+            .into()
+    }
+}
+
+#[test]
+fn test_split_into_chain_and_ephemeral_instructions() {
+    let setup = Setup::new();
+    let chain_program = Pubkey::new_unique();
+    let ephemeral_program = Pubkey::new_unique();
+    let tx = setup.transaction(&[
+        ComputeBudgetInstruction::set_compute_unit_limit(100_000),
+        write_ix(chain_program, &[setup.undelegated]),
+        write_ix(ephemeral_program, &[setup.delegated]),
+    ]);
+
+    let split = TransactionSplit::try_from_versioned_transaction(
+        &tx,
+        &setup.endpoint(),
+    )
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(split.authority, setup.authority);
+    assert_eq!(split.chain.instruction_indexes, vec![0, 1]);
+    assert_eq!(split.chain.writable, vec![setup.payer, setup.undelegated]);
+    assert_eq!(
+        split.chain.readonly,
+        vec![compute_budget::id(), chain_program]
+    );
+    assert_eq!(split.ephemeral.instruction_indexes, vec![0, 2]);
+    assert_eq!(split.ephemeral.writable, vec![setup.payer, setup.delegated]);
+    assert_eq!(
+        split.ephemeral.readonly,
+        vec![compute_budget::id(), ephemeral_program]
+    );
+}
+
+#[test]
+fn test_no_split_if_instruction_writes_both() {
+    let setup = Setup::new();
+    let tx = setup.transaction(&[write_ix(
+        Pubkey::new_unique(),
+        &[setup.undelegated, setup.delegated],
+    )]);
+
+    let split = TransactionSplit::try_from_versioned_transaction(
+        &tx,
+        &setup.endpoint(),
+    )
+    .unwrap();
+
+    assert_eq!(split, None);
+}
+
+#[test]
+fn test_no_split_for_routable_transaction() {
+    let setup = Setup::new();
+    let tx = setup
+        .transaction(&[write_ix(Pubkey::new_unique(), &[setup.delegated])]);
+    let endpoint = Endpoint::from(TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            feepayer_chain_snapshot(setup.payer),
+            delegated_chain_snapshot(setup.delegated, setup.authority),
+        ],
+        payer: setup.payer,
+        ..Default::default()
+    });
+    assert!(endpoint.is_ephemeral());
+
+    let split =
+        TransactionSplit::try_from_versioned_transaction(&tx, &endpoint)
+            .unwrap();
+
+    assert_eq!(split, None);
+}
@@ -0,0 +1,119 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub, accounts::account_with_data,
+    delegation_record_parser_stub::DelegationRecordParserStub,
+};
+use conjunto_transwise::{
+    errors::TranswiseError,
+    transwise::Transwise,
+    wire_transaction::{deserialize_wire_transaction, WireEncoding},
+};
+use solana_sdk::{
+    instruction::Instruction,
+    message::Message,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    system_instruction,
+    transaction::{Transaction, VersionedTransaction},
+};
+
+fn transfer_to(to: &Pubkey) -> Transaction {
+    let payer = Pubkey::new_unique();
+    Transaction::new_unsigned(Message::new(
+        &[system_instruction::transfer(&payer, to, 42)],
+        Some(&payer),
+    ))
+}
+
+#[test]
+fn test_deserialize_wire_transaction_encodings() {
+    let tx = VersionedTransaction::from(transfer_to(&Pubkey::new_unique()));
+    let raw = bincode::serialize(&tx).unwrap();
+
+    for (data, encoding) in [
+        (bs58::encode(&raw).into_vec(), WireEncoding::Base58),
+        (
+            BASE64_STANDARD.encode(&raw).into_bytes(),
+            WireEncoding::Base64,
+        ),
+        (raw.clone(), WireEncoding::Raw),
+    ] {
+        assert_eq!(deserialize_wire_transaction(&data, encoding).unwrap(), tx);
+    }
+}
+
+#[test]
+fn test_deserialize_invalid_wire_transaction() {
+    let result = deserialize_wire_transaction(b"0OIl", WireEncoding::Base58);
+    assert!(matches!(
+        result,
+        Err(TranswiseError::InvalidTransactionEncoding {
+            encoding: WireEncoding::Base58,
+            ..
+        })
+    ));
+
+    let result = deserialize_wire_transaction(&[1, 2, 3], WireEncoding::Raw);
+    assert!(matches!(
+        result,
+        Err(TranswiseError::FailedToDeserializeTransaction { .. })
+    ));
+}
+
+#[test]
+fn test_deserialize_too_large_wire_transaction() {
+    let payer = Pubkey::new_unique();
+    let tx =
+        VersionedTransaction::from(Transaction::new_unsigned(Message::new(
+            &[Instruction::new_with_bytes(
+                Pubkey::new_unique(),
+                &[0; PACKET_DATA_SIZE],
+                vec![],
+            )],
+            Some(&payer),
+        )));
+    let raw = bincode::serialize(&tx).unwrap();
+
+    let result = deserialize_wire_transaction(&raw, WireEncoding::Raw);
+    assert!(matches!(
+        result,
+        Err(TranswiseError::DecodedTransactionTooLarge { decoded_len, .. })
+            if decoded_len == raw.len()
+    ));
+
+    let result = deserialize_wire_transaction(
+        BASE64_STANDARD.encode(&raw).as_bytes(),
+        WireEncoding::Base64,
+    );
+    assert!(matches!(
+        result,
+        Err(TranswiseError::EncodedTransactionTooLarge {
+            encoding: WireEncoding::Base64,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_guide_wire_and_legacy_transaction() {
+    let to = Pubkey::new_unique();
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.add(to, account_with_data());
+    let transwise = Transwise::with_providers(
+        account_provider,
+        DelegationRecordParserStub::default(),
+    );
+    let tx = transfer_to(&to);
+
+    let endpoint = transwise.guide_transaction(&tx).await.unwrap();
+    assert!(endpoint.is_chain());
+
+    let data = bs58::encode(bincode::serialize(&tx).unwrap()).into_vec();
+    assert_eq!(
+        transwise
+            .guide_wire_transaction(&data, WireEncoding::Base58)
+            .await
+            .unwrap(),
+        endpoint
+    );
+}